@@ -0,0 +1,31 @@
+-- Model routing table, replaces the hardcoded alias map, truncation table and fallback list in the chat proxy
+CREATE TABLE models (
+    alias TEXT PRIMARY KEY,
+    upstream_id TEXT NOT NULL,
+    context_window INTEGER NOT NULL DEFAULT 128000,
+    max_output_tokens INTEGER NOT NULL DEFAULT 4096,
+    max_messages INTEGER,
+    supports_vision BOOLEAN NOT NULL DEFAULT FALSE,
+    fallbacks TEXT[] NOT NULL DEFAULT '{}',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON models
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+-- Seed with the routes that used to be hardcoded in routes::oai::chat
+INSERT INTO models (alias, upstream_id, context_window, max_output_tokens, max_messages, supports_vision, fallbacks) VALUES
+    ('perplexity/mixtral-8x7b-instruct', 'openrouter/mistralai/mixtral-8x7b-instruct', 32768, 4096, NULL, FALSE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('perplexity/sonar-medium-online', 'openrouter/perplexity/llama-3-sonar-large-32k-online', 32768, 4096, 5, FALSE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('openrouter/perplexity/llama-3-sonar-large-32k-online', 'openrouter/perplexity/llama-3-sonar-large-32k-online', 32768, 4096, 5, FALSE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('openrouter/google/gemini-pro-1.5', 'gemini-1.5-flash-001', 1000000, 4096, NULL, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('claude-3-5-sonnet-20240620', 'bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0', 200000, 4096, NULL, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('claude-3-5-sonnet-20241022', 'bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0', 200000, 4096, NULL, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('bedrock/anthropic.claude-3-opus-20240229-v1:0', 'bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0', 200000, 4096, NULL, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('gpt-4-vision-preview', 'gpt-4-vision-preview', 128000, 4096, 3, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('gpt-4o', 'gpt-4o', 128000, 4096, NULL, TRUE, '{gpt-4o,claude-3-5-sonnet-20240620}'),
+    ('groq/llama3-70b-8192', 'groq/llama3-70b-8192', 8192, 4096, NULL, FALSE, '{gpt-4o,claude-3-5-sonnet-20240620}');
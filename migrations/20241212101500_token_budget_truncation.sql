@@ -0,0 +1,5 @@
+-- Truncation is now driven by the context window and a tokenizer rather than a message count
+ALTER TABLE models DROP COLUMN max_messages;
+
+-- Whether turns that don't fit the context window are summarized into a synthetic message
+ALTER TABLE models ADD COLUMN summarize_truncated BOOLEAN NOT NULL DEFAULT FALSE;
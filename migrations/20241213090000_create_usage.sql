@@ -0,0 +1,44 @@
+-- Per-million-token prices for each model route, used to cost completions
+ALTER TABLE models ADD COLUMN input_cost_per_mtok DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE models ADD COLUMN output_cost_per_mtok DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+UPDATE models SET input_cost_per_mtok = 5, output_cost_per_mtok = 15 WHERE upstream_id = 'gpt-4o';
+UPDATE models SET input_cost_per_mtok = 3, output_cost_per_mtok = 15 WHERE upstream_id LIKE 'bedrock/anthropic.claude-3-5-sonnet%';
+
+-- Token counts, cost and timings for each assistant message produced by the chat proxy
+CREATE TABLE message_usage (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    model TEXT NOT NULL,
+    upstream_model TEXT NOT NULL,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    estimated BOOLEAN NOT NULL DEFAULT FALSE,
+    cost_usd DOUBLE PRECISION NOT NULL DEFAULT 0,
+    ttft_ms INTEGER,
+    latency_ms INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_message_usage_user_id_created_at ON message_usage (user_id, created_at);
+
+-- Per-user, per-day, per-model rollup, upserted alongside each message_usage row
+CREATE TABLE usage_daily (
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    model TEXT NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    input_tokens BIGINT NOT NULL DEFAULT 0,
+    output_tokens BIGINT NOT NULL DEFAULT 0,
+    cost_usd DOUBLE PRECISION NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, day, model)
+);
+
+CREATE INDEX idx_usage_daily_day ON usage_daily (day);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON usage_daily
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,71 @@
+-- Rate limit plans, NULL limits are unlimited
+CREATE TABLE rate_limit_plans (
+    name TEXT PRIMARY KEY,
+    requests_per_minute INTEGER,
+    -- Bucket capacity, how many requests can be made back to back. Defaults to requests_per_minute
+    burst INTEGER,
+    tokens_per_day BIGINT,
+    -- When set, over-limit requests are served with this model instead of getting a 429
+    downgrade_model TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON rate_limit_plans
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+-- Users without a row here are on the default plan
+CREATE TABLE user_rate_limits (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    plan TEXT NOT NULL REFERENCES rate_limit_plans(name) ON UPDATE CASCADE,
+    -- Per-user overrides of the plan, NULL inherits from the plan. Overriding requests_per_minute
+    -- without burst sizes the bucket from the overridden rate rather than the plan's burst
+    requests_per_minute INTEGER,
+    burst INTEGER,
+    tokens_per_day BIGINT,
+    downgrade_model TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON user_rate_limits
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+-- Request token buckets, refilled lazily on each request
+CREATE TABLE rate_limit_buckets (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    tokens DOUBLE PRECISION NOT NULL,
+    refilled_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- The default plan starts unlimited so existing users see no change, tighten it explicitly
+INSERT INTO rate_limit_plans (name, requests_per_minute, burst, tokens_per_day, downgrade_model) VALUES
+    ('default', NULL, NULL, NULL, NULL),
+    ('unlimited', NULL, NULL, NULL, NULL),
+    -- No token allowance, so every request is downgraded. Replaces AuthenticatedUser::is_rate_limited
+    ('restricted', 30, 60, 0, 'groq/llama3-70b-8192');
+
+INSERT INTO user_rate_limits (user_id, plan)
+SELECT id, 'restricted' FROM users WHERE id IN (
+    'user_01J21MH9Q2ZJZC1C8R4ZHFJBNB',
+    'user_01J04BC3DXJ7PVGAW7VS30DG91',
+    'user_01HTYMBHYK14M12HHK34A2R8RC',
+    'user_01HYNY2S52Q5CQ6NWPP9D8D4AA',
+    'user_01HX6WMNT229K6V7CFPD7VRNV8',
+    'user_01HX9N7GH5QRFTGMYVWNPHCYMM',
+    'user_01J2MNK392KWQAJVX20CJBG5E7',
+    'user_01HZEP4TFR49AG913DPQJ6MASW',
+    'user_01HS55ATS5N0D9PEXY45TZDGXN',
+    'user_01HVR20FDCZH3QX8WPYHR45MX7',
+    'user_01HTJTP5X5PAH2XTH6A69Q3G08',
+    'user_01J03D3QK27ZD0B2E9A60X6E2R',
+    'user_01HRGR7RB2T8S04YXDH9YXQ31T',
+    'user_01J0AVNGZW118RXB7JSGAVZSFM',
+    'user_01HRDV9MWADXWSSNDE1HSASN8P',
+    'user_01J03D570TSXTNZ3FJGZFZ8VHA',
+    'user_01HRD1QJJTGDH2S2209N3WF9JX'
+);
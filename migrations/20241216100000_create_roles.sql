@@ -0,0 +1,38 @@
+-- Roles and the permissions they carry, replaces AuthenticatedUser::is_admin
+CREATE TABLE roles (
+    name TEXT PRIMARY KEY,
+    description TEXT NOT NULL DEFAULT '',
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON roles
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+CREATE TABLE user_roles (
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    granted_by TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, role)
+);
+
+CREATE INDEX idx_user_roles_role ON user_roles (role);
+
+-- Permissions checked by RequirePermission: users:read, users:sync, usage:read, devents:read,
+-- jobs:manage, models:manage, rate_limits:manage, roles:manage and memories:manage. * grants all
+INSERT INTO roles (name, description, permissions) VALUES
+    ('admin', 'Full access, implies every other role', '{*}'),
+    ('support', 'Look up users and their accounts', '{users:read}'),
+    ('analyst', 'Read usage and device events', '{usage:read,devents:read}');
+
+-- The admins that used to be hardcoded
+INSERT INTO user_roles (user_id, role)
+SELECT id, 'admin' FROM users WHERE id IN (
+    'user_01HRBJ8FVP3JT28DEWXN6JPKF5',
+    'user_01HY5EW9Z5XVE34GZXKH4NC2Y1',
+    'user_01J12R88378H1Z5R3JCGEPJ6RA'
+);
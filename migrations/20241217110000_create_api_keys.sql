@@ -0,0 +1,22 @@
+-- Personal API keys, an alternative to WorkOS JWTs for scripts and editors. Only the SHA-256 of the key is stored
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- The first characters of the key, so users can tell their keys apart
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- Empty means full access
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys (user_id);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON api_keys
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,26 @@
+-- One row per login. The id is the `jti` of the access tokens issued for the session, so revoking the row revokes them
+-- No foreign key on users, a login can land before the WorkOS user.created webhook does
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    user_agent TEXT,
+    ip_address TEXT,
+    -- SHA-256 of the current refresh token, rotated on every refresh
+    refresh_token_hash TEXT NOT NULL UNIQUE,
+    -- The refresh token it replaced, presenting it again means the token leaked and the session is revoked
+    previous_refresh_token_hash TEXT,
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- When the refresh token stops working, pushed back on every refresh
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_sessions_user_id ON sessions (user_id);
+CREATE INDEX idx_sessions_previous_refresh_token_hash ON sessions (previous_refresh_token_hash);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON sessions
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
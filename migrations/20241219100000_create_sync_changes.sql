@@ -0,0 +1,58 @@
+-- Change log behind /sync/changes, one row per write to a synced table, filled by triggers
+-- Clients page through it by (txid, id), only rows from transactions older than every in-flight transaction are served,
+-- so a transaction that commits late can never land behind a cursor a client already holds
+CREATE TABLE sync_changes (
+    id BIGSERIAL PRIMARY KEY,
+    txid BIGINT NOT NULL DEFAULT txid_current(),
+    user_id TEXT NOT NULL,
+    -- chat, message, file or memory
+    entity TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    -- Hard deleted, or soft deleted through deleted_at
+    deleted BOOLEAN NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_sync_changes_user_id_txid_id ON sync_changes (user_id, txid, id);
+
+CREATE OR REPLACE FUNCTION record_sync_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    changed RECORD;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    INSERT INTO sync_changes (user_id, entity, entity_id, deleted)
+    VALUES (
+        changed.user_id,
+        TG_ARGV[0],
+        changed.id,
+        TG_OP = 'DELETE' OR (to_jsonb(changed) ->> 'deleted_at') IS NOT NULL
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER record_sync_change
+AFTER INSERT OR UPDATE OR DELETE ON chats
+FOR EACH ROW
+EXECUTE PROCEDURE record_sync_change('chat');
+
+CREATE TRIGGER record_sync_change
+AFTER INSERT OR UPDATE OR DELETE ON messages
+FOR EACH ROW
+EXECUTE PROCEDURE record_sync_change('message');
+
+CREATE TRIGGER record_sync_change
+AFTER INSERT OR UPDATE OR DELETE ON files
+FOR EACH ROW
+EXECUTE PROCEDURE record_sync_change('file');
+
+CREATE TRIGGER record_sync_change
+AFTER INSERT OR UPDATE OR DELETE ON memories
+FOR EACH ROW
+EXECUTE PROCEDURE record_sync_change('memory');
@@ -0,0 +1,35 @@
+-- Publish every synced write on the sync_changes channel, which each instance LISTENs on to feed /sync/stream
+-- NOTIFY is delivered on commit, so listeners never hear about writes that roll back
+CREATE OR REPLACE FUNCTION record_sync_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    changed RECORD;
+    is_deleted BOOLEAN;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    is_deleted := TG_OP = 'DELETE' OR (to_jsonb(changed) ->> 'deleted_at') IS NOT NULL;
+
+    INSERT INTO sync_changes (user_id, entity, entity_id, deleted)
+    VALUES (changed.user_id, TG_ARGV[0], changed.id, is_deleted);
+
+    PERFORM pg_notify(
+        'sync_changes',
+        json_build_object(
+            'user_id', changed.user_id,
+            'entity', TG_ARGV[0],
+            'id', changed.id,
+            'action', CASE
+                WHEN is_deleted THEN 'deleted'
+                WHEN TG_OP = 'INSERT' THEN 'created'
+                ELSE 'updated'
+            END
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
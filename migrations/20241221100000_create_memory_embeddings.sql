@@ -0,0 +1,18 @@
+-- Pinned memories are always injected into the system prompt, the rest are ranked by relevance to the latest user turn
+ALTER TABLE memories ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- One embedding per memory, recomputed when the content hash or the embedding model changes
+CREATE TABLE memory_embeddings (
+    memory_id UUID PRIMARY KEY REFERENCES memories(id) ON DELETE CASCADE,
+    model TEXT NOT NULL,
+    -- SHA-256 of the content the embedding was computed from
+    content_hash TEXT NOT NULL,
+    embedding REAL[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON memory_embeddings
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
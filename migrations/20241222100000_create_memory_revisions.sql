@@ -0,0 +1,37 @@
+-- Every write to a memory, with who made it and why, so merges by the increment prompt can be inspected and undone
+CREATE TABLE memory_revisions (
+    id UUID PRIMARY KEY,
+    memory_id UUID NOT NULL REFERENCES memories(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    -- user, nightly_job, realtime_job or admin_job
+    author TEXT NOT NULL,
+    -- NEW or UPDATE from the increment prompt, EDIT, DELETE or ROLLBACK otherwise
+    reason TEXT NOT NULL,
+    previous_content TEXT,
+    content TEXT NOT NULL,
+    previous_grouping TEXT,
+    grouping TEXT,
+    -- Word level diff from previous_content to content
+    diff TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_memory_revisions_memory_id ON memory_revisions (memory_id, created_at);
+
+-- The messages sampled by the generation run that created or updated a memory
+CREATE TABLE memory_sources (
+    memory_id UUID NOT NULL REFERENCES memories(id) ON DELETE CASCADE,
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (memory_id, message_id)
+);
+
+CREATE INDEX idx_memory_sources_message_id ON memory_sources (message_id);
+
+-- Memories from before revisions were recorded get a creation revision with their current content,
+-- so every memory has a history to roll back to
+INSERT INTO memory_revisions (id, memory_id, user_id, author, reason, previous_content, content, previous_grouping, grouping, diff, created_at)
+SELECT uuid_generate_v4(), id, user_id, 'user', 'NEW', NULL, content, NULL, grouping,
+    '{+' || array_to_string(regexp_split_to_array(btrim(content), '\s+'), '+} {+') || '+}',
+    COALESCE(created_at, CURRENT_TIMESTAMP)
+FROM memories;
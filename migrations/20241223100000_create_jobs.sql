@@ -0,0 +1,35 @@
+CREATE TYPE job_status_enum AS ENUM ('queued', 'running', 'succeeded', 'failed', 'cancelled');
+
+-- Background work, claimed by workers with FOR UPDATE SKIP LOCKED so any number of them can share the table
+CREATE TABLE jobs (
+    id UUID PRIMARY KEY,
+    kind TEXT NOT NULL,
+    -- The user the job is for, if any, for filtering in the admin endpoints
+    user_id TEXT,
+    payload JSONB NOT NULL,
+    status job_status_enum NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+    -- When the job may next be claimed, pushed back with exponential backoff after a failure
+    run_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- At most one queued job per key, and a job isn't claimed while another with its key is running
+    dedupe_key TEXT,
+    locked_by TEXT,
+    locked_at TIMESTAMP WITH TIME ZONE,
+    last_error TEXT,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_jobs_queued_run_at ON jobs (run_at) WHERE status = 'queued';
+CREATE INDEX idx_jobs_running_locked_at ON jobs (locked_at) WHERE status = 'running';
+CREATE INDEX idx_jobs_status_created_at ON jobs (status, created_at DESC);
+CREATE INDEX idx_jobs_user_id ON jobs (user_id);
+CREATE INDEX idx_jobs_running_dedupe_key ON jobs (dedupe_key) WHERE status = 'running';
+CREATE UNIQUE INDEX idx_jobs_queued_dedupe_key ON jobs (dedupe_key) WHERE status = 'queued';
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON jobs
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,19 @@
+-- Per-day, per-prompt counts of how structured LLM replies were parsed, to catch prompts the model drifts away from
+CREATE TABLE llm_parse_stats (
+    day DATE NOT NULL,
+    prompt TEXT NOT NULL,
+    -- Parsed from the forced tool call
+    structured BIGINT NOT NULL DEFAULT 0,
+    -- The tool call was missing or invalid, parsed from a plain text reply instead
+    fallback BIGINT NOT NULL DEFAULT 0,
+    -- Neither produced anything usable
+    failed BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (day, prompt)
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON llm_parse_stats
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,35 @@
+-- Per-user memory preferences, users without a row have everything on and nothing excluded
+CREATE TABLE memory_settings (
+    user_id TEXT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- Off stops memories being generated or injected, existing ones are kept
+    memory_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Off stops extraction right after a message, leaving it to the nightly run
+    realtime_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Groupings never generated into or injected from
+    excluded_groups TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON memory_settings
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+-- Groupings a user created on top of the built in ones, offered to the grouping and increment prompts
+CREATE TABLE custom_memory_groups (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Tells the prompts what belongs in the group
+    description TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_custom_memory_groups_user_id_name ON custom_memory_groups (user_id, LOWER(name));
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON custom_memory_groups
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,46 @@
+-- How sure we still are of a memory, 1 when it was last created, edited or re-extracted, halving
+-- every half-life since. Scales its relevance ranking
+ALTER TABLE memories ADD COLUMN confidence REAL NOT NULL DEFAULT 1.0;
+-- Last time it was injected into a system prompt
+ALTER TABLE memories ADD COLUMN last_used_at TIMESTAMP WITH TIME ZONE;
+-- Last time the user edited it or a generation run found it again
+ALTER TABLE memories ADD COLUMN last_confirmed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+-- Set by the decay job once a memory is neither confirmed nor used for long enough. Archived
+-- memories are kept but no longer injected or shown in the memory list
+ALTER TABLE memories ADD COLUMN archived_at TIMESTAMP WITH TIME ZONE;
+
+-- Triggers are off so the backfill neither bumps updated_at nor floods the sync log
+ALTER TABLE memories DISABLE TRIGGER USER;
+
+UPDATE memories SET last_confirmed_at = updated_at;
+
+ALTER TABLE memories ENABLE TRIGGER USER;
+
+CREATE INDEX idx_memories_decay ON memories (last_confirmed_at)
+WHERE deleted_at IS NULL AND archived_at IS NULL AND NOT pinned;
+
+-- Marking memories used on every chat and the nightly decay would otherwise bump updated_at and
+-- reorder the memory list, so updates that only touch those bookkeeping columns leave it alone.
+-- Archiving by the decay job doesn't count as an edit either
+DROP TRIGGER set_timestamp ON memories;
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON memories
+FOR EACH ROW
+WHEN ((to_jsonb(OLD) - 'last_used_at' - 'confidence' - 'archived_at' - 'updated_at') IS DISTINCT FROM (to_jsonb(NEW) - 'last_used_at' - 'confidence' - 'archived_at' - 'updated_at'))
+EXECUTE PROCEDURE trigger_set_timestamp();
+
+-- Likewise they'd flood the sync log, so they don't count as sync changes. Archiving still does,
+-- clients drop archived memories from their list
+DROP TRIGGER record_sync_change ON memories;
+
+CREATE TRIGGER record_sync_change
+AFTER INSERT OR DELETE ON memories
+FOR EACH ROW
+EXECUTE PROCEDURE record_sync_change('memory');
+
+CREATE TRIGGER record_sync_change_update
+AFTER UPDATE ON memories
+FOR EACH ROW
+WHEN ((to_jsonb(OLD) - 'last_used_at' - 'confidence' - 'updated_at') IS DISTINCT FROM (to_jsonb(NEW) - 'last_used_at' - 'confidence' - 'updated_at'))
+EXECUTE PROCEDURE record_sync_change('memory');
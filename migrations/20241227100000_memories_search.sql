@@ -0,0 +1,3 @@
+-- Full-text search over memory content for the memory panel, queried with the same expression
+CREATE INDEX idx_memories_content_fts ON memories USING GIN (to_tsvector('english', content))
+WHERE deleted_at IS NULL;
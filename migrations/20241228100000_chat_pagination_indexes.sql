@@ -0,0 +1,7 @@
+-- Keyset pagination of a user's chats by last update and of a chat's messages by creation
+CREATE INDEX idx_chats_user_id_updated_at ON chats (user_id, updated_at DESC, id DESC)
+WHERE deleted_at IS NULL;
+CREATE INDEX idx_messages_chat_id_created_at ON messages (chat_id, created_at DESC, id DESC);
+
+-- Files of a page of messages
+CREATE INDEX IF NOT EXISTS idx_files_message_id ON files (message_id);
@@ -0,0 +1,6 @@
+-- Full-text search over chat names and messages, queried with the same expressions.
+-- Regenerated messages were replaced by their regeneration and are never searched
+CREATE INDEX idx_messages_text_fts ON messages USING GIN (to_tsvector('english', text))
+WHERE regenerated = false;
+CREATE INDEX idx_chats_name_fts ON chats USING GIN (to_tsvector('english', name))
+WHERE deleted_at IS NULL;
@@ -0,0 +1,29 @@
+-- The message each message follows, so regenerations show up as siblings: alternatives for the
+-- same spot share a parent. NULL for the first messages of a chat. Branches are separate chats,
+-- linked through chats.parent_message_id
+ALTER TABLE messages ADD COLUMN parent_id UUID REFERENCES messages (id) ON DELETE SET NULL;
+
+CREATE INDEX idx_messages_parent_id ON messages (parent_id);
+
+-- Older messages only have their order. The active path, the non regenerated messages, is
+-- linked in order, regenerated ones follow whatever message came before them. Triggers are off
+-- so the backfill neither bumps updated_at nor floods the sync log
+ALTER TABLE messages DISABLE TRIGGER USER;
+
+-- One pass over each chat in order rather than a lookup per message
+UPDATE messages m
+SET parent_id = linked.parent_id
+FROM (
+    SELECT id, LAG(id) OVER (PARTITION BY chat_id ORDER BY created_at, id) AS parent_id
+    FROM messages
+    WHERE NOT COALESCE(regenerated, FALSE)
+    UNION ALL
+    SELECT id, parent_id FROM (
+        SELECT id, regenerated, LAG(id) OVER (PARTITION BY chat_id ORDER BY created_at, id) AS parent_id
+        FROM messages
+    ) ordered
+    WHERE regenerated
+) linked
+WHERE m.id = linked.id AND linked.parent_id IS NOT NULL;
+
+ALTER TABLE messages ENABLE TRIGGER USER;
@@ -0,0 +1,13 @@
+-- Public read-only links to a snapshot of a chat up to a message. The snapshot holds no user ids or hidden files
+CREATE TABLE chat_shares (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token TEXT NOT NULL UNIQUE,
+    up_to_message_id UUID NOT NULL,
+    snapshot JSONB NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_chat_shares_chat_id ON chat_shares (chat_id, created_at);
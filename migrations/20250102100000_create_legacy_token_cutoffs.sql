@@ -0,0 +1,7 @@
+-- Tokens issued before sessions existed have no jti to revoke, so revoking all of a user's sessions
+-- also sets a cutoff here and legacy tokens issued before it stop working.
+-- No foreign key on users, like sessions
+CREATE TABLE legacy_token_cutoffs (
+    user_id TEXT PRIMARY KEY,
+    tokens_valid_after TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,4 @@
+-- The nightly run is keyed by the day it covers and only ever queued once per day, whatever the
+-- status of the first one. Every API instance runs the scheduler, and an instance firing after the
+-- first run was claimed would otherwise queue another sample
+CREATE UNIQUE INDEX idx_jobs_nightly_dedupe_key ON jobs (dedupe_key) WHERE kind = 'nightly_memories';
@@ -0,0 +1,39 @@
+-- Sync events carry the cursor of their change, so a client woken by one can ask /sync/changes for
+-- everything up to it even while other transactions hold back the settled history
+CREATE OR REPLACE FUNCTION record_sync_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    changed RECORD;
+    is_deleted BOOLEAN;
+    change_id BIGINT;
+    change_txid BIGINT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    is_deleted := TG_OP = 'DELETE' OR (to_jsonb(changed) ->> 'deleted_at') IS NOT NULL;
+
+    INSERT INTO sync_changes (user_id, entity, entity_id, deleted)
+    VALUES (changed.user_id, TG_ARGV[0], changed.id, is_deleted)
+    RETURNING id, txid INTO change_id, change_txid;
+
+    PERFORM pg_notify(
+        'sync_changes',
+        json_build_object(
+            'user_id', changed.user_id,
+            'entity', TG_ARGV[0],
+            'id', changed.id,
+            'action', CASE
+                WHEN is_deleted THEN 'deleted'
+                WHEN TG_OP = 'INSERT' THEN 'created'
+                ELSE 'updated'
+            END,
+            'cursor', change_txid || '-' || change_id
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
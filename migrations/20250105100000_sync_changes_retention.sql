@@ -0,0 +1,9 @@
+-- The change log is pruned nightly to SYNC_RETENTION_DAYS. The newest pruned position is kept here,
+-- a client resuming from an older cursor may have missed changes and has to sync in full
+CREATE TABLE sync_changes_pruned (
+    singleton BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (singleton),
+    txid BIGINT NOT NULL,
+    id BIGINT NOT NULL
+);
+
+CREATE INDEX idx_sync_changes_created_at ON sync_changes (created_at);
@@ -0,0 +1,41 @@
+-- Archived memories leave the synced set, so archiving is recorded as a tombstone like a delete.
+-- Unarchiving records an update and clients pick the memory up again
+CREATE OR REPLACE FUNCTION record_sync_change()
+RETURNS TRIGGER AS $$
+DECLARE
+    changed RECORD;
+    is_deleted BOOLEAN;
+    change_id BIGINT;
+    change_txid BIGINT;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    is_deleted := TG_OP = 'DELETE'
+        OR (to_jsonb(changed) ->> 'deleted_at') IS NOT NULL
+        OR (to_jsonb(changed) ->> 'archived_at') IS NOT NULL;
+
+    INSERT INTO sync_changes (user_id, entity, entity_id, deleted)
+    VALUES (changed.user_id, TG_ARGV[0], changed.id, is_deleted)
+    RETURNING id, txid INTO change_id, change_txid;
+
+    PERFORM pg_notify(
+        'sync_changes',
+        json_build_object(
+            'user_id', changed.user_id,
+            'entity', TG_ARGV[0],
+            'id', changed.id,
+            'action', CASE
+                WHEN is_deleted THEN 'deleted'
+                WHEN TG_OP = 'INSERT' THEN 'created'
+                ELSE 'updated'
+            END,
+            'cursor', change_txid || '-' || change_id
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
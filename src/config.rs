@@ -1,6 +1,11 @@
 use anyhow::anyhow;
 use shuttle_runtime::SecretStore;
 
+const DEFAULT_JOB_WORKERS: usize = 4;
+const DEFAULT_MEMORY_HALF_LIFE_DAYS: i64 = 30;
+const DEFAULT_MEMORY_ARCHIVE_AFTER_DAYS: i64 = 90;
+const DEFAULT_SYNC_RETENTION_DAYS: i64 = 30;
+
 #[derive(Clone)]
 pub struct AppConfig {
     pub db_connection_uri: String,
@@ -14,6 +19,18 @@ pub struct AppConfig {
     pub stripe_secret_key: String,
     pub loops_api_key: String,
     pub workos_webhook_signature: String,
+    pub openai_api_key: Option<String>,
+    pub anthropic_api_key: Option<String>,
+    pub llm_provider: Option<String>,
+    pub job_workers: usize,
+    /// Run only the job workers and scheduler, serving nothing but the hello route as a health check
+    pub jobs_only: bool,
+    /// Days for an unconfirmed memory's confidence to halve
+    pub memory_half_life_days: i64,
+    /// Days a memory can go unconfirmed and unused before it's archived
+    pub memory_archive_after_days: i64,
+    /// Days the sync change log is kept, clients with older cursors sync in full
+    pub sync_retention_days: i64,
 }
 
 impl AppConfig {
@@ -63,6 +80,60 @@ impl AppConfig {
             .get("WORKOS_WEBHOOK_SIGNATURE")
             .ok_or_else(|| anyhow!("WORKOS_WEBHOOK_SIGNATURE not found"))?;
 
+        // Optional, direct provider keys enable failover when Keywords is down
+        let openai_api_key = secret_store
+            .get("OPENAI_API_KEY")
+            .filter(|key| !key.is_empty());
+        let anthropic_api_key = secret_store
+            .get("ANTHROPIC_API_KEY")
+            .filter(|key| !key.is_empty());
+
+        // Optional, set to "mock" to run against the in-process LLM stand-in
+        let llm_provider = secret_store
+            .get("LLM_PROVIDER")
+            .filter(|provider| !provider.is_empty());
+
+        // Optional, how many background jobs this instance runs at once. 0 leaves jobs to other instances
+        let job_workers = secret_store
+            .get("JOB_WORKERS")
+            .map(|workers| workers.parse())
+            .transpose()
+            .map_err(|e| anyhow!("JOB_WORKERS is not a number: {}", e))?
+            .unwrap_or(DEFAULT_JOB_WORKERS);
+
+        // Optional, set to "true" for a worker deployment that doesn't serve the API
+        let jobs_only = secret_store
+            .get("JOBS_ONLY")
+            .map(|jobs_only| jobs_only.parse())
+            .transpose()
+            .map_err(|e| anyhow!("JOBS_ONLY is not a boolean: {}", e))?
+            .unwrap_or(false);
+        if jobs_only && job_workers == 0 {
+            return Err(anyhow!("JOBS_ONLY needs JOB_WORKERS above 0"));
+        }
+
+        // Optional, how fast memories decay and when they're archived
+        let memory_half_life_days = secret_store
+            .get("MEMORY_HALF_LIFE_DAYS")
+            .map(|days| days.parse())
+            .transpose()
+            .map_err(|e| anyhow!("MEMORY_HALF_LIFE_DAYS is not a number: {}", e))?
+            .unwrap_or(DEFAULT_MEMORY_HALF_LIFE_DAYS);
+        let memory_archive_after_days = secret_store
+            .get("MEMORY_ARCHIVE_AFTER_DAYS")
+            .map(|days| days.parse())
+            .transpose()
+            .map_err(|e| anyhow!("MEMORY_ARCHIVE_AFTER_DAYS is not a number: {}", e))?
+            .unwrap_or(DEFAULT_MEMORY_ARCHIVE_AFTER_DAYS);
+
+        // Optional, how long the sync change log is kept
+        let sync_retention_days = secret_store
+            .get("SYNC_RETENTION_DAYS")
+            .map(|days| days.parse())
+            .transpose()
+            .map_err(|e| anyhow!("SYNC_RETENTION_DAYS is not a number: {}", e))?
+            .unwrap_or(DEFAULT_SYNC_RETENTION_DAYS);
+
         Ok(AppConfig {
             db_connection_uri: db_connection_string,
             keywords_api_key,
@@ -75,6 +146,14 @@ impl AppConfig {
             stripe_secret_key,
             loops_api_key,
             workos_webhook_signature,
+            openai_api_key,
+            anthropic_api_key,
+            llm_provider,
+            job_workers,
+            jobs_only,
+            memory_half_life_days,
+            memory_archive_after_days,
+            sync_retention_days,
         })
     }
 }
@@ -0,0 +1,241 @@
+// Background job workers, pulling from the jobs table so queued work survives restarts and failures are retried
+
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use rand::seq::SliceRandom;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::models::job::{Job, JobPayload};
+use crate::models::memory_revision::MemoryAuthor;
+use crate::models::{Memory, SyncChange, User};
+use crate::{routes, AppConfig, AppState};
+
+/// How long a worker waits before polling again when there's nothing due
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// Jobs running longer than this are abandoned and retried
+const JOB_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+/// How often to look for jobs left running by a worker that died
+const REAP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Fraction of users the nightly run generates memories for
+const NIGHTLY_MEMORIES_FRACTION: f64 = 0.05;
+
+/// Start `app_config.job_workers` workers on this instance, plus the reaper that recovers jobs from dead ones
+pub fn start_workers(app_state: Arc<AppState>, app_config: Arc<AppConfig>) {
+    if app_config.job_workers == 0 {
+        info!("No job workers on this instance");
+        return;
+    }
+
+    // Unique per process so the jobs table shows which instance holds a job
+    let instance_id = Uuid::new_v4().simple().to_string()[..8].to_string();
+    for n in 0..app_config.job_workers {
+        let worker_id = format!("{}-{}", instance_id, n);
+        tokio::spawn(work(app_state.clone(), app_config.clone(), worker_id));
+    }
+    tokio::spawn(reap(app_state));
+    info!("Started {} job workers", app_config.job_workers);
+}
+
+async fn work(app_state: Arc<AppState>, app_config: Arc<AppConfig>, worker_id: String) {
+    loop {
+        let job = match Job::claim(&app_state.pool, &worker_id).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Worker {} failed to claim a job: {:?}", worker_id, e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        info!(
+            "Worker {} running {} job {}, attempt {} of {}",
+            worker_id, job.kind, job.id, job.attempts, job.max_attempts
+        );
+        let result =
+            match tokio::time::timeout(JOB_TIMEOUT, run(&app_state, &app_config, &job)).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("Timed out after {:?}", JOB_TIMEOUT)),
+            };
+
+        match result {
+            Ok(()) => {
+                if let Err(e) = Job::complete(&app_state.pool, job.id).await {
+                    error!("Failed to mark job {} succeeded: {:?}", job.id, e);
+                }
+            }
+            Err(e) => match Job::fail(&app_state.pool, &job, &format!("{:?}", e)).await {
+                Ok(status) => warn!(
+                    "{} job {} failed, now {}: {:?}",
+                    job.kind, job.id, status, e
+                ),
+                Err(fail_error) => {
+                    error!(
+                        "Failed to record failure of job {}: {:?}",
+                        job.id, fail_error
+                    )
+                }
+            },
+        }
+    }
+}
+
+async fn run(app_state: &Arc<AppState>, app_config: &Arc<AppConfig>, job: &Job) -> Result<()> {
+    match job.payload()? {
+        JobPayload::NightlyMemories { begin } => {
+            let queued = enqueue_memories_for_sample(app_state, begin).await?;
+            info!("Queued nightly memory generation for {} users", queued);
+        }
+        JobPayload::GenerateMemories {
+            user_id,
+            begin,
+            end,
+            max_samples,
+            samples_per_query,
+            author,
+        } => {
+            let range = begin.map(|begin| (begin, end.unwrap_or_else(Utc::now)));
+            let memories = routes::memory::generate_memories_from_chat_history(
+                &web::Data::new(app_state.clone()),
+                None,
+                &user_id,
+                max_samples,
+                samples_per_query,
+                range,
+                author,
+            )
+            .await?;
+            info!(
+                "Memories generated successfully for user: {}. Count: {}",
+                user_id,
+                memories.len()
+            );
+        }
+        JobPayload::AutorenameChat { user_id, chat_id } => {
+            match routes::chat::autorename(app_state, chat_id, &user_id, None).await? {
+                Some(chat) => info!("Chat {} renamed to {}", chat.id, chat.name),
+                None => warn!("Chat {} has no messages to name it from", chat_id),
+            }
+        }
+        JobPayload::SyncUsersWorkos => {
+            let users =
+                routes::auth::sync_workos_users(app_config.clone(), &app_state.pool).await?;
+            info!("Synced {} users from WorkOS", users.len());
+        }
+        JobPayload::SyncUsersKeywords => {
+            routes::auth::sync_keywords_users(app_config, &app_state.pool).await?;
+        }
+        JobPayload::DecayMemories => {
+            let (decayed, archived) = Memory::decay(
+                &app_state.pool,
+                Duration::days(app_config.memory_half_life_days),
+                Duration::days(app_config.memory_archive_after_days),
+                &app_state.memory_cache,
+            )
+            .await?;
+            info!("Decayed {} memories, archived {}", decayed, archived);
+        }
+        JobPayload::PruneSyncChanges => {
+            let pruned = SyncChange::prune(
+                &app_state.pool,
+                Utc::now() - Duration::days(app_config.sync_retention_days),
+            )
+            .await?;
+            info!("Pruned {} sync changes", pruned);
+        }
+        JobPayload::ImportMemories {
+            user_id,
+            export,
+            merge,
+        } => {
+            let imported = routes::memory::import_memories_for_user(
+                &web::Data::new(app_state.clone()),
+                &user_id,
+                export,
+                merge,
+            )
+            .await?;
+            info!(
+                "Imported {} memories for user {}, {} duplicates skipped",
+                imported.memories.len(),
+                user_id,
+                imported.duplicates
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Queue memory generation for a random sample of users. Returns how many jobs were queued
+async fn enqueue_memories_for_sample(
+    app_state: &AppState,
+    begin: chrono::DateTime<Utc>,
+) -> Result<usize> {
+    let all_users = User::get_all(&app_state.pool).await?;
+    info!("Total users: {}", all_users.len());
+
+    let sample_size = (all_users.len() as f64 * NIGHTLY_MEMORIES_FRACTION).ceil() as usize;
+    let selected_users: Vec<User> = all_users
+        .choose_multiple(&mut rand::thread_rng(), sample_size)
+        .cloned()
+        .collect();
+    info!("Selected users: {}", selected_users.len());
+
+    let mut queued = 0;
+    for user in selected_users {
+        let payload = JobPayload::GenerateMemories {
+            user_id: user.id,
+            begin: Some(begin),
+            end: None,
+            max_samples: None,
+            samples_per_query: None,
+            author: MemoryAuthor::NightlyJob,
+        };
+        if Job::enqueue(&app_state.pool, &payload, None)
+            .await?
+            .is_some()
+        {
+            queued += 1;
+        }
+    }
+
+    Ok(queued)
+}
+
+/// Queue the nightly memory run, covering the last day of messages, the memory decay and the sync
+/// log pruning. Called from the scheduler
+pub async fn enqueue_nightly_jobs(app_state: &AppState) {
+    let payloads = [
+        JobPayload::NightlyMemories {
+            begin: Utc::now() - Duration::days(1),
+        },
+        JobPayload::DecayMemories,
+        JobPayload::PruneSyncChanges,
+    ];
+    for payload in payloads {
+        if let Err(e) = Job::enqueue(&app_state.pool, &payload, None).await {
+            error!("Failed to queue {} job: {:?}", payload.kind(), e);
+        }
+    }
+}
+
+/// Requeue jobs whose worker stopped responding. Safe to run on every instance with workers
+async fn reap(app_state: Arc<AppState>) {
+    // Anything running past the job timeout, plus some slack, has lost its worker
+    let stale_after = Duration::seconds(JOB_TIMEOUT.as_secs() as i64) + Duration::minutes(5);
+    loop {
+        match Job::requeue_stale(&app_state.pool, stale_after).await {
+            Ok(0) => {}
+            Ok(recovered) => warn!("Recovered {} jobs from dead workers", recovered),
+            Err(e) => error!("Failed to recover stale jobs: {:?}", e),
+        }
+        tokio::time::sleep(REAP_INTERVAL).await;
+    }
+}
@@ -0,0 +1,318 @@
+// llm/anthropic.rs
+
+use anyhow::{anyhow, Result};
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseStream,
+    CreateChatCompletionRequest, CreateChatCompletionResponse, CreateChatCompletionStreamResponse,
+    Stop,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+use crate::llm::{completion_response, stream_chunk, LlmProvider, ProviderKind};
+
+const MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Direct Anthropic Messages API adapter, translating OpenAI requests and responses at the edge
+pub struct AnthropicProvider {
+    http: reqwest::Client,
+    api_key: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: &str) -> Self {
+        AnthropicProvider {
+            http: reqwest::Client::new(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Convert an OpenAI chat completion request into a Messages API body
+    fn to_messages_body(request: &CreateChatCompletionRequest, stream: bool) -> Value {
+        let mut system = Vec::new();
+        let mut messages: Vec<Value> = Vec::new();
+
+        for message in &request.messages {
+            let (role, blocks) = match message {
+                ChatCompletionRequestMessage::System(system_message) => {
+                    system.push(system_message.content.clone());
+                    continue;
+                }
+                ChatCompletionRequestMessage::User(user_message) => {
+                    let blocks = match &user_message.content {
+                        ChatCompletionRequestUserMessageContent::Text(text) => {
+                            vec![json!({ "type": "text", "text": text })]
+                        }
+                        ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                            .iter()
+                            .map(|part| match part {
+                                ChatCompletionRequestMessageContentPart::Text(text_part) => {
+                                    json!({ "type": "text", "text": text_part.text })
+                                }
+                                ChatCompletionRequestMessageContentPart::ImageUrl(image_part) => {
+                                    image_block(&image_part.image_url.url)
+                                }
+                            })
+                            .collect(),
+                    };
+                    ("user", blocks)
+                }
+                ChatCompletionRequestMessage::Assistant(assistant_message) => {
+                    let text = assistant_message.content.clone().unwrap_or_default();
+                    ("assistant", vec![json!({ "type": "text", "text": text })])
+                }
+                // Tool and function messages have no equivalent without tool definitions
+                _ => continue,
+            };
+
+            // The Messages API requires alternating roles, so merge consecutive turns
+            match messages.last_mut() {
+                Some(last) if last["role"] == role => {
+                    if let Some(content) = last["content"].as_array_mut() {
+                        content.extend(blocks);
+                    }
+                }
+                _ => messages.push(json!({ "role": role, "content": blocks })),
+            }
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        match &request.stop {
+            Some(Stop::String(stop)) => body["stop_sequences"] = json!([stop]),
+            Some(Stop::StringArray(stops)) => body["stop_sequences"] = json!(stops),
+            None => {}
+        }
+        if let Some(user_id) = &request.customer_identifier {
+            body["metadata"] = json!({ "user_id": user_id });
+        }
+        body
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .post(MESSAGES_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            let status = response.status();
+            let error_body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to read response body".to_string());
+            Err(anyhow!("Anthropic returned {}: {}", status, error_body))
+        }
+    }
+}
+
+/// Anthropic takes images either as base64 blocks or as plain urls
+fn image_block(url: &str) -> Value {
+    if let Some(data_url) = url.strip_prefix("data:") {
+        if let Some((media_type, data)) = data_url.split_once(";base64,") {
+            return json!({
+                "type": "image",
+                "source": { "type": "base64", "media_type": media_type, "data": data },
+            });
+        }
+    }
+    json!({ "type": "image", "source": { "type": "url", "url": url } })
+}
+
+fn finish_reason(stop_reason: &str) -> &'static str {
+    match stop_reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        _ => "stop",
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn translate_model(&self, model: &str) -> Option<String> {
+        // bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0 -> claude-3-5-sonnet-20241022
+        let model = model.strip_prefix("bedrock/anthropic.").unwrap_or(model);
+        let model = match model.rfind("-v") {
+            Some(idx) if model[idx..].contains(':') => &model[..idx],
+            _ => model,
+        };
+        if model.starts_with("claude-") {
+            Some(model.to_string())
+        } else {
+            None
+        }
+    }
+
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        let body = Self::to_messages_body(&request, false);
+        let response: Value = self.send(&body).await?.json().await?;
+
+        let content = response["content"]
+            .as_array()
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .filter_map(|block| block["text"].as_str())
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+
+        let usage = match (
+            response["usage"]["input_tokens"].as_u64(),
+            response["usage"]["output_tokens"].as_u64(),
+        ) {
+            (Some(input), Some(output)) => Some((input as u32, output as u32)),
+            _ => None,
+        };
+
+        completion_response(
+            response["id"].as_str().unwrap_or_default(),
+            response["model"].as_str().unwrap_or(&request.model),
+            &content,
+            finish_reason(response["stop_reason"].as_str().unwrap_or_default()),
+            usage,
+        )
+    }
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream> {
+        let body = Self::to_messages_body(&request, true);
+        let response = self.send(&body).await?;
+
+        let state = SseState {
+            bytes: Box::pin(response.bytes_stream()),
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            id: String::new(),
+            model: request.model.clone(),
+            done: false,
+        };
+
+        let stream = stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(item) = state.pending.pop_front() {
+                    return Some((item, state));
+                }
+                if state.done {
+                    return None;
+                }
+                match state.bytes.next().await {
+                    Some(Ok(bytes)) => {
+                        state.buffer.extend_from_slice(&bytes);
+                        while let Some(pos) = state.buffer.windows(2).position(|w| w == b"\n\n") {
+                            let event: Vec<u8> = state.buffer.drain(..pos + 2).collect();
+                            state.handle_event(&String::from_utf8_lossy(&event));
+                        }
+                    }
+                    Some(Err(e)) => {
+                        state.done = true;
+                        return Some((Err(OpenAIError::StreamError(e.to_string())), state));
+                    }
+                    None => state.done = true,
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+type ChunkResult = Result<CreateChatCompletionStreamResponse, OpenAIError>;
+
+/// State for translating Anthropic server sent events into OpenAI chunks
+struct SseState {
+    bytes: Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>,
+    buffer: Vec<u8>,
+    pending: VecDeque<ChunkResult>,
+    id: String,
+    model: String,
+    done: bool,
+}
+
+impl SseState {
+    fn handle_event(&mut self, event: &str) {
+        for data in event.lines().filter_map(|line| line.strip_prefix("data:")) {
+            let data: Value = match serde_json::from_str(data.trim()) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.pending.push_back(Err(OpenAIError::JSONDeserialize(e)));
+                    continue;
+                }
+            };
+
+            match data["type"].as_str() {
+                Some("message_start") => {
+                    self.id = data["message"]["id"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string();
+                    if let Some(model) = data["message"]["model"].as_str() {
+                        self.model = model.to_string();
+                    }
+                }
+                Some("content_block_delta") => {
+                    if let Some(text) = data["delta"]["text"].as_str() {
+                        self.push_chunk(Some(text), None);
+                    }
+                }
+                Some("message_delta") => {
+                    if let Some(stop_reason) = data["delta"]["stop_reason"].as_str() {
+                        self.push_chunk(None, Some(finish_reason(stop_reason)));
+                    }
+                }
+                Some("message_stop") => self.done = true,
+                Some("error") => {
+                    let message = data["error"]["message"]
+                        .as_str()
+                        .unwrap_or("Unknown Anthropic stream error");
+                    self.pending
+                        .push_back(Err(OpenAIError::StreamError(message.to_string())));
+                    self.done = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn push_chunk(&mut self, content: Option<&str>, finish_reason: Option<&str>) {
+        let chunk = stream_chunk(&self.id, &self.model, content, finish_reason)
+            .map_err(OpenAIError::JSONDeserialize);
+        self.pending.push_back(chunk);
+    }
+}
@@ -0,0 +1,156 @@
+// llm/context.rs
+
+use anyhow::{Context, Result};
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+    ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestUserMessageContent, CreateChatCompletionRequestArgs,
+};
+use lazy_static::lazy_static;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+use tracing::warn;
+
+use crate::llm::{message_text, LlmRouter};
+use crate::prompts::Prompts;
+
+/// Role and separator tokens every message costs on top of its content
+const TOKENS_PER_MESSAGE: usize = 4;
+/// Flat cost of an attached image, roughly a high detail 512px tile
+const TOKENS_PER_IMAGE: usize = 765;
+/// Room kept for the synthetic summary when a model summarizes dropped turns
+pub const SUMMARY_MAX_TOKENS: usize = 512;
+/// Cheap model used to summarize dropped turns
+const SUMMARY_MODEL: &str = "gemini/gemini-1.5-flash";
+
+lazy_static! {
+    // Building the tokenizer is expensive, do it once. Falls back to a character estimate if it fails
+    static ref BPE: Option<CoreBPE> = cl100k_base()
+        .map_err(|e| warn!("Failed to initialize tokenizer: {:?}", e))
+        .ok();
+}
+
+pub fn count_tokens(text: &str) -> usize {
+    match BPE.as_ref() {
+        Some(bpe) => bpe.encode_with_special_tokens(text).len(),
+        None => text.chars().count() / 4,
+    }
+}
+
+fn image_count(message: &ChatCompletionRequestMessage) -> usize {
+    match message {
+        ChatCompletionRequestMessage::User(user_message) => match &user_message.content {
+            ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                .iter()
+                .filter(|part| matches!(part, ChatCompletionRequestMessageContentPart::ImageUrl(_)))
+                .count(),
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Estimated prompt tokens for a single message. Counted with cl100k, so it's approximate for
+/// non-OpenAI models, callers should leave some headroom
+pub fn count_message_tokens(message: &ChatCompletionRequestMessage) -> usize {
+    TOKENS_PER_MESSAGE
+        + count_tokens(&message_text(message))
+        + image_count(message) * TOKENS_PER_IMAGE
+}
+
+/// The result of fitting a conversation into a token budget
+pub struct Truncation {
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// Turns that didn't fit, oldest first
+    pub dropped: Vec<ChatCompletionRequestMessage>,
+}
+
+/// Fit messages into `budget` prompt tokens. Leading system messages and the latest user turn
+/// (the last user message and anything after it) are always kept, even if they alone exceed the
+/// budget. The rest of the history is kept newest first for as long as it fits.
+pub fn truncate_to_budget(
+    mut messages: Vec<ChatCompletionRequestMessage>,
+    budget: usize,
+) -> Truncation {
+    let system_end = messages
+        .iter()
+        .position(|message| !matches!(message, ChatCompletionRequestMessage::System(_)))
+        .unwrap_or(messages.len());
+    let latest_turn_start = messages
+        .iter()
+        .rposition(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+        .unwrap_or(messages.len().saturating_sub(1))
+        .max(system_end);
+
+    let latest_turn = messages.split_off(latest_turn_start);
+    let mut history = messages.split_off(system_end);
+    let mut kept = messages;
+
+    let mut used: usize = kept
+        .iter()
+        .chain(latest_turn.iter())
+        .map(count_message_tokens)
+        .sum();
+
+    // Walk the history from newest to oldest, stop at the first turn that doesn't fit so the
+    // kept history stays contiguous
+    let mut keep_from = history.len();
+    for (i, message) in history.iter().enumerate().rev() {
+        let tokens = count_message_tokens(message);
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        keep_from = i;
+    }
+
+    let kept_history = history.split_off(keep_from);
+    kept.extend(kept_history);
+    kept.extend(latest_turn);
+
+    Truncation {
+        messages: kept,
+        dropped: history,
+    }
+}
+
+/// Summarize dropped turns into a system message that can be placed after the system prompt
+pub async fn summarize_dropped(
+    llm: &LlmRouter,
+    dropped: &[ChatCompletionRequestMessage],
+) -> Result<ChatCompletionRequestMessage> {
+    let transcript = dropped
+        .iter()
+        .filter_map(|message| {
+            let role = match message {
+                ChatCompletionRequestMessage::User(_) => "user",
+                ChatCompletionRequestMessage::Assistant(_) => "assistant",
+                _ => return None,
+            };
+            Some(format!("{}: {}", role, message_text(message)))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(SUMMARY_MODEL)
+        .max_tokens(SUMMARY_MAX_TOKENS as u32)
+        .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+            .content(Prompts::SUMMARIZE_DROPPED_TURNS.replace("{0}", &transcript))
+            .build()?
+            .into()])
+        .build()?;
+
+    let response = llm.create(request).await?;
+    let summary = response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .context("Empty summary from AI")?;
+
+    Ok(ChatCompletionRequestMessage::System(
+        ChatCompletionRequestSystemMessage {
+            content: format!("Summary of the earlier conversation:\n{}", summary.trim()),
+            name: Some("summary".to_string()),
+        },
+    ))
+}
@@ -0,0 +1,161 @@
+// llm/embeddings.rs
+
+use anyhow::{anyhow, Result};
+use async_openai::config::OpenAIConfig;
+use async_openai::types::CreateEmbeddingRequestArgs;
+use async_openai::Client;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+
+const OPENAI_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+const HASH_EMBEDDING_DIMENSIONS: usize = 256;
+
+/// Turns text into vectors for relevance ranking. Vectors from different models aren't comparable,
+/// so stored embeddings are keyed by `model()`
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    fn model(&self) -> &str;
+
+    /// One vector per input, in input order
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+}
+
+/// OpenAI embeddings, used whenever an OpenAI key is configured
+pub struct OpenAIEmbeddingProvider {
+    client: Client<OpenAIConfig>,
+}
+
+impl OpenAIEmbeddingProvider {
+    pub fn new(api_key: &str) -> Self {
+        OpenAIEmbeddingProvider {
+            client: Client::with_config(OpenAIConfig::new().with_api_key(api_key)),
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAIEmbeddingProvider {
+    fn model(&self) -> &str {
+        OPENAI_EMBEDDING_MODEL
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(OPENAI_EMBEDDING_MODEL)
+            .input(texts.to_vec())
+            .build()?;
+
+        let mut data = self.client.embeddings().create(request).await?.data;
+        if data.len() != texts.len() {
+            return Err(anyhow!(
+                "Expected {} embeddings, got {}",
+                texts.len(),
+                data.len()
+            ));
+        }
+        data.sort_by_key(|embedding| embedding.index);
+
+        Ok(data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+}
+
+/// Deterministic bag-of-words feature hashing, no network. Good enough to rank on shared words,
+/// used in mock mode, without an OpenAI key, and in tests
+pub struct HashEmbeddingProvider {
+    dimensions: usize,
+}
+
+impl HashEmbeddingProvider {
+    pub fn new() -> Self {
+        HashEmbeddingProvider {
+            dimensions: HASH_EMBEDDING_DIMENSIONS,
+        }
+    }
+
+    /// FNV-1a, stable across builds and platforms unlike the std hasher
+    fn hash(token: &str) -> u64 {
+        token.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+        let lowercase = text.to_lowercase();
+        for token in lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| token.len() > 1)
+        {
+            let hash = Self::hash(token);
+            let sign = if hash & (1 << 63) == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign;
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+impl Default for HashEmbeddingProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for HashEmbeddingProvider {
+    fn model(&self) -> &str {
+        "hash-256"
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed_one(text)).collect())
+    }
+}
+
+/// OpenAI when a key is configured, the local hash embedding otherwise or when LLM_PROVIDER=mock
+pub fn embedding_provider_from_config(app_config: &AppConfig) -> Arc<dyn EmbeddingProvider> {
+    if app_config.llm_provider.as_deref() == Some("mock") {
+        info!("Using the hash embedding provider for memories");
+        return Arc::new(HashEmbeddingProvider::new());
+    }
+
+    match &app_config.openai_api_key {
+        Some(api_key) => Arc::new(OpenAIEmbeddingProvider::new(api_key)),
+        None => {
+            warn!("No OpenAI key configured, falling back to hash embeddings for memories");
+            Arc::new(HashEmbeddingProvider::new())
+        }
+    }
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Cosine similarity, 0 for mismatched or zero vectors
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
@@ -0,0 +1,58 @@
+// llm/keywords.rs
+
+use anyhow::Result;
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse,
+};
+use async_openai::Client;
+use async_trait::async_trait;
+
+use crate::llm::{LlmProvider, ProviderKind};
+
+/// Keywords AI gateway, OpenAI compatible and understands our extra request fields
+/// (customer_identifier, thread_identifier, fallback)
+pub struct KeywordsProvider {
+    client: Client<OpenAIConfig>,
+}
+
+impl KeywordsProvider {
+    pub fn new(api_key: &str) -> Self {
+        KeywordsProvider {
+            client: Client::with_config(
+                OpenAIConfig::new()
+                    .with_api_key(api_key)
+                    .with_api_base("https://api.keywordsai.co/api"),
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for KeywordsProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Keywords
+    }
+
+    fn translate_model(&self, model: &str) -> Option<String> {
+        // Keywords proxies every model we use
+        Some(model.to_string())
+    }
+
+    async fn create(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        // This field is not part of the OpenAI API and is only used internally
+        request.invisibility = None;
+        Ok(self.client.chat().create(request).await?)
+    }
+
+    async fn create_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream> {
+        request.invisibility = None;
+        Ok(self.client.chat().create_stream(request).await?)
+    }
+}
@@ -0,0 +1,156 @@
+// llm/mock.rs
+
+use anyhow::Result;
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::stream;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::llm::{completion_response, message_text, stream_chunk, LlmProvider, ProviderKind};
+
+/// In-process stand-in for an upstream LLM. Replies with the first recorded response whose
+/// trigger appears in the prompt, otherwise echoes the last user message back. Requests with
+/// tools get a recorded tool call if there is one, and the text reply otherwise.
+#[derive(Default)]
+pub struct MockProvider {
+    responses: Vec<(String, MockReply)>,
+}
+
+enum MockReply {
+    Text(String),
+    /// Arguments of a call to the request's first tool, as a JSON string
+    ToolCall(String),
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        MockProvider::default()
+    }
+
+    /// Reply with `response` whenever any message in the request contains `trigger`
+    #[allow(dead_code)] // Only used for recorded responses in offline runs
+    pub fn with_response(mut self, trigger: &str, response: &str) -> Self {
+        self.responses
+            .push((trigger.to_string(), MockReply::Text(response.to_string())));
+        self
+    }
+
+    /// Call the request's tool with `arguments` whenever a request with tools contains `trigger`
+    #[allow(dead_code)] // Only used for recorded responses in offline runs
+    pub fn with_tool_call(mut self, trigger: &str, arguments: &str) -> Self {
+        self.responses.push((
+            trigger.to_string(),
+            MockReply::ToolCall(arguments.to_string()),
+        ));
+        self
+    }
+
+    /// The first recorded reply of the right kind whose trigger appears in the request
+    fn find_reply(&self, texts: &[String], tool_call: bool) -> Option<&MockReply> {
+        self.responses
+            .iter()
+            .filter(|(_, reply)| matches!(reply, MockReply::ToolCall(_)) == tool_call)
+            .find(|(trigger, _)| texts.iter().any(|text| text.contains(trigger.as_str())))
+            .map(|(_, reply)| reply)
+    }
+
+    fn reply(&self, request: &CreateChatCompletionRequest) -> String {
+        let texts: Vec<String> = request.messages.iter().map(message_text).collect();
+
+        match self.find_reply(&texts, false) {
+            Some(MockReply::Text(response)) => response.clone(),
+            _ => texts.last().cloned().unwrap_or_default(),
+        }
+    }
+
+    /// A tool call reply, for requests with tools that have one recorded
+    fn tool_call_reply(
+        &self,
+        request: &CreateChatCompletionRequest,
+    ) -> Result<Option<CreateChatCompletionResponse>> {
+        let Some(tool) = request.tools.as_ref().and_then(|tools| tools.first()) else {
+            return Ok(None);
+        };
+        let texts: Vec<String> = request.messages.iter().map(message_text).collect();
+        let Some(MockReply::ToolCall(arguments)) = self.find_reply(&texts, true) else {
+            return Ok(None);
+        };
+
+        let response = json!({
+            "id": format!("mock-{}", Uuid::new_v4()),
+            "object": "chat.completion",
+            "created": Utc::now().timestamp(),
+            "model": request.model,
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": format!("call-{}", Uuid::new_v4()),
+                        "type": "function",
+                        "function": { "name": tool.function.name, "arguments": arguments },
+                    }],
+                },
+                "finish_reason": "tool_calls",
+            }],
+        });
+        Ok(Some(serde_json::from_value(response)?))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Mock
+    }
+
+    fn translate_model(&self, model: &str) -> Option<String> {
+        Some(model.to_string())
+    }
+
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        if let Some(response) = self.tool_call_reply(&request)? {
+            return Ok(response);
+        }
+
+        let content = self.reply(&request);
+        completion_response(
+            &format!("mock-{}", Uuid::new_v4()),
+            &request.model,
+            &content,
+            "stop",
+            None,
+        )
+    }
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream> {
+        let id = format!("mock-{}", Uuid::new_v4());
+        let content = self.reply(&request);
+
+        let mut chunks: Vec<Result<_, OpenAIError>> = content
+            .split_inclusive(' ')
+            .map(|word| {
+                stream_chunk(&id, &request.model, Some(word), None)
+                    .map_err(OpenAIError::JSONDeserialize)
+            })
+            .collect();
+        chunks.push(
+            stream_chunk(&id, &request.model, None, Some("stop"))
+                .map_err(OpenAIError::JSONDeserialize),
+        );
+
+        Ok(Box::pin(stream::iter(chunks)))
+    }
+}
@@ -0,0 +1,278 @@
+// llm/mod.rs
+
+use anyhow::{anyhow, Result};
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPart,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseStream,
+    CreateChatCompletionRequest, CreateChatCompletionResponse, CreateChatCompletionStreamResponse,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+
+pub mod anthropic;
+pub mod context;
+pub mod embeddings;
+pub mod keywords;
+pub mod mock;
+pub mod openai;
+pub mod structured;
+
+pub use anthropic::AnthropicProvider;
+pub use keywords::KeywordsProvider;
+pub use mock::MockProvider;
+pub use openai::OpenAIProvider;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProviderKind {
+    Keywords,
+    OpenAI,
+    Anthropic,
+    Mock,
+}
+
+impl ProviderKind {
+    /// Explicit routing prefix a model id can carry, e.g. "anthropic/claude-3-5-sonnet-20241022"
+    fn prefix(&self) -> &'static str {
+        match self {
+            ProviderKind::Keywords => "keywords/",
+            ProviderKind::OpenAI => "openai/",
+            ProviderKind::Anthropic => "anthropic/",
+            ProviderKind::Mock => "mock/",
+        }
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderKind::Keywords => write!(f, "keywords"),
+            ProviderKind::OpenAI => write!(f, "openai"),
+            ProviderKind::Anthropic => write!(f, "anthropic"),
+            ProviderKind::Mock => write!(f, "mock"),
+        }
+    }
+}
+
+/// A chat completion backend. Requests and responses are always in the OpenAI shape,
+/// providers that speak something else translate at the edge.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    /// The model id this provider would send upstream for `model`, or None if it can't serve it.
+    /// Used by the router to decide whether a provider is a valid failover target.
+    fn translate_model(&self, model: &str) -> Option<String>;
+
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse>;
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream>;
+}
+
+/// Routes completions to a provider per model, failing over to the next capable provider
+/// when the primary one can't start a completion.
+pub struct LlmRouter {
+    providers: HashMap<ProviderKind, Arc<dyn LlmProvider>>,
+    default: ProviderKind,
+    failover: Vec<ProviderKind>,
+}
+
+impl LlmRouter {
+    pub fn new(default_provider: Arc<dyn LlmProvider>) -> Self {
+        let default = default_provider.kind();
+        let mut providers: HashMap<ProviderKind, Arc<dyn LlmProvider>> = HashMap::new();
+        providers.insert(default, default_provider);
+        LlmRouter {
+            providers,
+            default,
+            failover: Vec::new(),
+        }
+    }
+
+    /// Register an additional provider, which is also used as a failover target in insertion order
+    pub fn with_provider(mut self, provider: Arc<dyn LlmProvider>) -> Self {
+        let kind = provider.kind();
+        self.providers.insert(kind, provider);
+        if kind != self.default && !self.failover.contains(&kind) {
+            self.failover.push(kind);
+        }
+        self
+    }
+
+    /// Build the router from the app config. LLM_PROVIDER=mock routes everything to the in-process mock.
+    pub fn from_config(app_config: &AppConfig) -> Self {
+        if app_config.llm_provider.as_deref() == Some("mock") {
+            info!("Using the mock LLM provider for all completions");
+            return LlmRouter::new(Arc::new(MockProvider::new()));
+        }
+
+        let mut router = LlmRouter::new(Arc::new(KeywordsProvider::new(
+            &app_config.keywords_api_key,
+        )));
+        if let Some(api_key) = &app_config.openai_api_key {
+            router = router.with_provider(Arc::new(OpenAIProvider::new(api_key)));
+        }
+        if let Some(api_key) = &app_config.anthropic_api_key {
+            router = router.with_provider(Arc::new(AnthropicProvider::new(api_key)));
+        }
+        router
+    }
+
+    /// Ordered list of (provider, upstream model id) candidates for a requested model
+    fn candidates(&self, model: &str) -> Vec<(Arc<dyn LlmProvider>, String)> {
+        // An explicit prefix pins the request to one provider, no failover
+        for (kind, provider) in &self.providers {
+            if let Some(stripped) = model.strip_prefix(kind.prefix()) {
+                return provider
+                    .translate_model(stripped)
+                    .map(|m| vec![(provider.clone(), m)])
+                    .unwrap_or_default();
+            }
+        }
+
+        std::iter::once(self.default)
+            .chain(self.failover.iter().copied())
+            .filter_map(|kind| self.providers.get(&kind))
+            .filter_map(|provider| {
+                provider
+                    .translate_model(model)
+                    .map(|m| (provider.clone(), m))
+            })
+            .collect()
+    }
+
+    pub async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        let mut last_error = anyhow!("No provider can serve model {}", request.model);
+        for (provider, model) in self.candidates(&request.model) {
+            let request = CreateChatCompletionRequest {
+                model,
+                ..request.clone()
+            };
+            match provider.create(request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!(
+                        "Provider {} failed chat completion: {:?}",
+                        provider.kind(),
+                        e
+                    );
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    pub async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream> {
+        let mut last_error = anyhow!("No provider can serve model {}", request.model);
+        for (provider, model) in self.candidates(&request.model) {
+            let request = CreateChatCompletionRequest {
+                model,
+                ..request.clone()
+            };
+            match provider.create_stream(request).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    warn!(
+                        "Provider {} failed to start chat completion stream: {:?}",
+                        provider.kind(),
+                        e
+                    );
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Build an OpenAI shaped completion response, for providers that don't speak OpenAI natively
+pub(crate) fn completion_response(
+    id: &str,
+    model: &str,
+    content: &str,
+    finish_reason: &str,
+    usage: Option<(u32, u32)>,
+) -> Result<CreateChatCompletionResponse> {
+    let mut response = json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": finish_reason,
+        }],
+    });
+    if let Some((prompt_tokens, completion_tokens)) = usage {
+        response["usage"] = json!({
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        });
+    }
+    Ok(serde_json::from_value(response)?)
+}
+
+/// Build an OpenAI shaped streaming chunk, for providers that don't speak OpenAI natively
+pub(crate) fn stream_chunk(
+    id: &str,
+    model: &str,
+    content: Option<&str>,
+    finish_reason: Option<&str>,
+) -> Result<CreateChatCompletionStreamResponse, serde_json::Error> {
+    serde_json::from_value(json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "delta": { "role": "assistant", "content": content },
+            "finish_reason": finish_reason,
+        }],
+    }))
+}
+
+/// Plain text of a request message, image parts are skipped
+pub(crate) fn message_text(message: &ChatCompletionRequestMessage) -> String {
+    match message {
+        ChatCompletionRequestMessage::System(system_message) => system_message.content.clone(),
+        ChatCompletionRequestMessage::User(user_message) => match &user_message.content {
+            ChatCompletionRequestUserMessageContent::Text(text) => text.clone(),
+            ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ChatCompletionRequestMessageContentPart::Text(text_part) => {
+                        Some(text_part.text.as_str())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        },
+        ChatCompletionRequestMessage::Assistant(assistant_message) => {
+            assistant_message.content.clone().unwrap_or_default()
+        }
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,66 @@
+// llm/openai.rs
+
+use anyhow::Result;
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{
+    ChatCompletionResponseStream, CreateChatCompletionRequest, CreateChatCompletionResponse,
+};
+use async_openai::Client;
+use async_trait::async_trait;
+
+use crate::llm::{LlmProvider, ProviderKind};
+
+/// Direct OpenAI, used when Keywords is down or for models pinned with an "openai/" prefix
+pub struct OpenAIProvider {
+    client: Client<OpenAIConfig>,
+}
+
+impl OpenAIProvider {
+    pub fn new(api_key: &str) -> Self {
+        OpenAIProvider {
+            client: Client::with_config(OpenAIConfig::new().with_api_key(api_key)),
+        }
+    }
+
+    /// Strip the Keywords specific fields, OpenAI rejects unknown parameters
+    fn conform(mut request: CreateChatCompletionRequest) -> CreateChatCompletionRequest {
+        request.invisibility = None;
+        request.customer_identifier = None;
+        request.thread_identifier = None;
+        request.fallback = None;
+        request
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAIProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAI
+    }
+
+    fn translate_model(&self, model: &str) -> Option<String> {
+        if model.starts_with("gpt-") || model.starts_with("o1") {
+            Some(model.to_string())
+        } else {
+            None
+        }
+    }
+
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        Ok(self.client.chat().create(Self::conform(request)).await?)
+    }
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionResponseStream> {
+        Ok(self
+            .client
+            .chat()
+            .create_stream(Self::conform(request))
+            .await?)
+    }
+}
@@ -0,0 +1,106 @@
+// llm/structured.rs
+
+use anyhow::{Context, Result};
+use async_openai::types::{
+    ChatCompletionNamedToolChoice, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestUserMessageArgs, ChatCompletionToolArgs, ChatCompletionToolChoiceOption,
+    ChatCompletionToolType, CreateChatCompletionRequestArgs, FunctionName, FunctionObjectArgs,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::llm::LlmRouter;
+
+/// A reply requested as the arguments of a forced tool call, so it arrives as JSON instead of free text
+pub trait StructuredOutput: DeserializeOwned {
+    /// Name of the tool the model is made to call
+    const TOOL_NAME: &'static str;
+    const TOOL_DESCRIPTION: &'static str;
+
+    /// JSON schema of the tool's arguments
+    fn schema() -> Value;
+
+    /// Reject replies that deserialize but can't be used
+    fn validate(&self) -> Result<()>;
+}
+
+/// Send `prompt` with `T`'s tool as the only allowed reply. Errors only if the request itself fails,
+/// a reply without a usable tool call is logged and returned as None so callers can fall back to text
+pub async fn structured_completion<T: StructuredOutput>(
+    llm: &LlmRouter,
+    model: &str,
+    prompt: &str,
+) -> Result<Option<T>> {
+    let tool = ChatCompletionToolArgs::default()
+        .r#type(ChatCompletionToolType::Function)
+        .function(
+            FunctionObjectArgs::default()
+                .name(T::TOOL_NAME)
+                .description(T::TOOL_DESCRIPTION)
+                .parameters(T::schema())
+                .build()?,
+        )
+        .build()?;
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .messages(vec![
+            ChatCompletionRequestSystemMessageArgs::default()
+                .content(format!(
+                    "Reply by calling the {} tool. Anything the instructions ask you to put in tags goes in its arguments instead.",
+                    T::TOOL_NAME
+                ))
+                .build()?
+                .into(),
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into(),
+        ])
+        .tools(vec![tool])
+        .tool_choice(ChatCompletionToolChoiceOption::Named(
+            ChatCompletionNamedToolChoice {
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionName {
+                    name: T::TOOL_NAME.to_string(),
+                },
+            },
+        ))
+        .build()?;
+
+    let response = llm.create(request).await?;
+
+    let Some(arguments) = response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.tool_calls.as_ref())
+        .and_then(|tool_calls| {
+            tool_calls
+                .iter()
+                .find(|tool_call| tool_call.function.name == T::TOOL_NAME)
+        })
+        .map(|tool_call| tool_call.function.arguments.clone())
+    else {
+        warn!("No {} tool call in the reply from {}", T::TOOL_NAME, model);
+        return Ok(None);
+    };
+
+    let output = serde_json::from_str::<T>(&arguments)
+        .context("Tool call arguments don't match the schema")
+        .and_then(|output| output.validate().map(|_| output));
+
+    match output {
+        Ok(output) => Ok(Some(output)),
+        Err(e) => {
+            warn!(
+                "Unusable {} tool call from {}: {:?}. Arguments: {}",
+                T::TOOL_NAME,
+                model,
+                e,
+                arguments
+            );
+            Ok(None)
+        }
+    }
+}
@@ -1,27 +1,26 @@
 use actix_cors::Cors;
 use actix_web::middleware::Logger;
 use actix_web::web;
-use async_openai::{config::OpenAIConfig, Client};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use config::AppConfig;
-use futures::stream::{self, StreamExt};
-use models::{Invite, Memory, User};
+use llm::embeddings::{embedding_provider_from_config, EmbeddingProvider};
+use llm::LlmRouter;
+use models::{ApiKey, Invite, LlmModel, Memory, RateLimit, SyncBus, SyncChange};
 use moka::future::Cache;
-use rand::seq::SliceRandom;
 use shuttle_actix_web::ShuttleActixWeb;
 use shuttle_runtime::SecretStore;
 use sqlx::postgres::PgPool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Semaphore;
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tracing::{debug, error, info};
 use utoipa::OpenApi;
 use utoipa_scalar::{Scalar, Servable};
 use uuid::Uuid;
 
 mod config;
+mod jobs;
+mod llm;
 mod middleware;
 mod models;
 mod prompts;
@@ -31,21 +30,37 @@ mod types;
 #[derive(Clone)]
 struct AppState {
     pool: PgPool,
-    keywords_client: Client<OpenAIConfig>,
+    llm: Arc<LlmRouter>,
+    embedder: Arc<dyn EmbeddingProvider>,
     stripe_client: stripe::Client,
     memory_cache: Cache<String, HashMap<Uuid, Memory>>,
     invite_cache: Cache<String, HashMap<Uuid, Invite>>,
+    model_cache: Cache<String, Option<LlmModel>>,
+    rate_limit_cache: Cache<String, RateLimit>,
+    role_cache: Cache<String, Vec<String>>,
+    api_key_cache: Cache<String, Option<ApiKey>>,
+    session_cache: Cache<Uuid, bool>,
+    legacy_token_cache: Cache<String, Option<DateTime<Utc>>>,
+    sync_events: SyncBus,
 }
 
 #[derive(OpenApi)]
 #[openapi(
         nest(
             (path = "/", api = routes::hello::ApiDoc),
+            (path = "/api_keys", api = routes::api_keys::ApiDoc),
             (path = "/auth", api = routes::auth::ApiDoc),
             (path = "/chats", api = routes::chat::ApiDoc),
+            (path = "/jobs", api = routes::jobs::ApiDoc),
+            (path = "/models", api = routes::llm_models::ApiDoc),
             (path = "/pay", api = routes::pay::ApiDoc),
+            (path = "/rate_limits", api = routes::rate_limits::ApiDoc),
+            (path = "/roles", api = routes::roles::ApiDoc),
             (path = "/oai", api = routes::oai::ApiDoc),
+            (path = "/search", api = routes::search::ApiDoc),
+            (path = "/share", api = routes::share::ApiDoc),
             (path = "/sync", api = routes::sync::ApiDoc),
+            (path = "/usage", api = routes::usage::ApiDoc),
         ),
         tags(
             (name = "cloak", description = "Invisibiliy cloak API, powering i.inc and related services.")
@@ -62,11 +77,8 @@ async fn main(
         pool: PgPool::connect(&app_config.db_connection_uri)
             .await
             .unwrap(),
-        keywords_client: Client::with_config(
-            OpenAIConfig::new()
-                .with_api_key(app_config.keywords_api_key.clone())
-                .with_api_base("https://api.keywordsai.co/api"),
-        ),
+        llm: Arc::new(LlmRouter::from_config(&app_config)),
+        embedder: embedding_provider_from_config(&app_config),
         stripe_client: stripe::Client::new(app_config.stripe_secret_key.clone()),
         memory_cache: Cache::builder()
             .max_capacity(1024 * 1024 * 10) // 10Mb limit
@@ -82,22 +94,50 @@ async fn main(
                 (value.len() * estimated_invite_size) as u32
             })
             .build(),
+        model_cache: Cache::builder()
+            .max_capacity(1024)
+            .time_to_live(Duration::from_secs(60)) // Pick up registry edits made on other instances
+            .build(),
+        rate_limit_cache: Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(Duration::from_secs(60)) // Pick up limit edits made on other instances
+            .build(),
+        role_cache: Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(Duration::from_secs(60)) // Pick up grants and revokes made on other instances
+            .build(),
+        api_key_cache: Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(Duration::from_secs(60)) // Bounds how long a key revoked on another instance keeps working
+            .build(),
+        session_cache: Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(Duration::from_secs(60)) // Bounds how long a session revoked on another instance keeps working
+            .build(),
+        legacy_token_cache: Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(Duration::from_secs(60)) // Bounds how long a revoke-all on another instance leaves legacy tokens working
+            .build(),
+        sync_events: SyncBus::default(),
     });
 
-    // Run a job every day at midnight to generate memories for all users
+    // Relay change notifications from every instance to this instance's /sync/stream subscribers
+    if !app_config.jobs_only {
+        tokio::spawn(SyncChange::listen(
+            app_state.pool.clone(),
+            app_state.sync_events.clone(),
+        ));
+    }
+
+    jobs::start_workers(app_state.clone(), app_config.clone());
+
+    // Queue the nightly memory run, memory decay and sync log pruning every day at midnight, the workers pick them up from the jobs table
     let scheduler = JobScheduler::new().await.unwrap();
     let app_state_clone: Arc<AppState> = app_state.clone();
-    let yesterday: chrono::prelude::DateTime<Utc> = Utc::now() - chrono::Duration::days(1);
     let job = Job::new_async("0 0 0 * * *", move |_uuid, _l| {
         let app_state: Arc<AppState> = app_state_clone.clone();
         Box::pin(async move {
-            let timeout = Duration::from_secs(3 * 60 * 60); // 3 hours
-            match tokio::time::timeout(timeout, generate_all_users_memories(app_state, yesterday))
-                .await
-            {
-                Ok(_) => info!("Job completed successfully within the time limit"),
-                Err(_) => error!("Job timed out after 3 hours"),
-            }
+            jobs::enqueue_nightly_jobs(&app_state).await;
         })
     })
     .unwrap();
@@ -107,9 +147,22 @@ async fn main(
     let openapi = ApiDoc::openapi();
 
     let config = move |cfg: &mut web::ServiceConfig| {
+        // A worker deployment only answers the health check
+        if app_config.jobs_only {
+            cfg.service(routes::hello::hello_world);
+            return;
+        }
+
         cfg.service(
             web::scope("")
                 .service(routes::hello::hello_world)
+                .service(
+                    web::scope("/api_keys")
+                        .service(routes::api_keys::create_api_key)
+                        .service(routes::api_keys::list_api_keys)
+                        .service(routes::api_keys::update_api_key)
+                        .service(routes::api_keys::revoke_api_key),
+                )
                 .service(
                     web::scope("/auth")
                         .service(routes::auth::auth_callback)
@@ -120,18 +173,47 @@ async fn main(
                         .service(routes::auth::sync_users_keywords)
                         .service(routes::auth::login)
                         .service(routes::auth::refresh_token)
+                        .service(routes::auth::rotate_refresh_token)
+                        .service(routes::auth::list_sessions)
+                        .service(routes::auth::revoke_session)
+                        .service(routes::auth::revoke_all_sessions)
                         .service(routes::auth::signup),
                 )
                 .service(
                     web::scope("/chats")
+                        .service(routes::chat::list_chats)
+                        .service(routes::chat::get_chat)
+                        .service(routes::chat::get_chat_messages)
+                        .service(routes::chat::get_chat_tree)
+                        .service(routes::chat::activate_message)
+                        .service(routes::chat::share_chat)
+                        .service(routes::chat::list_chat_shares)
+                        .service(routes::chat::revoke_chat_share)
                         .service(routes::chat::delete_chat)
                         .service(routes::chat::update_chat)
                         .service(routes::chat::autorename_chat),
                 )
+                .service(
+                    web::scope("/jobs")
+                        .service(routes::jobs::list_jobs)
+                        .service(routes::jobs::enqueue_job)
+                        .service(routes::jobs::job_stats)
+                        .service(routes::jobs::get_job)
+                        .service(routes::jobs::retry_job)
+                        .service(routes::jobs::cancel_job),
+                )
                 .service(
                     web::scope("/messages")
                         .service(routes::messages::upvote_message)
-                        .service(routes::messages::downvote_message),
+                        .service(routes::messages::downvote_message)
+                        .service(routes::messages::edit_message)
+                        .service(routes::messages::get_message_edits),
+                )
+                .service(
+                    web::scope("/models")
+                        .service(routes::llm_models::list_models)
+                        .service(routes::llm_models::upsert_model)
+                        .service(routes::llm_models::delete_model),
                 )
                 .service(
                     web::scope("/oai")
@@ -147,13 +229,49 @@ async fn main(
                         .service(routes::pay::paid)
                         .service(routes::pay::payment_success),
                 )
+                .service(
+                    web::scope("/rate_limits")
+                        .service(routes::rate_limits::list_plans)
+                        .service(routes::rate_limits::upsert_plan)
+                        .service(routes::rate_limits::get_user_rate_limit)
+                        .service(routes::rate_limits::set_user_rate_limit)
+                        .service(routes::rate_limits::delete_user_rate_limit),
+                )
+                .service(
+                    web::scope("/roles")
+                        .service(routes::roles::list_roles)
+                        .service(routes::roles::get_my_roles)
+                        .service(routes::roles::get_user_roles)
+                        .service(routes::roles::grant_role)
+                        .service(routes::roles::revoke_role),
+                )
+                .service(web::scope("/search").service(routes::search::search))
+                .service(
+                    web::scope("/share")
+                        .service(routes::share::get_share)
+                        .service(routes::share::get_share_html),
+                )
                 .service(
                     web::scope("/memories")
                         .service(routes::memory::generate_memories_from_chat_history_endpoint)
                         .service(routes::memory::create_memory)
                         .service(routes::memory::get_memories)
+                        .service(routes::memory::get_memory_settings)
+                        .service(routes::memory::update_memory_settings)
+                        .service(routes::memory::get_memory_groups)
+                        .service(routes::memory::create_memory_group)
+                        .service(routes::memory::update_memory_group)
+                        .service(routes::memory::delete_memory_group)
+                        .service(routes::memory::get_archived_memories)
+                        .service(routes::memory::export_memories)
+                        .service(routes::memory::import_memories)
                         .service(routes::memory::update_memory)
-                        .service(routes::memory::delete_memory),
+                        .service(routes::memory::delete_memory)
+                        .service(routes::memory::pin_memory)
+                        .service(routes::memory::unpin_memory)
+                        .service(routes::memory::unarchive_memory)
+                        .service(routes::memory::get_memory_history)
+                        .service(routes::memory::rollback_memory),
                 )
                 .service(web::scope("/sidekick").service(routes::sidekick::fetch_save_url))
                 .service(
@@ -162,11 +280,24 @@ async fn main(
                         .service(routes::devents::get_devent)
                         .service(routes::devents::create_devent),
                 )
-                .service(web::scope("/sync").service(routes::sync::sync_all))
+                .service(
+                    web::scope("/sync")
+                        .service(routes::sync::sync_all)
+                        .service(routes::sync::sync_changes)
+                        .service(routes::sync::sync_stream),
+                )
+                .service(
+                    web::scope("/usage")
+                        .service(routes::usage::get_usage)
+                        .service(routes::usage::get_usage_by_user)
+                        .service(routes::usage::get_parse_stats)
+                        .service(routes::usage::get_user_usage),
+                )
                 .service(web::scope("/webhook").service(routes::webhook::user_created))
                 .service(Scalar::with_url("/scalar", openapi))
                 .wrap(middleware::auth::AuthenticationMiddleware {
                     app_config: app_config.clone(),
+                    app_state: app_state.clone(),
                 })
                 .wrap(middleware::logging::LoggingMiddleware)
                 .wrap(Logger::new("%{r}a \"%r\" %s %b \"%{User-Agent}i\" %U %T"))
@@ -178,81 +309,3 @@ async fn main(
 
     Ok(config.into())
 }
-
-fn select_random_fraction(users: &[User], fraction: f64) -> Vec<User> {
-    let mut rng = rand::thread_rng();
-    let sample_size = (users.len() as f64 * fraction).ceil() as usize;
-    users
-        .choose_multiple(&mut rng, sample_size)
-        .cloned()
-        .collect()
-}
-
-async fn generate_all_users_memories(app_state: Arc<AppState>, begin_time: chrono::DateTime<Utc>) {
-    let all_users = match User::get_all(&app_state.pool).await {
-        Ok(users) => users,
-        Err(e) => {
-            debug!("Failed to get all users: {:?}", e);
-            return;
-        }
-    };
-    info!("Total users: {}", all_users.len());
-
-    let selected_users = select_random_fraction(&all_users, 0.05);
-    let selected_users_count = selected_users.len();
-    info!("Selected users: {}", selected_users_count);
-
-    let batch_size = 500;
-    let semaphore = Arc::new(Semaphore::new(batch_size));
-
-    let successful_futures = stream::iter(selected_users)
-        .chunks(batch_size)
-        .flat_map(|chunk| {
-            let app_state = app_state.clone();
-            let semaphore = semaphore.clone();
-            stream::iter(chunk).map(move |user| {
-                let app_state = app_state.clone();
-                let user_id = user.id;
-                let semaphore = semaphore.clone();
-                async move {
-                    let response = routes::memory::generate_memories_from_chat_history(
-                        &web::Data::new(app_state),
-                        Some(semaphore),
-                        &user_id,
-                        None,
-                        None,
-                        Some((begin_time, Utc::now())),
-                    )
-                    .await;
-                    match response {
-                        Ok(memories) => {
-                            info!(
-                                "Memories generated successfully for user: {}. Count: {}",
-                                user_id,
-                                memories.len()
-                            );
-                            Some(user_id)
-                        }
-                        Err(e) => {
-                            error!("Error generating memories for user {}: {:?}", user_id, e);
-                            None
-                        }
-                    }
-                }
-            })
-        })
-        .buffer_unordered(batch_size)
-        .filter_map(|result| async move { result })
-        .collect::<Vec<_>>()
-        .await;
-
-    let processed_users_count = successful_futures.len();
-
-    info!("Memory generation complete. Breakdown:");
-    info!("Selected users: {}", selected_users_count);
-    info!("Processed users: {}", processed_users_count);
-    info!(
-        "Not processed users: {}",
-        selected_users_count - processed_users_count
-    );
-}
@@ -1,57 +1,46 @@
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::ErrorUnauthorized,
+    error::{ErrorForbidden, ErrorUnauthorized},
     http::header::AUTHORIZATION,
     Error, FromRequest, HttpMessage, HttpRequest,
 };
+use chrono::Utc;
 use futures_util::future::LocalBoxFuture;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use std::{
     future::{ready, Ready},
+    rc::Rc,
     sync::Arc,
 };
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
 
-use crate::{types::Claims, AppConfig};
+use crate::models::api_key::{ApiKey, API_KEY_PREFIX};
+use crate::models::Session;
+use crate::{types::Claims, AppConfig, AppState};
+
+/// How long after issue a token without a session is accepted
+const LEGACY_TOKEN_MAX_AGE_SECS: usize = 14 * 24 * 3600;
+
+/// How a request was authenticated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// A JWT of a revocable session
+    Session,
+    /// A JWT issued before sessions existed, without a jti
+    LegacyJwt,
+    /// A personal API key
+    ApiKey,
+}
 
 #[derive(Clone)]
 pub struct AuthenticatedUser {
     pub user_id: String,
+    /// The session behind the JWT, None for API keys and tokens issued before sessions existed
+    pub session_id: Option<Uuid>,
+    pub method: AuthMethod,
 }
 
-impl AuthenticatedUser {
-    pub fn is_admin(&self) -> bool {
-        matches!(
-            self.user_id.as_str(),
-            "user_01HRBJ8FVP3JT28DEWXN6JPKF5" | // Sulaiman skghori
-            "user_01HY5EW9Z5XVE34GZXKH4NC2Y1" |
-            "user_01J12R88378H1Z5R3JCGEPJ6RA"
-        )
-    }
-
-    pub fn is_rate_limited(&self) -> bool {
-        matches!(
-            self.user_id.as_str(),
-            "user_01J21MH9Q2ZJZC1C8R4ZHFJBNB" | // Hamzeh Hammad
-            "user_01J04BC3DXJ7PVGAW7VS30DG91" | // Vasyl Larin
-            "user_01HTYMBHYK14M12HHK34A2R8RC" | // Dmitriy Osipov
-            "user_01HYNY2S52Q5CQ6NWPP9D8D4AA" | // Francesco Simone Mensa
-            "user_01HX6WMNT229K6V7CFPD7VRNV8" | // Sergi C
-            "user_01HX9N7GH5QRFTGMYVWNPHCYMM" | // David Spokes
-            "user_01J2MNK392KWQAJVX20CJBG5E7" | // Simon Kirchebner
-            "user_01HZEP4TFR49AG913DPQJ6MASW" |
-            "user_01HS55ATS5N0D9PEXY45TZDGXN" |
-            "user_01HVR20FDCZH3QX8WPYHR45MX7" |
-            "user_01HTJTP5X5PAH2XTH6A69Q3G08" |
-            "user_01J03D3QK27ZD0B2E9A60X6E2R" |
-            "user_01HRGR7RB2T8S04YXDH9YXQ31T" |
-            "user_01J0AVNGZW118RXB7JSGAVZSFM" |
-            "user_01HRDV9MWADXWSSNDE1HSASN8P" |
-            "user_01J03D570TSXTNZ3FJGZFZ8VHA" |
-            "user_01HRD1QJJTGDH2S2209N3WF9JX"
-        )
-    }
-}
 // This is the trait that actix-web uses to extract the `AuthenticatedUser` from the request
 // This is how we can use `AuthenticatedUser` as a parameter in our route handlers
 // It automatically returns a 401 Unauthorized if the user is not authenticated
@@ -70,6 +59,7 @@ impl FromRequest for AuthenticatedUser {
 
 pub struct AuthenticationMiddleware {
     pub app_config: Arc<AppConfig>,
+    pub app_state: Arc<AppState>,
 }
 
 // Middleware factory is `Transform` trait
@@ -89,15 +79,17 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthenticationMiddlewareService {
-            service,
+            service: Rc::new(service),
             app_config: self.app_config.clone(),
+            app_state: self.app_state.clone(),
         }))
     }
 }
 
 pub struct AuthenticationMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
     app_config: Arc<AppConfig>,
+    app_state: Arc<AppState>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthenticationMiddlewareService<S>
@@ -113,43 +105,158 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        // Here's where we extract JWT from the request, validate it, and insert the user_id into the request extensions
+        // Here's where we extract a JWT or API key from the request, validate it, and insert the user_id into the request extensions
         let app_config = self.app_config.clone();
+        let app_state = self.app_state.clone();
+        let service = Rc::clone(&self.service);
 
         let auth_header = req
             .headers()
             .get(AUTHORIZATION)
             .and_then(|value| value.to_str().ok())
             .filter(|value| value.starts_with("Bearer "))
-            .map(|value| &value["Bearer ".len()..]);
+            .map(|value| value["Bearer ".len()..].to_string());
 
-        match auth_header {
-            Some(token) => {
-                let decoding_key = DecodingKey::from_secret(app_config.jwt_secret.as_ref());
+        Box::pin(async move {
+            match auth_header {
+                // Personal API keys, resolved to the same AuthenticatedUser as a JWT
+                Some(token) if token.starts_with(API_KEY_PREFIX) => {
+                    match ApiKey::authenticate(&app_state.pool, &token, &app_state.api_key_cache)
+                        .await
+                    {
+                        Ok(Some(api_key)) => {
+                            if !api_key.allows(req.method(), req.path()) {
+                                debug!("API key {} not scoped for {}", api_key.id, req.path());
+                                return Err(ErrorForbidden(
+                                    "This API key's scopes don't allow this endpoint",
+                                ));
+                            }
 
-                match decode::<Claims>(token, &decoding_key, &Validation::default()) {
-                    Ok(token_data) => {
-                        let claims = token_data.claims;
-                        let user_id = claims.sub;
+                            if api_key.touch_due() {
+                                api_key.mark_used_in_cache(&app_state.api_key_cache).await;
+                                let pool = app_state.pool.clone();
+                                let api_key_id = api_key.id;
+                                actix_web::rt::spawn(async move {
+                                    if let Err(e) = ApiKey::touch(&pool, api_key_id).await {
+                                        error!("Failed to update API key last used: {:?}", e);
+                                    }
+                                });
+                            }
 
-                        debug!("Authenticated user: {}", &user_id);
-                        req.extensions_mut().insert(AuthenticatedUser { user_id });
+                            debug!("Authenticated user {} with API key", &api_key.user_id);
+                            req.extensions_mut().insert(AuthenticatedUser {
+                                user_id: api_key.user_id,
+                                session_id: None,
+                                method: AuthMethod::ApiKey,
+                            });
+                        }
+                        Ok(None) => {
+                            warn!("Invalid or revoked API key");
+                        }
+                        Err(e) => {
+                            error!("Failed to look up API key: {:?}", e);
+                        }
                     }
-                    Err(e) => {
-                        warn!("Invalid token: {:?}", e);
+                }
+                Some(token) => {
+                    if let Some(authenticated_user) =
+                        authenticate_jwt(&token, &app_config, &app_state).await
+                    {
+                        req.extensions_mut().insert(authenticated_user);
                     }
                 }
+                None => {
+                    debug!("No Authorization header found.");
+                }
+            };
+
+            let res = service.call(req).await?;
+            Ok(res)
+        })
+    }
+}
+
+/// Decode a JWT and, for tokens issued with a session, check the session hasn't been revoked
+async fn authenticate_jwt(
+    token: &str,
+    app_config: &AppConfig,
+    app_state: &AppState,
+) -> Option<AuthenticatedUser> {
+    let decoding_key = DecodingKey::from_secret(app_config.jwt_secret.as_ref());
+
+    let claims = match decode::<Claims>(token, &decoding_key, &Validation::default()) {
+        Ok(token_data) => token_data.claims,
+        Err(e) => {
+            warn!("Invalid token: {:?}", e);
+            return None;
+        }
+    };
+
+    // Legacy tokens without a session can't be revoked one by one, so they're accepted for a shorter
+    // window than the 5 weeks they were signed for, and not at all if issued before the user last
+    // revoked all sessions. Clients swap them for a session via GET /auth/token/refresh
+    let Some(jti) = claims.jti else {
+        let age_secs = (Utc::now().timestamp() as usize).saturating_sub(claims.iat);
+        if age_secs > LEGACY_TOKEN_MAX_AGE_SECS {
+            warn!("Legacy token for user {} is too old", claims.sub);
+            return None;
+        }
+        match Session::legacy_tokens_valid_after(
+            &app_state.pool,
+            &claims.sub,
+            &app_state.legacy_token_cache,
+        )
+        .await
+        {
+            Ok(Some(valid_after)) if (claims.iat as i64) < valid_after.timestamp() => {
+                warn!("Legacy token for user {} was revoked", claims.sub);
+                return None;
             }
-            None => {
-                debug!("No Authorization header found.");
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to look up legacy token cutoff: {:?}", e);
+                return None;
             }
-        };
+        }
+        debug!("Authenticated user: {}", &claims.sub);
+        return Some(AuthenticatedUser {
+            user_id: claims.sub,
+            session_id: None,
+            method: AuthMethod::LegacyJwt,
+        });
+    };
 
-        let fut = self.service.call(req);
+    let session_id = match Uuid::parse_str(&jti) {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            warn!("Invalid token jti: {:?}", e);
+            return None;
+        }
+    };
 
-        Box::pin(async move {
-            let res = fut.await?;
-            Ok(res)
-        })
+    match Session::is_active(&app_state.pool, session_id, &app_state.session_cache).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Token for revoked session {}", session_id);
+            return None;
+        }
+        Err(e) => {
+            error!("Failed to look up session: {:?}", e);
+            return None;
+        }
     }
+
+    let pool = app_state.pool.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = Session::touch(&pool, session_id).await {
+            error!("Failed to update session last seen: {:?}", e);
+        }
+    });
+
+    debug!("Authenticated user: {}", &claims.sub);
+    Some(AuthenticatedUser {
+        user_id: claims.sub,
+        session_id: Some(session_id),
+        method: AuthMethod::Session,
+    })
 }
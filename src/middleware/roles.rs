@@ -0,0 +1,131 @@
+use actix_web::{
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    web, Error, FromRequest, HttpMessage, HttpRequest,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{marker::PhantomData, ops::Deref, sync::Arc};
+use tracing::error;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::role::{UserRole, ALL_PERMISSIONS};
+use crate::AppState;
+
+/// A permission a handler can require, see `RequirePermission`. Roles carry permissions in
+/// roles.permissions, "*" grants all of them
+pub trait PermissionName {
+    const NAME: &'static str;
+}
+
+/// List users
+pub struct UsersRead;
+/// Sync users from WorkOS and to Keywords
+pub struct UsersSync;
+/// Usage and LLM parse stats
+pub struct UsageRead;
+/// Read devents for any session
+pub struct DeventsRead;
+/// Inspect, queue, retry and cancel background jobs
+pub struct JobsManage;
+/// Edit the model routing registry
+pub struct ModelsManage;
+/// Edit rate limit plans and per-user limits
+pub struct RateLimitsManage;
+/// List roles and grant or revoke them
+pub struct RolesManage;
+/// Generate memories for any user
+pub struct MemoriesManage;
+
+impl PermissionName for UsersRead {
+    const NAME: &'static str = "users:read";
+}
+
+impl PermissionName for UsersSync {
+    const NAME: &'static str = "users:sync";
+}
+
+impl PermissionName for UsageRead {
+    const NAME: &'static str = "usage:read";
+}
+
+impl PermissionName for DeventsRead {
+    const NAME: &'static str = "devents:read";
+}
+
+impl PermissionName for JobsManage {
+    const NAME: &'static str = "jobs:manage";
+}
+
+impl PermissionName for ModelsManage {
+    const NAME: &'static str = "models:manage";
+}
+
+impl PermissionName for RateLimitsManage {
+    const NAME: &'static str = "rate_limits:manage";
+}
+
+impl PermissionName for RolesManage {
+    const NAME: &'static str = "roles:manage";
+}
+
+impl PermissionName for MemoriesManage {
+    const NAME: &'static str = "memories:manage";
+}
+
+/// An authenticated user whose roles carry permission `P`.
+/// Use it in place of `AuthenticatedUser` and the handler returns 401/403 before it runs
+pub struct RequirePermission<P: PermissionName> {
+    pub user: AuthenticatedUser,
+    _permission: PhantomData<P>,
+}
+
+impl<P: PermissionName> Deref for RequirePermission<P> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl<P: PermissionName> FromRequest for RequirePermission<P> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = req.extensions().get::<AuthenticatedUser>().cloned();
+        let app_state = req.app_data::<web::Data<Arc<AppState>>>().cloned();
+
+        Box::pin(async move {
+            let user = user.ok_or_else(|| ErrorUnauthorized("User not authenticated"))?;
+            let app_state = app_state.ok_or_else(|| {
+                error!("AppState missing from request");
+                ErrorInternalServerError("AppState missing from request")
+            })?;
+
+            let permissions =
+                UserRole::permissions(&app_state.pool, &user.user_id, &app_state.role_cache)
+                    .await
+                    .map_err(|e| {
+                        error!(
+                            "Failed to get permissions for user {}: {:?}",
+                            user.user_id, e
+                        );
+                        ErrorInternalServerError(e)
+                    })?;
+
+            if permissions
+                .iter()
+                .any(|permission| permission == P::NAME || permission == ALL_PERMISSIONS)
+            {
+                Ok(RequirePermission {
+                    user,
+                    _permission: PhantomData,
+                })
+            } else {
+                Err(ErrorForbidden(format!(
+                    "Requires the {} permission",
+                    P::NAME
+                )))
+            }
+        })
+    }
+}
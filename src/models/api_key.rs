@@ -0,0 +1,260 @@
+// models/api_key.rs
+
+use actix_web::http::Method;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use moka::future::Cache;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::str::FromStr;
+use tracing::debug;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Every key starts with this, which is how the auth middleware tells keys from JWTs
+pub const API_KEY_PREFIX: &str = "inv_";
+const API_KEY_RANDOM_LEN: usize = 40;
+/// How much of the key is stored in the clear for display
+const DISPLAY_PREFIX_LEN: usize = 12;
+/// How often last_used_at is written for a key in use
+const TOUCH_INTERVAL_SECS: i64 = 60;
+/// Keys can't manage keys, so a leaked key can't be used to mint new ones
+const KEY_MANAGEMENT_PATH: &str = "/api_keys";
+/// Nor sessions and tokens, so a key can't be turned into a login that outlives it
+const AUTH_PATH: &str = "/auth";
+
+/// What an API key may be used for. A key without scopes has full access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ApiKeyScope {
+    #[serde(rename = "chat")]
+    Chat,
+    #[serde(rename = "memories:read")]
+    MemoriesRead,
+    #[serde(rename = "memories:write")]
+    MemoriesWrite,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Chat => "chat",
+            ApiKeyScope::MemoriesRead => "memories:read",
+            ApiKeyScope::MemoriesWrite => "memories:write",
+        }
+    }
+
+    /// Whether the scope covers a request, by method and path
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        match self {
+            ApiKeyScope::Chat => path.starts_with("/oai/"),
+            ApiKeyScope::MemoriesRead => *method == Method::GET && path.starts_with("/memories"),
+            ApiKeyScope::MemoriesWrite => path.starts_with("/memories"),
+        }
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "chat" => Ok(ApiKeyScope::Chat),
+            "memories:read" => Ok(ApiKeyScope::MemoriesRead),
+            "memories:write" => Ok(ApiKeyScope::MemoriesWrite),
+            _ => Err(anyhow::anyhow!("Unknown API key scope: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    fn generate() -> String {
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(API_KEY_RANDOM_LEN)
+            .map(char::from)
+            .collect();
+        format!("{}{}", API_KEY_PREFIX, random)
+    }
+
+    pub fn hash(key: &str) -> String {
+        hex::encode(Sha256::digest(key.as_bytes()))
+    }
+
+    /// Whether the key's scopes cover a request. Unscoped keys allow everything but managing keys
+    /// and anything under /auth
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        if [KEY_MANAGEMENT_PATH, AUTH_PATH]
+            .iter()
+            .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)))
+        {
+            return false;
+        }
+        self.scopes.is_empty()
+            || self
+                .scopes
+                .iter()
+                .filter_map(|scope| ApiKeyScope::from_str(scope).ok())
+                .any(|scope| scope.allows(method, path))
+    }
+
+    /// Create a key for a user. Returns the plaintext key, which is never stored and can't be shown again
+    pub async fn create(
+        pool: &PgPool,
+        user_id: &str,
+        name: &str,
+        scopes: &[ApiKeyScope],
+    ) -> Result<(Self, String)> {
+        let key = Self::generate();
+        let scopes: Vec<String> = scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect();
+
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            INSERT INTO api_keys (id, user_id, name, prefix, key_hash, scopes)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(&key[..DISPLAY_PREFIX_LEN])
+        .bind(Self::hash(&key))
+        .bind(&scopes)
+        .fetch_one(pool)
+        .await?;
+
+        debug!("API key created: {:?}", api_key);
+        Ok((api_key, key))
+    }
+
+    pub async fn get_all_for_user(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let api_keys = sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(api_keys)
+    }
+
+    /// Rename one of the user's keys, None if they have no such key
+    pub async fn rename(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: &str,
+        name: &str,
+    ) -> Result<Option<Self>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "UPDATE api_keys SET name = $3 WHERE id = $1 AND user_id = $2 RETURNING *",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    /// Revoke one of the user's keys, None if they have no such key
+    pub async fn revoke(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: &str,
+        api_key_cache: &Cache<String, Option<ApiKey>>,
+    ) -> Result<Option<Self>> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND user_id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(api_key) = &api_key {
+            api_key_cache.invalidate(&api_key.key_hash).await;
+            debug!("API key revoked: {:?}", api_key);
+        }
+        Ok(api_key)
+    }
+
+    /// Look up a live key from its plaintext, through the cache
+    pub async fn authenticate(
+        pool: &PgPool,
+        key: &str,
+        api_key_cache: &Cache<String, Option<ApiKey>>,
+    ) -> Result<Option<Self>> {
+        let key_hash = Self::hash(key);
+        if let Some(cached) = api_key_cache.get(&key_hash).await {
+            return Ok(cached);
+        }
+
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(&key_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        api_key_cache.insert(key_hash, api_key.clone()).await;
+
+        Ok(api_key)
+    }
+
+    /// Whether the key's last use is old enough to record again. Checked against the cached key,
+    /// so busy keys don't spawn a write on every request
+    pub fn touch_due(&self) -> bool {
+        self.last_used_at.is_none_or(|last_used_at| {
+            Utc::now() - last_used_at > Duration::seconds(TOUCH_INTERVAL_SECS)
+        })
+    }
+
+    /// Mark the cached key used now, so this instance skips the write until it's due again
+    pub async fn mark_used_in_cache(&self, api_key_cache: &Cache<String, Option<ApiKey>>) {
+        let mut api_key = self.clone();
+        api_key.last_used_at = Some(Utc::now());
+        api_key_cache
+            .insert(api_key.key_hash.clone(), Some(api_key))
+            .await;
+    }
+
+    /// Record that the key was used, at most once a minute across instances
+    pub async fn touch(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE api_keys SET last_used_at = NOW()
+            WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -6,6 +6,8 @@ use tracing::debug;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::models::PageCursor;
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Chat {
     pub id: Uuid,
@@ -123,6 +125,86 @@ impl Chat {
         Ok(chat)
     }
 
+    /// A user's chat by id, None if it doesn't exist or was deleted
+    pub async fn get(pool: &PgPool, chat_id: Uuid, user_id: &str) -> Result<Option<Self>> {
+        let chat = sqlx::query_as::<_, Chat>(
+            "SELECT * FROM chats WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL",
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(chat)
+    }
+
+    /// A user's chat followed by every chat branched off its messages, and off theirs, oldest first.
+    /// Empty if the chat doesn't exist or was deleted
+    pub async fn get_with_branches(
+        pool: &PgPool,
+        chat_id: Uuid,
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let chats = sqlx::query_as::<_, Chat>(
+            r#"
+            WITH RECURSIVE family AS (
+                SELECT *, 0 AS depth FROM chats
+                WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL
+                UNION
+                SELECT c.*, family.depth + 1 FROM chats c
+                JOIN messages m ON m.id = c.parent_message_id
+                JOIN family ON family.id = m.chat_id
+                WHERE c.user_id = $2 AND c.deleted_at IS NULL
+            )
+            SELECT * FROM family
+            ORDER BY depth, created_at, id
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(chats)
+    }
+
+    /// A page of a user's chats, most recently updated first, starting after `after`.
+    /// Returns the cursor of the next page if there is one
+    pub async fn list(
+        pool: &PgPool,
+        user_id: &str,
+        after: Option<PageCursor>,
+        limit: i64,
+    ) -> Result<(Vec<Self>, Option<PageCursor>)> {
+        let mut chats = sqlx::query_as::<_, Chat>(
+            r#"
+            SELECT * FROM chats
+            WHERE user_id = $1 AND deleted_at IS NULL
+            AND ($2::TIMESTAMPTZ IS NULL OR (updated_at, id) < ($2, $3))
+            ORDER BY updated_at DESC, id DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(pool)
+        .await?;
+
+        let next_cursor = if chats.len() as i64 > limit {
+            chats.truncate(limit as usize);
+            chats.last().map(|chat| PageCursor {
+                at: chat.updated_at,
+                id: chat.id,
+            })
+        } else {
+            None
+        };
+
+        Ok((chats, next_cursor))
+    }
+
     /// Updates the name of the chat
     pub async fn update_name(
         pool: &PgPool,
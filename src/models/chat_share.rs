@@ -0,0 +1,207 @@
+// models/chat_share.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::debug;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::message::Role;
+use crate::models::{Chat, File, Message};
+
+/// Long enough that share links can't be guessed
+const SHARE_TOKEN_LEN: usize = 32;
+
+/// A chat as shown through a share link. Only what the owner saw in the chat, nothing identifying them
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharedChat {
+    pub name: String,
+    /// The active path up to the shared message, oldest first
+    pub messages: Vec<SharedMessage>,
+    pub shared_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharedMessage {
+    pub role: Role,
+    pub text: String,
+    pub model_id: Option<String>,
+    /// URLs of the images attached to the message that are shown to the user
+    pub images: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ChatShare {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub user_id: String,
+    /// Public link is /share/{token}
+    pub token: String,
+    pub up_to_message_id: Uuid,
+    #[serde(skip_serializing)]
+    #[schema(value_type = Object)]
+    pub snapshot: serde_json::Value,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ChatShare {
+    fn generate_token() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SHARE_TOKEN_LEN)
+            .map(char::from)
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> Result<SharedChat> {
+        Ok(serde_json::from_value(self.snapshot.clone())?)
+    }
+
+    /// Share a chat as it is now, up to `up_to_message_id` or its latest active message, following
+    /// the path that leads to that message. None if the message isn't in the chat or it's empty
+    pub async fn create(
+        pool: &PgPool,
+        chat: &Chat,
+        up_to_message_id: Option<Uuid>,
+    ) -> Result<Option<Self>> {
+        let messages = sqlx::query_as::<_, Message>(
+            r#"
+            WITH RECURSIVE path AS (
+                SELECT *, 0 AS depth FROM messages
+                WHERE id = COALESCE($1, (
+                    SELECT id FROM messages
+                    WHERE chat_id = $2 AND regenerated = false
+                    ORDER BY created_at DESC, id DESC
+                    LIMIT 1
+                ))
+                AND chat_id = $2 AND user_id = $3
+                UNION ALL
+                SELECT m.*, path.depth + 1 FROM messages m
+                JOIN path ON m.id = path.parent_id
+                WHERE m.chat_id = $2
+            )
+            SELECT * FROM path
+            ORDER BY depth DESC
+            "#,
+        )
+        .bind(up_to_message_id)
+        .bind(chat.id)
+        .bind(&chat.user_id)
+        .fetch_all(pool)
+        .await?;
+        let Some(last) = messages.last() else {
+            return Ok(None);
+        };
+        let up_to_message_id = last.id;
+
+        let message_ids: Vec<Uuid> = messages.iter().map(|message| message.id).collect();
+        let files = File::get_for_messages(pool, &message_ids, &chat.user_id).await?;
+        let shared_at = Utc::now();
+        let snapshot = SharedChat {
+            name: chat.name.clone(),
+            messages: messages
+                .into_iter()
+                .map(|message| SharedMessage {
+                    images: files
+                        .iter()
+                        .filter(|file| file.message_id == message.id && file.show_to_user)
+                        .filter_map(|file| file.url.clone())
+                        .collect(),
+                    role: message.role,
+                    text: message.text,
+                    model_id: message.model_id,
+                    created_at: message.created_at,
+                })
+                .collect(),
+            shared_at,
+        };
+
+        let share = sqlx::query_as::<_, ChatShare>(
+            r#"
+            INSERT INTO chat_shares (id, chat_id, user_id, token, up_to_message_id, snapshot, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(chat.id)
+        .bind(&chat.user_id)
+        .bind(Self::generate_token())
+        .bind(up_to_message_id)
+        .bind(serde_json::to_value(&snapshot)?)
+        .bind(shared_at)
+        .fetch_one(pool)
+        .await?;
+
+        debug!("Chat {} shared as {}", chat.id, share.id);
+        Ok(Some(share))
+    }
+
+    /// Shares of a user's chat, newest first, including revoked ones
+    pub async fn get_all_for_chat(
+        pool: &PgPool,
+        chat_id: Uuid,
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let shares = sqlx::query_as::<_, ChatShare>(
+            r#"
+            SELECT * FROM chat_shares
+            WHERE chat_id = $1 AND user_id = $2
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(shares)
+    }
+
+    /// A live share by its token. None once revoked or if the chat was deleted
+    pub async fn get_by_token(pool: &PgPool, token: &str) -> Result<Option<Self>> {
+        let share = sqlx::query_as::<_, ChatShare>(
+            r#"
+            SELECT s.* FROM chat_shares s
+            JOIN chats c ON c.id = s.chat_id
+            WHERE s.token = $1 AND s.revoked_at IS NULL AND c.deleted_at IS NULL
+            "#,
+        )
+        .bind(token)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Revoke one of the user's shares of a chat, None if there's no such share
+    pub async fn revoke(
+        pool: &PgPool,
+        id: Uuid,
+        chat_id: Uuid,
+        user_id: &str,
+    ) -> Result<Option<Self>> {
+        let share = sqlx::query_as::<_, ChatShare>(
+            r#"
+            UPDATE chat_shares SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND chat_id = $2 AND user_id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(share) = &share {
+            debug!("Chat share revoked: {}", share.id);
+        }
+        Ok(share)
+    }
+}
@@ -1,6 +1,7 @@
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, Type};
+use sqlx::{FromRow, PgPool, Type};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
@@ -46,6 +47,27 @@ impl File {
             ..Default::default()
         }
     }
+
+    /// Files attached to any of the given messages
+    pub async fn get_for_messages(
+        pool: &PgPool,
+        message_ids: &[Uuid],
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let files = sqlx::query_as::<_, File>(
+            r#"
+            SELECT * FROM files
+            WHERE message_id = ANY($1) AND user_id = $2
+            ORDER BY created_at
+            "#,
+        )
+        .bind(message_ids)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(files)
+    }
 }
 
 impl Default for File {
@@ -0,0 +1,399 @@
+// models/job.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Type};
+use std::fmt;
+use tracing::debug;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::memory_revision::MemoryAuthor;
+use crate::types::MemoryExport;
+
+/// Backoff after the first failure, doubled on every retry
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 60 * 60;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Type, ToSchema, PartialEq, Eq)]
+#[sqlx(type_name = "job_status_enum", rename_all = "lowercase")] // SQL value name
+#[serde(rename_all = "lowercase")] // JSON value name
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Succeeded => write!(f, "succeeded"),
+            JobStatus::Failed => write!(f, "failed"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// What a job does, stored as the job's payload with the variant name as `kind`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobPayload {
+    /// Queue memory generation for a random sample of users, covering their messages since `begin`
+    NightlyMemories {
+        begin: DateTime<Utc>,
+    },
+    /// Generate memories from a user's messages since `begin`, or from their whole history without it
+    GenerateMemories {
+        user_id: String,
+        begin: Option<DateTime<Utc>>,
+        /// Defaults to when the job runs, so a queued job also covers messages sent while it waited
+        end: Option<DateTime<Utc>>,
+        max_samples: Option<u32>,
+        samples_per_query: Option<u32>,
+        author: MemoryAuthor,
+    },
+    AutorenameChat {
+        user_id: String,
+        chat_id: Uuid,
+    },
+    SyncUsersWorkos,
+    SyncUsersKeywords,
+    /// Lower the confidence of unconfirmed memories and archive the stale ones
+    DecayMemories,
+    /// Delete sync changes older than the retention window
+    PruneSyncChanges,
+    /// Import memories from an export, merging them into the user's through the increment prompt when `merge` is set
+    ImportMemories {
+        user_id: String,
+        export: MemoryExport,
+        merge: bool,
+    },
+}
+
+impl JobPayload {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobPayload::NightlyMemories { .. } => "nightly_memories",
+            JobPayload::GenerateMemories { .. } => "generate_memories",
+            JobPayload::AutorenameChat { .. } => "autorename_chat",
+            JobPayload::SyncUsersWorkos => "sync_users_workos",
+            JobPayload::SyncUsersKeywords => "sync_users_keywords",
+            JobPayload::DecayMemories => "decay_memories",
+            JobPayload::PruneSyncChanges => "prune_sync_changes",
+            JobPayload::ImportMemories { .. } => "import_memories",
+        }
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            JobPayload::GenerateMemories { user_id, .. }
+            | JobPayload::AutorenameChat { user_id, .. }
+            | JobPayload::ImportMemories { user_id, .. } => Some(user_id),
+            JobPayload::NightlyMemories { .. }
+            | JobPayload::SyncUsersWorkos
+            | JobPayload::SyncUsersKeywords
+            | JobPayload::DecayMemories
+            | JobPayload::PruneSyncChanges => None,
+        }
+    }
+
+    /// Jobs with the same key collapse into one while queued and never run concurrently.
+    /// Memory generation is keyed per user and author, so a queued real-time run doesn't swallow the nightly one.
+    /// Imports are keyed per user, a second one is refused rather than collapsed while the first is queued.
+    /// The nightly run is keyed by the day it starts from and queued once per day, whatever its status
+    pub fn dedupe_key(&self) -> String {
+        match self {
+            JobPayload::NightlyMemories { begin } => {
+                format!("{}:{}", self.kind(), begin.date_naive())
+            }
+            JobPayload::GenerateMemories {
+                user_id, author, ..
+            } => format!("{}:{}:{}", self.kind(), user_id, author),
+            JobPayload::AutorenameChat { chat_id, .. } => format!("{}:{}", self.kind(), chat_id),
+            JobPayload::ImportMemories { user_id, .. } => format!("{}:{}", self.kind(), user_id),
+            JobPayload::SyncUsersWorkos
+            | JobPayload::SyncUsersKeywords
+            | JobPayload::DecayMemories
+            | JobPayload::PruneSyncChanges => self.kind().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    pub user_id: Option<String>,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub dedupe_key: Option<String>,
+    pub locked_by: Option<String>,
+    pub locked_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Number of jobs of a kind in a status
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct JobCount {
+    pub kind: String,
+    pub status: JobStatus,
+    pub count: i64,
+}
+
+impl Job {
+    pub fn payload(&self) -> Result<JobPayload> {
+        Ok(serde_json::from_value(self.payload.clone())?)
+    }
+
+    /// How long to wait before retrying after the given attempt failed
+    fn backoff(attempts: i32) -> Duration {
+        let exponent = attempts.clamp(1, 16) as u32 - 1;
+        Duration::seconds((BASE_BACKOFF_SECS * 2_i64.pow(exponent)).min(MAX_BACKOFF_SECS))
+    }
+
+    /// Queue a job, to run now or at `run_at`. None if a job with the same dedupe key is already queued,
+    /// or for the nightly run, was queued for that day at all
+    pub async fn enqueue(
+        pool: &PgPool,
+        payload: &JobPayload,
+        run_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Self>> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            INSERT INTO jobs (id, kind, user_id, payload, run_at, dedupe_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(payload.kind())
+        .bind(payload.user_id())
+        .bind(serde_json::to_value(payload)?)
+        .bind(run_at.unwrap_or_else(Utc::now))
+        .bind(payload.dedupe_key())
+        .fetch_optional(pool)
+        .await?;
+
+        match &job {
+            Some(job) => debug!("Job enqueued: {:?}", job),
+            None => debug!("Job {} already queued", payload.dedupe_key()),
+        }
+        Ok(job)
+    }
+
+    /// Claim the next due job for a worker. SKIP LOCKED lets concurrent workers claim different jobs
+    /// without waiting on each other, and jobs whose dedupe key is already running are left queued
+    pub async fn claim(pool: &PgPool, worker_id: &str) -> Result<Option<Self>> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'running', attempts = attempts + 1, locked_by = $1, locked_at = NOW()
+            WHERE id = (
+                SELECT id FROM jobs queued
+                WHERE queued.status = 'queued'
+                AND queued.run_at <= NOW()
+                AND NOT EXISTS (
+                    SELECT 1 FROM jobs running
+                    WHERE running.status = 'running' AND running.dedupe_key = queued.dedupe_key
+                )
+                ORDER BY queued.run_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(worker_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn complete(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'succeeded', finished_at = NOW(), locked_by = NULL, locked_at = NULL
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt. The job is queued again after a backoff until it runs out of attempts,
+    /// or fails outright if a newer job with its dedupe key is already queued to take its place
+    pub async fn fail(pool: &PgPool, job: &Job, error: &str) -> Result<JobStatus> {
+        let status = sqlx::query_scalar::<_, JobStatus>(
+            r#"
+            UPDATE jobs
+            SET status = next.status,
+                finished_at = CASE WHEN next.status = 'failed' THEN NOW() END,
+                run_at = $2,
+                last_error = $3,
+                locked_by = NULL,
+                locked_at = NULL
+            FROM (
+                SELECT id, CASE
+                    WHEN attempts < max_attempts AND NOT EXISTS (
+                        SELECT 1 FROM jobs queued
+                        WHERE queued.status = 'queued' AND queued.dedupe_key = failed.dedupe_key
+                    ) THEN 'queued'::job_status_enum
+                    ELSE 'failed'::job_status_enum
+                END AS status
+                FROM jobs failed
+                WHERE failed.id = $1
+            ) next
+            WHERE jobs.id = next.id
+            RETURNING jobs.status
+            "#,
+        )
+        .bind(job.id)
+        .bind(Utc::now() + Self::backoff(job.attempts))
+        .bind(error)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(status)
+    }
+
+    /// Put back jobs whose worker died mid-run, e.g. in a deploy. Returns how many were recovered
+    pub async fn requeue_stale(pool: &PgPool, timeout: Duration) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = next.status,
+                finished_at = CASE WHEN next.status = 'failed' THEN NOW() END,
+                run_at = NOW(),
+                last_error = 'Worker ' || COALESCE(jobs.locked_by, 'unknown') || ' stopped responding',
+                locked_by = NULL,
+                locked_at = NULL
+            FROM (
+                SELECT id, CASE
+                    WHEN attempts < max_attempts AND NOT EXISTS (
+                        SELECT 1 FROM jobs queued
+                        WHERE queued.status = 'queued' AND queued.dedupe_key = stale.dedupe_key
+                    ) THEN 'queued'::job_status_enum
+                    ELSE 'failed'::job_status_enum
+                END AS status
+                FROM jobs stale
+                WHERE stale.status = 'running' AND stale.locked_at < $1
+                FOR UPDATE SKIP LOCKED
+            ) next
+            WHERE jobs.id = next.id
+            "#,
+        )
+        .bind(Utc::now() - timeout)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let job = sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(job)
+    }
+
+    /// Most recent jobs first, optionally filtered
+    pub async fn list(
+        pool: &PgPool,
+        status: Option<JobStatus>,
+        kind: Option<&str>,
+        user_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let jobs = sqlx::query_as::<_, Job>(
+            r#"
+            SELECT * FROM jobs
+            WHERE ($1::job_status_enum IS NULL OR status = $1)
+            AND ($2::TEXT IS NULL OR kind = $2)
+            AND ($3::TEXT IS NULL OR user_id = $3)
+            ORDER BY created_at DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(status)
+        .bind(kind)
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    pub async fn counts(pool: &PgPool) -> Result<Vec<JobCount>> {
+        let counts = sqlx::query_as::<_, JobCount>(
+            r#"
+            SELECT kind, status, COUNT(*) AS count FROM jobs
+            GROUP BY kind, status
+            ORDER BY kind, status
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Queue a failed or cancelled job to run now with a fresh set of attempts. None if the job isn't
+    /// failed or cancelled, or another job with its dedupe key is already queued
+    pub async fn retry(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs
+            SET status = 'queued', attempts = 0, run_at = NOW(), finished_at = NULL
+            WHERE id = $1
+            AND status IN ('failed', 'cancelled')
+            AND NOT EXISTS (
+                SELECT 1 FROM jobs queued
+                WHERE queued.status = 'queued' AND queued.dedupe_key = jobs.dedupe_key
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Cancel a queued job. None if the job isn't queued
+    pub async fn cancel(pool: &PgPool, id: Uuid) -> Result<Option<Self>> {
+        let job = sqlx::query_as::<_, Job>(
+            r#"
+            UPDATE jobs SET status = 'cancelled', finished_at = NOW()
+            WHERE id = $1 AND status = 'queued'
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(job)
+    }
+}
@@ -0,0 +1,182 @@
+// models/llm_model.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::debug;
+use utoipa::ToSchema;
+
+/// A routable model alias, as requested by clients, and how to serve it upstream
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct LlmModel {
+    pub alias: String,
+    pub upstream_id: String,
+    pub context_window: i32,
+    pub max_output_tokens: i32,
+    pub supports_vision: bool,
+    pub fallbacks: Vec<String>,
+    pub enabled: bool,
+    pub summarize_truncated: bool,
+    /// USD per million prompt tokens
+    pub input_cost_per_mtok: f64,
+    /// USD per million completion tokens
+    pub output_cost_per_mtok: f64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for LlmModel {
+    fn default() -> Self {
+        LlmModel {
+            alias: String::new(),
+            upstream_id: String::new(),
+            context_window: 128000,
+            max_output_tokens: 4096,
+            supports_vision: true,
+            fallbacks: vec![
+                "gpt-4o".to_string(),
+                "claude-3-5-sonnet-20240620".to_string(),
+            ],
+            enabled: true,
+            summarize_truncated: false,
+            input_cost_per_mtok: 0.0,
+            output_cost_per_mtok: 0.0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+impl LlmModel {
+    /// Models that aren't in the table are passed through to the provider as-is with default limits
+    pub fn passthrough(alias: &str) -> Self {
+        LlmModel {
+            alias: alias.to_string(),
+            upstream_id: alias.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Prompt tokens left once the completion is reserved. We count with cl100k, which can undercount
+    /// for other tokenizers, so keep 10% headroom
+    pub fn prompt_budget(&self) -> usize {
+        let available = (self.context_window - self.max_output_tokens).max(0) as usize;
+        available * 9 / 10
+    }
+
+    /// Cost in USD of a completion on this model, zero for unpriced models
+    pub fn cost(&self, input_tokens: u32, output_tokens: u32) -> f64 {
+        (input_tokens as f64 * self.input_cost_per_mtok
+            + output_tokens as f64 * self.output_cost_per_mtok)
+            / 1_000_000.0
+    }
+
+    /// Resolve a requested model alias through the cached registry
+    pub async fn resolve(
+        pool: &PgPool,
+        alias: &str,
+        model_cache: &Cache<String, Option<LlmModel>>,
+    ) -> Result<Self> {
+        if let Some(cached) = model_cache.get(alias).await {
+            return Ok(cached.unwrap_or_else(|| Self::passthrough(alias)));
+        }
+
+        let model = sqlx::query_as::<_, LlmModel>("SELECT * FROM models WHERE alias = $1")
+            .bind(alias)
+            .fetch_optional(pool)
+            .await?;
+
+        // Cache misses too, so unknown models don't hit the database on every request
+        model_cache.insert(alias.to_string(), model.clone()).await;
+
+        debug!("Resolved model {}: {:?}", alias, model);
+        Ok(model.unwrap_or_else(|| Self::passthrough(alias)))
+    }
+
+    /// The registered model that served a completion, from the model name the upstream reported.
+    /// Matches an alias or upstream id exactly, or a dated version of an upstream id such as
+    /// gpt-4o-2024-08-06 for gpt-4o. None if nothing matches
+    pub async fn find_served(pool: &PgPool, served_model: &str) -> Result<Option<Self>> {
+        let model = sqlx::query_as::<_, LlmModel>(
+            r#"
+            SELECT * FROM models
+            WHERE alias = $1 OR upstream_id = $1 OR $1 LIKE upstream_id || '-%'
+            ORDER BY alias = $1 DESC, upstream_id = $1 DESC, LENGTH(upstream_id) DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(served_model)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(model)
+    }
+
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        let models = sqlx::query_as::<_, LlmModel>("SELECT * FROM models ORDER BY alias")
+            .fetch_all(pool)
+            .await?;
+
+        Ok(models)
+    }
+
+    /// Insert or replace a model route and drop it from the cache
+    pub async fn upsert(
+        pool: &PgPool,
+        model: &LlmModel,
+        model_cache: &Cache<String, Option<LlmModel>>,
+    ) -> Result<Self> {
+        let query_str = r#"
+            INSERT INTO models (alias, upstream_id, context_window, max_output_tokens, supports_vision, fallbacks, enabled, summarize_truncated, input_cost_per_mtok, output_cost_per_mtok)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (alias) DO UPDATE SET
+                upstream_id = EXCLUDED.upstream_id,
+                context_window = EXCLUDED.context_window,
+                max_output_tokens = EXCLUDED.max_output_tokens,
+                supports_vision = EXCLUDED.supports_vision,
+                fallbacks = EXCLUDED.fallbacks,
+                enabled = EXCLUDED.enabled,
+                summarize_truncated = EXCLUDED.summarize_truncated,
+                input_cost_per_mtok = EXCLUDED.input_cost_per_mtok,
+                output_cost_per_mtok = EXCLUDED.output_cost_per_mtok
+            RETURNING *
+        "#;
+
+        let model = sqlx::query_as::<_, LlmModel>(query_str)
+            .bind(&model.alias)
+            .bind(&model.upstream_id)
+            .bind(model.context_window)
+            .bind(model.max_output_tokens)
+            .bind(model.supports_vision)
+            .bind(&model.fallbacks)
+            .bind(model.enabled)
+            .bind(model.summarize_truncated)
+            .bind(model.input_cost_per_mtok)
+            .bind(model.output_cost_per_mtok)
+            .fetch_one(pool)
+            .await?;
+
+        model_cache.invalidate(&model.alias).await;
+
+        debug!("Model upserted: {:?}", model);
+        Ok(model)
+    }
+
+    pub async fn delete(
+        pool: &PgPool,
+        alias: &str,
+        model_cache: &Cache<String, Option<LlmModel>>,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM models WHERE alias = $1")
+            .bind(alias)
+            .execute(pool)
+            .await?;
+
+        model_cache.invalidate(alias).await;
+
+        debug!("Model deleted: {}", alias);
+        Ok(())
+    }
+}
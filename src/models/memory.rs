@@ -1,18 +1,31 @@
 // models/memory.rs
 
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use lazy_static::lazy_static;
 use moka::future::Cache;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, FromRow, PgPool};
-use std::collections::HashMap;
+use sqlx::{FromRow, PgPool};
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::llm::context::count_tokens;
+use crate::llm::embeddings::{cosine_similarity, EmbeddingProvider};
+use crate::models::memory_revision::{MemoryChange, MemoryRevision};
+use crate::models::{MemoryEmbedding, PageCursor, UserMemoryGroups};
+use crate::types::{MemoriesQuery, MemorySort, SortOrder};
+
+/// Most memories injected into the system prompt on top of the pinned ones
+const RELEVANT_MEMORIES_TOP_K: usize = 25;
+/// Token budget for injected memories, pinned ones count against it first
+pub const MEMORY_TOKEN_BUDGET: usize = 1500;
+/// Below this confidence an injected memory is flagged as possibly outdated
+const LOW_CONFIDENCE: f32 = 0.5;
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Memory {
     pub id: Uuid,
@@ -22,6 +35,15 @@ pub struct Memory {
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub grouping: Option<String>,
+    /// Always injected into the system prompt, whatever the relevance ranking says. Never decays
+    pub pinned: bool,
+    /// 1 when last confirmed, halving every half-life since. Scales the relevance ranking
+    pub confidence: f32,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Last time the user edited the memory or a generation run found it again
+    pub last_confirmed_at: DateTime<Utc>,
+    /// Set once the memory went unconfirmed and unused for too long, archived memories aren't injected
+    pub archived_at: Option<DateTime<Utc>>,
 }
 
 impl Default for Memory {
@@ -34,6 +56,11 @@ impl Default for Memory {
             updated_at: Utc::now(),
             deleted_at: None,
             grouping: None,
+            pinned: false,
+            confidence: 1.0,
+            last_used_at: None,
+            last_confirmed_at: Utc::now(),
+            archived_at: None,
         }
     }
 }
@@ -74,14 +101,15 @@ impl Memory {
         grouping: Option<&str>,
         user_id: &str,
         memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        change: &MemoryChange,
     ) -> Result<Self> {
         let now_utc = Utc::now();
         let memory_id = Uuid::new_v4();
 
         let new_memory = Memory::new(memory_id, user_id, memory, Some(now_utc), grouping);
 
-        let memory = query_as!(
-            Memory,
+        let mut tx = pool.begin().await?;
+        let memory = sqlx::query_as::<_, Memory>(
             r#"
             INSERT INTO memories (
                 id, user_id, created_at, updated_at, 
@@ -90,16 +118,19 @@ impl Memory {
             VALUES ($1, $2, $3, $4, $5, $6) 
             RETURNING *
             "#,
-            new_memory.id,
-            new_memory.user_id,
-            new_memory.created_at,
-            new_memory.updated_at,
-            new_memory.content,
-            new_memory.grouping
         )
-        .fetch_one(pool)
+        .bind(new_memory.id)
+        .bind(&new_memory.user_id)
+        .bind(new_memory.created_at)
+        .bind(new_memory.updated_at)
+        .bind(&new_memory.content)
+        .bind(&new_memory.grouping)
+        .fetch_one(&mut *tx)
         .await?;
 
+        MemoryRevision::record(&mut tx, &memory, None, change).await?;
+        tx.commit().await?;
+
         // Update the cache with the new memory
         debug!("Updating cache for new memory: {:?}", memory.id);
         if let Some(user_memories) = memory_cache.get(user_id).await {
@@ -127,46 +158,102 @@ impl Memory {
         grouping: Option<&str>,
         user_id: &str,
         memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        change: &MemoryChange,
+    ) -> Result<Self> {
+        Self::write_content(
+            pool,
+            memory_id,
+            new_memory,
+            grouping,
+            user_id,
+            memory_cache,
+            change,
+            false,
+        )
+        .await
+    }
+
+    /// Set a memory back to a revision's content, restoring it if it was deleted
+    pub async fn rollback(
+        pool: &PgPool,
+        memory_id: Uuid,
+        content: &str,
+        grouping: Option<&str>,
+        user_id: &str,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        change: &MemoryChange,
+    ) -> Result<Self> {
+        Self::write_content(
+            pool,
+            memory_id,
+            content,
+            grouping,
+            user_id,
+            memory_cache,
+            change,
+            true,
+        )
+        .await
+    }
+
+    /// Replace a memory's content. Deleted memories are only found, and undeleted, with `restore`
+    #[allow(clippy::too_many_arguments)]
+    async fn write_content(
+        pool: &PgPool,
+        memory_id: Uuid,
+        new_memory: &str,
+        grouping: Option<&str>,
+        user_id: &str,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        change: &MemoryChange,
+        restore: bool,
     ) -> Result<Self> {
         let now_utc = Utc::now();
 
-        let memory = query_as!(
-            Memory,
+        let mut tx = pool.begin().await?;
+        let previous = sqlx::query_as::<_, Memory>(
             r#"
             SELECT *
             FROM memories 
-            WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL
+            WHERE id = $1 AND user_id = $2 AND (deleted_at IS NULL OR $3)
+            FOR UPDATE
             "#,
-            memory_id,
-            user_id
         )
-        .fetch_one(pool)
+        .bind(memory_id)
+        .bind(user_id)
+        .bind(restore)
+        .fetch_one(&mut *tx)
         .await?;
 
         let updated_memory = Memory {
             content: new_memory.to_string(),
             updated_at: now_utc,
             grouping: grouping.map(|g| g.to_string()),
-            ..memory
+            ..previous.clone()
         };
 
-        let memory = query_as!(
-            Memory,
+        // An edit or a merge by the increment prompt confirms the memory
+        let memory = sqlx::query_as::<_, Memory>(
             r#"
             UPDATE memories 
-            SET content = $1, updated_at = $2, grouping = $3
-            WHERE id = $4 AND user_id = $5 AND deleted_at IS NULL
+            SET content = $1, updated_at = $2, grouping = $3,
+                confidence = 1.0, last_confirmed_at = $2, archived_at = NULL, deleted_at = NULL
+            WHERE id = $4 AND user_id = $5 AND (deleted_at IS NULL OR $6)
             RETURNING *
             "#,
-            updated_memory.content,
-            updated_memory.updated_at,
-            updated_memory.grouping,
-            updated_memory.id,
-            updated_memory.user_id
         )
-        .fetch_one(pool)
+        .bind(&updated_memory.content)
+        .bind(updated_memory.updated_at)
+        .bind(&updated_memory.grouping)
+        .bind(updated_memory.id)
+        .bind(&updated_memory.user_id)
+        .bind(restore)
+        .fetch_one(&mut *tx)
         .await?;
 
+        MemoryRevision::record(&mut tx, &memory, Some(&previous), change).await?;
+        tx.commit().await?;
+
         info!("Updating cache for memory: {:?}", memory_id);
         if let Some(user_memories) = memory_cache.get(user_id).await {
             let mut updated_user_memories = user_memories.clone();
@@ -191,22 +278,26 @@ impl Memory {
         memory_id: Uuid,
         user_id: &str,
         memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        change: &MemoryChange,
     ) -> Result<Memory> {
-        let memory = query_as!(
-            Memory,
+        let mut tx = pool.begin().await?;
+        let memory = sqlx::query_as::<_, Memory>(
             r#"
             UPDATE memories 
             SET deleted_at = $1
             WHERE id = $2 AND user_id = $3 AND deleted_at IS NULL
             RETURNING *
             "#,
-            Utc::now(),
-            memory_id,
-            user_id
         )
-        .fetch_one(pool)
+        .bind(Utc::now())
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
         .await?;
 
+        MemoryRevision::record(&mut tx, &memory, Some(&memory), change).await?;
+        tx.commit().await?;
+
         if let Some(mut user_memories) = memory_cache.get(user_id).await {
             user_memories.remove(&memory_id);
             memory_cache
@@ -222,6 +313,186 @@ impl Memory {
         Ok(memory)
     }
 
+    /// Pin or unpin a memory. Pinned memories are always injected and never decay
+    pub async fn set_pinned(
+        pool: &PgPool,
+        memory_id: Uuid,
+        user_id: &str,
+        pinned: bool,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    ) -> Result<Option<Memory>> {
+        let memory = sqlx::query_as::<_, Memory>(
+            r#"
+            UPDATE memories
+            SET pinned = $1, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $2 AND user_id = $3 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(pinned)
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        memory_cache.invalidate(user_id).await;
+        Ok(memory)
+    }
+
+    /// Mark memories a generation run found again as confirmed, restoring their confidence
+    pub async fn confirm(
+        pool: &PgPool,
+        memory_ids: &[Uuid],
+        user_id: &str,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    ) -> Result<u64> {
+        if memory_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let result = sqlx::query(
+            r#"
+            UPDATE memories
+            SET confidence = 1.0, last_confirmed_at = CURRENT_TIMESTAMP, archived_at = NULL
+            WHERE id = ANY($1) AND user_id = $2 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(memory_ids)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+        memory_cache.invalidate(user_id).await;
+        Ok(result.rows_affected())
+    }
+
+    /// Whether a memory is the user's, deleted ones included
+    pub async fn exists_for_user(pool: &PgPool, memory_id: Uuid, user_id: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM memories WHERE id = $1 AND user_id = $2)",
+        )
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// A user's archived memories, most recently archived first
+    pub async fn get_archived(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let memories = sqlx::query_as::<_, Memory>(
+            r#"
+            SELECT *
+            FROM memories
+            WHERE user_id = $1 AND deleted_at IS NULL AND archived_at IS NOT NULL
+            ORDER BY archived_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(memories)
+    }
+
+    /// Archive a memory as of `archived_at`, as the decay job would have
+    pub async fn archive(
+        pool: &PgPool,
+        memory_id: Uuid,
+        user_id: &str,
+        archived_at: DateTime<Utc>,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    ) -> Result<Option<Memory>> {
+        let memory = sqlx::query_as::<_, Memory>(
+            r#"
+            UPDATE memories
+            SET archived_at = $1
+            WHERE id = $2 AND user_id = $3 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(archived_at)
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        memory_cache.invalidate(user_id).await;
+        Ok(memory)
+    }
+
+    /// Bring an archived memory back, as if it was just confirmed
+    pub async fn unarchive(
+        pool: &PgPool,
+        memory_id: Uuid,
+        user_id: &str,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    ) -> Result<Option<Memory>> {
+        let memory = sqlx::query_as::<_, Memory>(
+            r#"
+            UPDATE memories
+            SET archived_at = NULL, confidence = 1.0, last_confirmed_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL AND archived_at IS NOT NULL
+            RETURNING *
+            "#,
+        )
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        memory_cache.invalidate(user_id).await;
+        Ok(memory)
+    }
+
+    /// Lower the confidence of every unpinned memory by how long it went unconfirmed, halving every
+    /// `half_life`, and archive the ones neither confirmed nor used within `archive_after`.
+    /// Returns how many memories were decayed and archived
+    pub async fn decay(
+        pool: &PgPool,
+        half_life: Duration,
+        archive_after: Duration,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    ) -> Result<(u64, u64)> {
+        let now = Utc::now();
+        let decayed = sqlx::query(
+            r#"
+            UPDATE memories
+            SET confidence = decayed.confidence
+            FROM (
+                SELECT id, POWER(0.5, EXTRACT(EPOCH FROM $1::TIMESTAMPTZ - last_confirmed_at)::FLOAT8 / $2) AS confidence
+                FROM memories
+                WHERE deleted_at IS NULL AND archived_at IS NULL AND NOT pinned
+            ) decayed
+            WHERE memories.id = decayed.id AND ABS(memories.confidence - decayed.confidence) >= 0.01
+            "#,
+        )
+        .bind(now)
+        .bind(half_life.num_seconds() as f64)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        let archived = sqlx::query(
+            r#"
+            UPDATE memories
+            SET archived_at = $1
+            WHERE deleted_at IS NULL AND archived_at IS NULL AND NOT pinned
+                AND last_confirmed_at < $2
+                AND (last_used_at IS NULL OR last_used_at < $2)
+            "#,
+        )
+        .bind(now)
+        .bind(now - archive_after)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        memory_cache.invalidate_all();
+        Ok((decayed, archived))
+    }
+
     pub async fn get_all_memories(
         pool: &PgPool,
         user_id: &str,
@@ -244,15 +515,14 @@ impl Memory {
         }
 
         // If not in cache, fetch from database
-        let result = query_as!(
-            Memory,
+        let result = sqlx::query_as::<_, Memory>(
             r#"
             SELECT *
             FROM memories 
-            WHERE user_id = $1 AND deleted_at IS NULL
+            WHERE user_id = $1 AND deleted_at IS NULL AND archived_at IS NULL
             "#,
-            user_id
         )
+        .bind(user_id)
         .fetch_all(pool)
         .await?;
 
@@ -269,23 +539,221 @@ impl Memory {
         Ok(result)
     }
 
+    /// Ids of a user's memories whose content matches a full-text query, through the content tsvector index
+    pub async fn search_ids(pool: &PgPool, user_id: &str, query: &str) -> Result<HashSet<Uuid>> {
+        let ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT id
+            FROM memories
+            WHERE user_id = $1 AND deleted_at IS NULL AND archived_at IS NULL
+                AND to_tsvector('english', content) @@ websearch_to_tsquery('english', $2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(query)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+
+    /// One page of a user's memories matching `query`, sorted by the chosen timestamp with the id
+    /// breaking ties, starting after `after`. Memories come from the cache, the search only
+    /// narrows down which ones. Returns the cursor of the next page if there is one
+    pub async fn filter_page(
+        pool: &PgPool,
+        user_id: &str,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        query: &MemoriesQuery,
+        after: Option<PageCursor>,
+    ) -> Result<(Vec<Self>, Option<PageCursor>)> {
+        let mut memories = Self::get_all_memories(pool, user_id, memory_cache).await?;
+
+        if let Some(q) = query.q.as_deref().filter(|q| !q.trim().is_empty()) {
+            let matching = Self::search_ids(pool, user_id, q).await?;
+            memories.retain(|memory| matching.contains(&memory.id));
+        }
+
+        memories.retain(|memory| {
+            query
+                .grouping
+                .as_ref()
+                .is_none_or(|grouping| memory.grouping.as_ref() == Some(grouping))
+                && query.created_after.is_none_or(|t| memory.created_at >= t)
+                && query.created_before.is_none_or(|t| memory.created_at < t)
+                && query.updated_after.is_none_or(|t| memory.updated_at >= t)
+                && query.updated_before.is_none_or(|t| memory.updated_at < t)
+        });
+
+        let cursor_of = |memory: &Memory| PageCursor {
+            at: match query.sort {
+                MemorySort::CreatedAt => memory.created_at,
+                MemorySort::UpdatedAt => memory.updated_at,
+            },
+            id: memory.id,
+        };
+
+        memories.sort_by_key(cursor_of);
+        if query.order == SortOrder::Desc {
+            memories.reverse();
+        }
+
+        if let Some(after) = after {
+            memories.retain(|memory| match query.order {
+                SortOrder::Asc => cursor_of(memory) > after,
+                SortOrder::Desc => cursor_of(memory) < after,
+            });
+        }
+
+        let next = match query.limit {
+            Some(limit) if memories.len() > limit => {
+                memories.truncate(limit);
+                memories.last().map(cursor_of)
+            }
+            _ => None,
+        };
+
+        Ok((memories, next))
+    }
+
+    /// The memories worth injecting for a user turn: every pinned memory, then the ones most similar to
+    /// `query` while they fit the token budget. Skips embedding entirely when everything fits anyway.
+    /// Nothing for users who turned memory off, and nothing from groups they excluded
+    pub async fn get_relevant_memories(
+        pool: &PgPool,
+        user_id: &str,
+        query: &str,
+        embedder: &dyn EmbeddingProvider,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        groups: &UserMemoryGroups,
+    ) -> Result<Vec<Self>> {
+        if !groups.settings.memory_enabled {
+            return Ok(Vec::new());
+        }
+
+        let mut memories = Self::get_all_memories(pool, user_id, memory_cache).await?;
+        memories.retain(|memory| {
+            !memory
+                .grouping
+                .as_deref()
+                .is_some_and(|grouping| groups.is_excluded(grouping))
+        });
+
+        let total_tokens: usize = memories
+            .iter()
+            .map(|memory| count_tokens(&memory.content))
+            .sum();
+        if total_tokens <= MEMORY_TOKEN_BUDGET || query.trim().is_empty() {
+            Self::mark_used_in_background(pool, &memories);
+            return Ok(memories);
+        }
+
+        let ranking = async {
+            let embeddings = MemoryEmbedding::ensure(pool, embedder, &memories).await?;
+            let query_embedding = embedder
+                .embed(&[query.to_string()])
+                .await?
+                .pop()
+                .unwrap_or_default();
+            anyhow::Ok((embeddings, query_embedding))
+        };
+
+        // Without embeddings the ranking ties everywhere and the stable sort keeps the newest first
+        let (embeddings, query_embedding) = ranking.await.unwrap_or_else(|e| {
+            warn!("Failed to rank memories, using the newest: {:?}", e);
+            memories.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+            (HashMap::new(), Vec::new())
+        });
+
+        let selected = Self::select_relevant(
+            memories,
+            &embeddings,
+            &query_embedding,
+            RELEVANT_MEMORIES_TOP_K,
+            MEMORY_TOKEN_BUDGET,
+        );
+        info!(
+            "Selected {} of {} tokens of memories for user {}",
+            selected.len(),
+            total_tokens,
+            user_id
+        );
+        Self::mark_used_in_background(pool, &selected);
+        Ok(selected)
+    }
+
+    /// Record that memories were injected, off the request path
+    fn mark_used_in_background(pool: &PgPool, memories: &[Self]) {
+        if memories.is_empty() {
+            return;
+        }
+
+        let pool = pool.clone();
+        let memory_ids: Vec<Uuid> = memories.iter().map(|memory| memory.id).collect();
+        tokio::spawn(async move {
+            let result = sqlx::query("UPDATE memories SET last_used_at = $1 WHERE id = ANY($2)")
+                .bind(Utc::now())
+                .bind(&memory_ids)
+                .execute(&pool)
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to mark memories used: {:?}", e);
+            }
+        });
+    }
+
+    /// Pinned memories first, whatever they cost, then up to `top_k` of the rest by similarity
+    /// to the query scaled by confidence, skipping any that would overflow the budget
+    pub fn select_relevant(
+        memories: Vec<Self>,
+        embeddings: &HashMap<Uuid, Vec<f32>>,
+        query_embedding: &[f32],
+        top_k: usize,
+        budget: usize,
+    ) -> Vec<Self> {
+        let (mut selected, rest): (Vec<Self>, Vec<Self>) =
+            memories.into_iter().partition(|memory| memory.pinned);
+        let mut used: usize = selected
+            .iter()
+            .map(|memory| count_tokens(&memory.content))
+            .sum();
+
+        let mut ranked: Vec<(f32, Self)> = rest
+            .into_iter()
+            .map(|memory| {
+                let score = embeddings
+                    .get(&memory.id)
+                    .map(|embedding| cosine_similarity(embedding, query_embedding))
+                    .unwrap_or(0.0)
+                    * memory.confidence;
+                (score, memory)
+            })
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        for (_, memory) in ranked.into_iter().take(top_k) {
+            let tokens = count_tokens(&memory.content);
+            if used + tokens <= budget {
+                used += tokens;
+                selected.push(memory);
+            }
+        }
+        selected
+    }
+
     pub fn format_memories(memories: Vec<Self>) -> String {
         let mut formatted_memories = String::new();
 
         for (index, memory) in memories.iter().enumerate() {
             info!("Memory {}: Content: {:?}", index, memory.content);
-            if let Some(captures) = USER_INFO_REGEX.captures(&memory.content) {
-                info!("Memory {}: Regex match found", index);
-                if let Some(content) = captures.get(1) {
-                    let extracted = content.as_str().trim();
-                    info!(
-                        "Memory {}: Extracted content length: {} chars",
-                        index,
-                        extracted.len()
-                    );
-                    formatted_memories.push_str(extracted);
-                    formatted_memories.push('\n')
-                }
+            if let Some(extracted) = Self::extract_user_information(&memory.content) {
+                info!(
+                    "Memory {}: Extracted content length: {} chars",
+                    index,
+                    extracted.len()
+                );
+                formatted_memories.push_str(extracted);
+                formatted_memories.push('\n')
             } else {
                 info!("Memory {}: No regex match found", index);
             }
@@ -293,6 +761,14 @@ impl Memory {
         formatted_memories.trim_end().to_string()
     }
 
+    /// The contents of the `<user information>` tags in a free text generation reply
+    pub fn extract_user_information(content: &str) -> Option<&str> {
+        USER_INFO_REGEX
+            .captures(content)
+            .and_then(|captures| captures.get(1))
+            .map(|content| content.as_str().trim())
+    }
+
     pub fn format_grouped_memories(memories: &Vec<Memory>, with_id: bool) -> String {
         use std::collections::HashMap;
 
@@ -311,6 +787,15 @@ impl Memory {
                 .push(memory.clone());
         }
 
+        // Pinned memories lead each group, then the ones we're surest of
+        for contents in grouped_memories.values_mut() {
+            contents.sort_by(|a, b| {
+                b.pinned
+                    .cmp(&a.pinned)
+                    .then(b.confidence.total_cmp(&a.confidence))
+            });
+        }
+
         // Format memories
         grouped_memories
             .iter()
@@ -322,6 +807,8 @@ impl Memory {
                         .iter()
                         .map(|memory| if with_id {
                             format!("- {},{}", memory.id, memory.content)
+                        } else if !memory.pinned && memory.confidence < LOW_CONFIDENCE {
+                            format!("- {} (may be outdated)", memory.content)
                         } else {
                             format!("- {}", memory.content)
                         })
@@ -333,8 +820,8 @@ impl Memory {
             .join("\n\n")
     }
 
-    pub fn formated_allowed_groups() -> String {
-        format!("[{}]", ALLOWED_GROUPS.join(", "))
+    pub fn allowed_groups() -> &'static [String] {
+        &ALLOWED_GROUPS
     }
 
     pub fn get_valid_group(group: Option<&str>) -> String {
@@ -366,7 +853,8 @@ impl Memory {
             created_at: created_at.unwrap_or_else(Utc::now),
             updated_at: Utc::now(),
             content: content.to_string(),
-            grouping: Some(Self::get_valid_group(grouping)),
+            // Callers check the grouping against the user's groups, see UserMemoryGroups::valid_group
+            grouping: Some(grouping.map_or_else(|| GENERIC_GROUP.clone(), str::to_string)),
             ..Default::default()
         }
     }
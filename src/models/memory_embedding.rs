@@ -0,0 +1,99 @@
+// models/memory_embedding.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::llm::embeddings::EmbeddingProvider;
+use crate::models::Memory;
+
+/// Keeps each embeddings request comfortably under provider input limits
+const EMBED_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MemoryEmbedding {
+    pub memory_id: Uuid,
+    pub model: String,
+    pub content_hash: String,
+    pub embedding: Vec<f32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl MemoryEmbedding {
+    fn content_hash(content: &str) -> String {
+        hex::encode(Sha256::digest(content.as_bytes()))
+    }
+
+    /// Embeddings for the memories, computing and storing any that are missing or stale
+    pub async fn ensure(
+        pool: &PgPool,
+        embedder: &dyn EmbeddingProvider,
+        memories: &[Memory],
+    ) -> Result<HashMap<Uuid, Vec<f32>>> {
+        let ids: Vec<Uuid> = memories.iter().map(|memory| memory.id).collect();
+
+        let stored = sqlx::query_as::<_, MemoryEmbedding>(
+            "SELECT * FROM memory_embeddings WHERE memory_id = ANY($1) AND model = $2",
+        )
+        .bind(&ids)
+        .bind(embedder.model())
+        .fetch_all(pool)
+        .await?;
+
+        let mut stored: HashMap<Uuid, MemoryEmbedding> = stored
+            .into_iter()
+            .map(|embedding| (embedding.memory_id, embedding))
+            .collect();
+
+        let mut embeddings = HashMap::new();
+        let mut stale = Vec::new();
+        for memory in memories {
+            let content_hash = Self::content_hash(&memory.content);
+            match stored.remove(&memory.id) {
+                Some(embedding) if embedding.content_hash == content_hash => {
+                    embeddings.insert(memory.id, embedding.embedding);
+                }
+                _ => stale.push((memory, content_hash)),
+            }
+        }
+
+        for batch in stale.chunks(EMBED_BATCH_SIZE) {
+            let texts: Vec<String> = batch
+                .iter()
+                .map(|(memory, _)| memory.content.clone())
+                .collect();
+            let vectors = embedder.embed(&texts).await?;
+
+            for ((memory, content_hash), vector) in batch.iter().zip(vectors) {
+                sqlx::query(
+                    r#"
+                    INSERT INTO memory_embeddings (memory_id, model, content_hash, embedding)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (memory_id) DO UPDATE
+                    SET model = EXCLUDED.model,
+                        content_hash = EXCLUDED.content_hash,
+                        embedding = EXCLUDED.embedding
+                    "#,
+                )
+                .bind(memory.id)
+                .bind(embedder.model())
+                .bind(content_hash)
+                .bind(&vector)
+                .execute(pool)
+                .await?;
+
+                embeddings.insert(memory.id, vector);
+            }
+        }
+
+        if !stale.is_empty() {
+            debug!("Embedded {} new or changed memories", stale.len());
+        }
+        Ok(embeddings)
+    }
+}
@@ -0,0 +1,272 @@
+// models/memory_revision.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::collections::HashMap;
+use std::fmt;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Memory;
+
+pub const REASON_NEW: &str = "NEW";
+pub const REASON_UPDATE: &str = "UPDATE";
+pub const REASON_EDIT: &str = "EDIT";
+pub const REASON_DELETE: &str = "DELETE";
+pub const REASON_ROLLBACK: &str = "ROLLBACK";
+
+/// Who wrote a memory revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryAuthor {
+    User,
+    NightlyJob,
+    RealtimeJob,
+    /// A generation run triggered from the admin endpoint
+    AdminJob,
+    /// Merged in from a memory export through /memories/import
+    Import,
+}
+
+impl fmt::Display for MemoryAuthor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryAuthor::User => write!(f, "user"),
+            MemoryAuthor::NightlyJob => write!(f, "nightly_job"),
+            MemoryAuthor::RealtimeJob => write!(f, "realtime_job"),
+            MemoryAuthor::AdminJob => write!(f, "admin_job"),
+            MemoryAuthor::Import => write!(f, "import"),
+        }
+    }
+}
+
+/// Why a memory is being written and what it came from, recorded alongside the write
+#[derive(Debug, Clone)]
+pub struct MemoryChange {
+    pub author: MemoryAuthor,
+    pub reason: &'static str,
+    /// Messages the memory was derived from, empty for edits by the user
+    pub source_message_ids: Vec<Uuid>,
+}
+
+impl MemoryChange {
+    pub fn new(author: MemoryAuthor, reason: &'static str, source_message_ids: &[Uuid]) -> Self {
+        MemoryChange {
+            author,
+            reason,
+            source_message_ids: source_message_ids.to_vec(),
+        }
+    }
+
+    pub fn by_user(reason: &'static str) -> Self {
+        Self::new(MemoryAuthor::User, reason, &[])
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MemoryRevision {
+    pub id: Uuid,
+    pub memory_id: Uuid,
+    pub user_id: String,
+    pub author: String,
+    pub reason: String,
+    pub previous_content: Option<String>,
+    pub content: String,
+    pub previous_grouping: Option<String>,
+    pub grouping: Option<String>,
+    /// `[-removed-]` and `{+added+}` words
+    pub diff: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MemorySource {
+    pub memory_id: Uuid,
+    pub message_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MemoryRevision {
+    /// Record a write to `memory`, and link it to the change's source messages.
+    /// Runs on the caller's connection so it commits or rolls back with the write itself
+    pub async fn record(
+        conn: &mut PgConnection,
+        memory: &Memory,
+        previous: Option<&Memory>,
+        change: &MemoryChange,
+    ) -> Result<Self> {
+        let previous_content = previous.map(|previous| previous.content.as_str());
+
+        let revision = sqlx::query_as::<_, MemoryRevision>(
+            r#"
+            INSERT INTO memory_revisions (id, memory_id, user_id, author, reason, previous_content, content, previous_grouping, grouping, diff)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(memory.id)
+        .bind(&memory.user_id)
+        .bind(change.author.to_string())
+        .bind(change.reason)
+        .bind(previous_content)
+        .bind(&memory.content)
+        .bind(previous.and_then(|previous| previous.grouping.as_deref()))
+        .bind(&memory.grouping)
+        .bind(word_diff(previous_content.unwrap_or_default(), &memory.content))
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if !change.source_message_ids.is_empty() {
+            // Messages deleted since they were sampled are skipped rather than failing the write
+            sqlx::query(
+                r#"
+                INSERT INTO memory_sources (memory_id, message_id)
+                SELECT $1, id FROM messages WHERE id = ANY($2)
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(memory.id)
+            .bind(&change.source_message_ids)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(revision)
+    }
+
+    /// A memory's revisions, oldest first
+    pub async fn get_for_memory(
+        pool: &PgPool,
+        memory_id: Uuid,
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let revisions = sqlx::query_as::<_, MemoryRevision>(
+            r#"
+            SELECT * FROM memory_revisions
+            WHERE memory_id = $1 AND user_id = $2
+            ORDER BY created_at
+            "#,
+        )
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(revisions)
+    }
+
+    pub async fn get(
+        pool: &PgPool,
+        id: Uuid,
+        memory_id: Uuid,
+        user_id: &str,
+    ) -> Result<Option<Self>> {
+        let revision = sqlx::query_as::<_, MemoryRevision>(
+            "SELECT * FROM memory_revisions WHERE id = $1 AND memory_id = $2 AND user_id = $3",
+        )
+        .bind(id)
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(revision)
+    }
+
+    /// Who created each of a user's memories, from their first revision
+    pub async fn creators_for_user(pool: &PgPool, user_id: &str) -> Result<HashMap<Uuid, String>> {
+        let creators = sqlx::query_as::<_, (Uuid, String)>(
+            r#"
+            SELECT DISTINCT ON (memory_id) memory_id, author
+            FROM memory_revisions
+            WHERE user_id = $1
+            ORDER BY memory_id, created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(creators.into_iter().collect())
+    }
+}
+
+impl MemorySource {
+    pub async fn get_for_memory(
+        pool: &PgPool,
+        memory_id: Uuid,
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let sources = sqlx::query_as::<_, MemorySource>(
+            r#"
+            SELECT s.*
+            FROM memory_sources s
+            JOIN memories m ON m.id = s.memory_id
+            WHERE s.memory_id = $1 AND m.user_id = $2
+            ORDER BY s.created_at
+            "#,
+        )
+        .bind(memory_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sources)
+    }
+
+    /// The sources of all of a user's memories, oldest first
+    pub async fn get_for_user(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let sources = sqlx::query_as::<_, MemorySource>(
+            r#"
+            SELECT s.*
+            FROM memory_sources s
+            JOIN memories m ON m.id = s.memory_id
+            WHERE m.user_id = $1
+            ORDER BY s.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sources)
+    }
+}
+
+/// Word level diff in wdiff notation. Memories are a sentence or two, so the quadratic LCS is fine
+pub fn word_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+
+    // lcs[i][j] is the LCS length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut parts = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            parts.push(old[i].to_string());
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            parts.push(format!("[-{}-]", old[i]));
+            i += 1;
+        } else {
+            parts.push(format!("{{+{}+}}", new[j]));
+            j += 1;
+        }
+    }
+    parts.join(" ")
+}
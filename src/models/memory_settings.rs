@@ -0,0 +1,305 @@
+// models/memory_settings.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Memory;
+
+/// Most custom groups per user, every one of them goes into the grouping and increment prompts
+pub const MAX_CUSTOM_GROUPS: usize = 20;
+
+/// A user's memory preferences
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MemorySettings {
+    pub user_id: String,
+    /// Off stops memories being generated or injected into chats, existing ones are kept
+    pub memory_enabled: bool,
+    /// Off stops extraction right after a message, the nightly run still covers it
+    pub realtime_enabled: bool,
+    /// Groupings never generated into or injected from
+    pub excluded_groups: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A grouping a user created on top of the built in ones
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct CustomMemoryGroup {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    /// Tells the prompts what belongs in the group
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Everything that decides where a user's memories can go: their settings and custom groups
+#[derive(Debug, Clone)]
+pub struct UserMemoryGroups {
+    pub settings: MemorySettings,
+    pub custom: Vec<CustomMemoryGroup>,
+}
+
+impl MemorySettings {
+    /// Settings for users who never changed them
+    pub fn default_for(user_id: &str) -> Self {
+        MemorySettings {
+            user_id: user_id.to_string(),
+            memory_enabled: true,
+            realtime_enabled: true,
+            excluded_groups: Vec::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub async fn get(pool: &PgPool, user_id: &str) -> Result<Self> {
+        let settings =
+            sqlx::query_as::<_, MemorySettings>("SELECT * FROM memory_settings WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(pool)
+                .await?
+                .unwrap_or_else(|| Self::default_for(user_id));
+
+        Ok(settings)
+    }
+
+    /// Whether memories are extracted right after a message
+    pub fn realtime_active(&self) -> bool {
+        self.memory_enabled && self.realtime_enabled
+    }
+
+    /// Change the given settings, leaving the ones that are None as they were
+    pub async fn update(
+        pool: &PgPool,
+        user_id: &str,
+        memory_enabled: Option<bool>,
+        realtime_enabled: Option<bool>,
+        excluded_groups: Option<&[String]>,
+    ) -> Result<Self> {
+        let settings = sqlx::query_as::<_, MemorySettings>(
+            r#"
+            INSERT INTO memory_settings (user_id, memory_enabled, realtime_enabled, excluded_groups)
+            VALUES ($1, COALESCE($2::BOOLEAN, TRUE), COALESCE($3::BOOLEAN, TRUE), COALESCE($4::TEXT[], '{}'))
+            ON CONFLICT (user_id) DO UPDATE SET
+                memory_enabled = COALESCE($2::BOOLEAN, memory_settings.memory_enabled),
+                realtime_enabled = COALESCE($3::BOOLEAN, memory_settings.realtime_enabled),
+                excluded_groups = COALESCE($4::TEXT[], memory_settings.excluded_groups)
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(memory_enabled)
+        .bind(realtime_enabled)
+        .bind(excluded_groups)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(settings)
+    }
+}
+
+impl CustomMemoryGroup {
+    pub async fn get_all(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let groups = sqlx::query_as::<_, CustomMemoryGroup>(
+            "SELECT * FROM custom_memory_groups WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(groups)
+    }
+
+    /// Create a group, None if the user already has one with that name
+    pub async fn create(
+        pool: &PgPool,
+        user_id: &str,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let group = sqlx::query_as::<_, CustomMemoryGroup>(
+            r#"
+            INSERT INTO custom_memory_groups (id, user_id, name, description)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, (LOWER(name))) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(description)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(group)
+    }
+
+    /// Rename a group or change its description. Memories filed under the old name move with it.
+    /// None if the group doesn't exist
+    pub async fn update(
+        pool: &PgPool,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        user_id: &str,
+        group_id: Uuid,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let mut tx = pool.begin().await?;
+        let Some(previous) = sqlx::query_as::<_, CustomMemoryGroup>(
+            "SELECT * FROM custom_memory_groups WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let group = sqlx::query_as::<_, CustomMemoryGroup>(
+            r#"
+            UPDATE custom_memory_groups
+            SET name = $1, description = $2
+            WHERE id = $3
+            RETURNING *
+            "#,
+        )
+        .bind(name)
+        .bind(description)
+        .bind(group_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Self::move_memories(&mut tx, user_id, &previous.name, &group.name).await?;
+        tx.commit().await?;
+        memory_cache.invalidate(user_id).await;
+
+        Ok(Some(group))
+    }
+
+    /// Delete a group, its memories fall back to the generic grouping. None if the group doesn't exist
+    pub async fn delete(
+        pool: &PgPool,
+        memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+        user_id: &str,
+        group_id: Uuid,
+    ) -> Result<Option<Self>> {
+        let mut tx = pool.begin().await?;
+        let Some(group) = sqlx::query_as::<_, CustomMemoryGroup>(
+            "DELETE FROM custom_memory_groups WHERE id = $1 AND user_id = $2 RETURNING *",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        Self::move_memories(
+            &mut tx,
+            user_id,
+            &group.name,
+            &Memory::get_valid_group(None),
+        )
+        .await?;
+        tx.commit().await?;
+        memory_cache.invalidate(user_id).await;
+
+        Ok(Some(group))
+    }
+
+    async fn move_memories(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        user_id: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE memories
+            SET grouping = $1, updated_at = CURRENT_TIMESTAMP
+            WHERE user_id = $2 AND grouping = $3 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(to)
+        .bind(user_id)
+        .bind(from)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl UserMemoryGroups {
+    pub async fn for_user(pool: &PgPool, user_id: &str) -> Result<Self> {
+        let (settings, custom) = tokio::try_join!(
+            MemorySettings::get(pool, user_id),
+            CustomMemoryGroup::get_all(pool, user_id)
+        )?;
+
+        Ok(UserMemoryGroups { settings, custom })
+    }
+
+    /// Whether memories filed under `grouping` are left out
+    pub fn is_excluded(&self, grouping: &str) -> bool {
+        self.settings
+            .excluded_groups
+            .iter()
+            .any(|excluded| excluded.eq_ignore_ascii_case(grouping))
+    }
+
+    /// Whether `grouping` is a built in or custom group of this user's
+    pub fn contains(&self, grouping: &str) -> bool {
+        Memory::allowed_groups()
+            .iter()
+            .chain(self.custom.iter().map(|group| &group.name))
+            .any(|name| name.eq_ignore_ascii_case(grouping))
+    }
+
+    /// The user's group matching `grouping`, or the generic group if there's none
+    pub fn valid_group(&self, grouping: Option<&str>) -> String {
+        match grouping.and_then(|g| self.custom.iter().find(|group| group.name == g)) {
+            Some(group) => group.name.clone(),
+            None => Memory::get_valid_group(grouping),
+        }
+    }
+
+    /// The groupings the grouping and increment prompts may choose from, followed by what the
+    /// user's custom ones are for
+    pub fn prompt_list(&self) -> String {
+        let names: Vec<&str> = Memory::allowed_groups()
+            .iter()
+            .chain(self.custom.iter().map(|group| &group.name))
+            .filter(|name| !self.is_excluded(name))
+            .map(String::as_str)
+            .collect();
+
+        let mut list = format!("[{}]", names.join(", "));
+        for group in &self.custom {
+            if let Some(description) = group.description.as_deref() {
+                if !self.is_excluded(&group.name) {
+                    list.push_str(&format!(
+                        "\n    - {} is a grouping the user created for: {}",
+                        group.name, description
+                    ));
+                }
+            }
+        }
+        list
+    }
+}
@@ -4,7 +4,8 @@ use async_openai::types::InvisibilityMetadata;
 use chrono::{DateTime, Utc};
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, FromRow, PgPool, Type};
+use sqlx::{query, FromRow, PgConnection, PgPool, Type};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use utoipa::ToSchema;
 use uuid::Uuid;
@@ -44,6 +45,15 @@ pub struct Message {
     pub upvoted: Option<bool>,
 }
 
+/// A message with the one it follows in its chat. Messages sharing a parent are alternatives for
+/// the same spot, of which only the one on the active path isn't regenerated
+#[derive(Debug, FromRow)]
+pub struct ThreadedMessage {
+    #[sqlx(flatten)]
+    pub message: Message,
+    pub parent_id: Option<Uuid>,
+}
+
 impl Default for Message {
     fn default() -> Self {
         Message {
@@ -80,25 +90,38 @@ impl Message {
         };
 
         // Save the message to the database
-        query!(
+        message.insert(pool).await?;
+
+        Ok(message)
+    }
+
+    /// Save the message after the latest message on the chat's active path. Regenerating marks
+    /// the old messages first, so a regenerated message's replacement becomes its sibling
+    async fn insert(&self, pool: &PgPool) -> Result<()> {
+        query(
             r#"
-            INSERT INTO messages (id, chat_id, user_id, text, role, regenerated, model_id, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            INSERT INTO messages (id, chat_id, user_id, text, role, regenerated, model_id, created_at, updated_at, parent_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, (
+                SELECT id FROM messages
+                WHERE chat_id = $2 AND regenerated = false
+                ORDER BY created_at DESC, id DESC
+                LIMIT 1
+            ))
             "#,
-            message.id,
-            message.chat_id,
-            message.user_id,
-            message.text,
-            message.role.clone() as Role, // idk why this is needed but it is
-            message.regenerated,
-            message.model_id,
-            message.created_at,
-            message.updated_at
         )
+        .bind(self.id)
+        .bind(self.chat_id)
+        .bind(&self.user_id)
+        .bind(&self.text)
+        .bind(&self.role)
+        .bind(self.regenerated)
+        .bind(&self.model_id)
+        .bind(self.created_at)
+        .bind(self.updated_at)
         .execute(pool)
         .await?;
 
-        Ok(message)
+        Ok(())
     }
 
     /// Create a new message from an OpenAI API request and saves to DB, either a user or assistant message.
@@ -129,23 +152,7 @@ impl Message {
         };
 
         // Save the message to the database
-        query!(
-            r#"
-            INSERT INTO messages (id, chat_id, user_id, text, role, regenerated, model_id, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-            "#,
-            message.id,
-            message.chat_id,
-            message.user_id,
-            message.text,
-            message.role.clone() as Role, // idk why this is needed but it is
-            message.regenerated,
-            message.model_id,
-            message.created_at,
-            message.updated_at
-        )
-        .execute(pool)
-        .await?;
+        message.insert(pool).await?;
 
         // Join futures
         let mut file_futres = Vec::new();
@@ -220,6 +227,74 @@ impl Message {
         Ok(())
     }
 
+    /// A user's message by id, None if it doesn't exist
+    pub async fn get(pool: &PgPool, message_id: Uuid, user_id: &str) -> Result<Option<Message>> {
+        let message =
+            sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE id = $1 AND user_id = $2")
+                .bind(message_id)
+                .bind(user_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(message)
+    }
+
+    /// Replace a user's message text, keeping the previous text in message_edits. With
+    /// `regenerate_following` the path to the edited message becomes the chat's active one and
+    /// every message below it is marked regenerated, other branches are left out of the path as
+    /// `activate` would. None if it doesn't exist
+    pub async fn edit(
+        pool: &PgPool,
+        message_id: Uuid,
+        user_id: &str,
+        text: &str,
+        regenerate_following: bool,
+    ) -> Result<Option<Message>> {
+        let mut tx = pool.begin().await?;
+        let Some(previous) = sqlx::query_as::<_, Message>(
+            "SELECT * FROM messages WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_edits (id, message_id, user_id, previous_text)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(message_id)
+        .bind(user_id)
+        .bind(&previous.text)
+        .execute(&mut *tx)
+        .await?;
+
+        let message = sqlx::query_as::<_, Message>(
+            r#"
+            UPDATE messages SET text = $2, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(message_id)
+        .bind(text)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if regenerate_following {
+            Self::activate_path(&mut tx, previous.chat_id, user_id, message_id, false).await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(message))
+    }
+
     pub async fn get_latest_message_by_user_id(
         pool: &PgPool,
         user_id: &str,
@@ -273,6 +348,174 @@ impl Message {
         Ok(messages)
     }
 
+    /// Up to `limit` of a chat's messages from before the message `before`, or the latest ones
+    /// without it, oldest first. Also returns whether there are older messages.
+    /// None if `before` isn't a message of the chat
+    pub async fn get_page_for_chat(
+        pool: &PgPool,
+        chat_id: Uuid,
+        user_id: &str,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Option<(Vec<Message>, bool)>> {
+        let cursor = match before {
+            Some(before) => {
+                let Some(cursor) = sqlx::query_as::<_, (DateTime<Utc>, Uuid)>(
+                    "SELECT created_at, id FROM messages WHERE id = $1 AND chat_id = $2 AND user_id = $3",
+                )
+                .bind(before)
+                .bind(chat_id)
+                .bind(user_id)
+                .fetch_optional(pool)
+                .await?
+                else {
+                    return Ok(None);
+                };
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        let mut messages = sqlx::query_as::<_, Message>(
+            r#"
+            SELECT * FROM messages
+            WHERE chat_id = $1 AND user_id = $2
+            AND ($3::TIMESTAMPTZ IS NULL OR (created_at, id) < ($3, $4))
+            ORDER BY created_at DESC, id DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(cursor.map(|(created_at, _)| created_at))
+        .bind(cursor.map(|(_, id)| id))
+        .bind(limit + 1)
+        .fetch_all(pool)
+        .await?;
+
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        messages.reverse();
+
+        Ok(Some((messages, has_more)))
+    }
+
+    /// All messages of the given chats with their parents, oldest first
+    pub async fn get_threaded_for_chats(
+        pool: &PgPool,
+        chat_ids: &[Uuid],
+        user_id: &str,
+    ) -> Result<Vec<ThreadedMessage>> {
+        let messages = sqlx::query_as::<_, ThreadedMessage>(
+            r#"
+            SELECT * FROM messages
+            WHERE chat_id = ANY($1) AND user_id = $2
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(chat_ids)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// Make the path through `message_id` the chat's active one: its ancestors, the message and,
+    /// below it, the active or else newest alternative at every step. Every other message is marked
+    /// regenerated. False if the message isn't in the chat
+    pub async fn activate(
+        pool: &PgPool,
+        chat_id: Uuid,
+        user_id: &str,
+        message_id: Uuid,
+    ) -> Result<bool> {
+        let mut tx = pool.begin().await?;
+        let activated = Self::activate_path(&mut tx, chat_id, user_id, message_id, true).await?;
+        tx.commit().await?;
+
+        Ok(activated)
+    }
+
+    /// Mark the path through `message_id` active and everything else in the chat regenerated.
+    /// Without `descend` the path ends at the message, so all of its descendants are regenerated
+    async fn activate_path(
+        conn: &mut PgConnection,
+        chat_id: Uuid,
+        user_id: &str,
+        message_id: Uuid,
+        descend: bool,
+    ) -> Result<bool> {
+        let messages = sqlx::query_as::<_, (Uuid, Option<Uuid>, bool)>(
+            r#"
+            SELECT id, parent_id, regenerated FROM messages
+            WHERE chat_id = $1 AND user_id = $2
+            ORDER BY created_at, id
+            FOR UPDATE
+            "#,
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        if !messages.iter().any(|(id, _, _)| *id == message_id) {
+            return Ok(false);
+        }
+
+        let parents: HashMap<Uuid, Option<Uuid>> = messages
+            .iter()
+            .map(|(id, parent_id, _)| (*id, *parent_id))
+            .collect();
+        let mut active: HashSet<Uuid> = HashSet::new();
+
+        // Up to the root. The set guards against cycles, parents are always older
+        let mut current = Some(message_id);
+        while let Some(id) = current {
+            if !active.insert(id) {
+                break;
+            }
+            current = parents.get(&id).copied().flatten();
+        }
+
+        // Down to a leaf, keeping alternatives that are already active where there are some
+        if descend {
+            let mut current = message_id;
+            loop {
+                let children: Vec<&(Uuid, Option<Uuid>, bool)> = messages
+                    .iter()
+                    .filter(|(_, parent_id, _)| *parent_id == Some(current))
+                    .collect();
+                let Some((next, _, _)) = children
+                    .iter()
+                    .find(|(_, _, regenerated)| !regenerated)
+                    .or(children.last())
+                else {
+                    break;
+                };
+                if !active.insert(*next) {
+                    break;
+                }
+                current = *next;
+            }
+        }
+
+        let active: Vec<Uuid> = active.into_iter().collect();
+        sqlx::query(
+            r#"
+            UPDATE messages
+            SET regenerated = NOT (id = ANY($2))
+            WHERE chat_id = $1 AND regenerated = (id = ANY($2))
+            "#,
+        )
+        .bind(chat_id)
+        .bind(&active)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(true)
+    }
+
     pub async fn upvote(pool: &PgPool, message_id: Uuid, user_id: &str) -> Result<()> {
         let query_str = r#"
             UPDATE messages
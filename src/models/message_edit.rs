@@ -0,0 +1,42 @@
+// models/message_edit.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The text a message had before one of its edits
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MessageEdit {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub user_id: String,
+    pub previous_text: String,
+    /// When the edit replacing `previous_text` was made
+    pub created_at: DateTime<Utc>,
+}
+
+impl MessageEdit {
+    /// Edits of a user's message, newest first
+    pub async fn get_for_message(
+        pool: &PgPool,
+        message_id: Uuid,
+        user_id: &str,
+    ) -> Result<Vec<Self>> {
+        let edits = sqlx::query_as::<_, MessageEdit>(
+            r#"
+            SELECT * FROM message_edits
+            WHERE message_id = $1 AND user_id = $2
+            ORDER BY created_at DESC, id DESC
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(edits)
+    }
+}
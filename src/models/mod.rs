@@ -1,17 +1,49 @@
+pub mod api_key;
 pub mod chat;
+pub mod chat_share;
 pub mod devent;
 pub mod file;
 pub mod invite;
+pub mod job;
+pub mod llm_model;
 pub mod memory;
+pub mod memory_embedding;
+pub mod memory_revision;
+pub mod memory_settings;
 pub mod message;
+pub mod message_edit;
+pub mod page_cursor;
+pub mod parse_stat;
+pub mod rate_limit;
 pub mod recordings;
+pub mod role;
+pub mod search;
+pub mod session;
+pub mod sync_change;
+pub mod usage;
 pub mod user;
 
+pub use api_key::ApiKey;
 pub use chat::Chat;
+pub use chat_share::{ChatShare, SharedChat, SharedMessage};
 pub use devent::Devent;
 pub use file::File;
 pub use invite::Invite;
+pub use job::{Job, JobCount, JobPayload, JobStatus};
+pub use llm_model::LlmModel;
 pub use memory::Memory;
+pub use memory_embedding::MemoryEmbedding;
+pub use memory_revision::{MemoryRevision, MemorySource};
+pub use memory_settings::{CustomMemoryGroup, MemorySettings, UserMemoryGroups};
 pub use message::Message;
+pub use message_edit::MessageEdit;
+pub use page_cursor::PageCursor;
+pub use parse_stat::{ParseOutcome, ParseStat};
+pub use rate_limit::{RateLimit, RateLimitPlan, UserRateLimit};
 pub use recordings::Recording;
+pub use role::{Role, UserRole};
+pub use search::SearchResult;
+pub use session::Session;
+pub use sync_change::{SyncBus, SyncChange, SyncCursor, SyncEvent};
+pub use usage::{MessageUsage, UsageDaily, UsageSummary};
 pub use user::User;
@@ -0,0 +1,36 @@
+// models/page_cursor.rs
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// A position in a list sorted by a timestamp, with the id breaking ties. Opaque to clients,
+/// serialized as `<timestamp in microseconds>_<id>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageCursor {
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.at.timestamp_micros(), self.id)
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (at, id) = s
+            .split_once('_')
+            .ok_or_else(|| anyhow!("Invalid cursor: {}", s))?;
+        Ok(PageCursor {
+            at: DateTime::from_timestamp_micros(at.parse()?)
+                .ok_or_else(|| anyhow!("Invalid cursor: {}", s))?,
+            id: id.parse()?,
+        })
+    }
+}
@@ -0,0 +1,76 @@
+// models/parse_stat.rs
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+
+/// How a structured LLM reply ended up being parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseOutcome {
+    Structured,
+    Fallback,
+    Failed,
+}
+
+/// Parse outcomes for one prompt on one day
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ParseStat {
+    pub day: NaiveDate,
+    pub prompt: String,
+    /// Parsed from the forced tool call
+    pub structured: i64,
+    /// Parsed from a plain text reply after the tool call was missing or invalid
+    pub fallback: i64,
+    /// Nothing usable either way
+    pub failed: i64,
+}
+
+impl ParseStat {
+    /// Count one outcome for a prompt, today
+    pub async fn record(pool: &PgPool, prompt: &str, outcome: ParseOutcome) -> Result<()> {
+        let (structured, fallback, failed) = match outcome {
+            ParseOutcome::Structured => (1_i64, 0_i64, 0_i64),
+            ParseOutcome::Fallback => (0, 1, 0),
+            ParseOutcome::Failed => (0, 0, 1),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO llm_parse_stats (day, prompt, structured, fallback, failed)
+            VALUES ((NOW() AT TIME ZONE 'UTC')::date, $1, $2, $3, $4)
+            ON CONFLICT (day, prompt) DO UPDATE SET
+                structured = llm_parse_stats.structured + EXCLUDED.structured,
+                fallback = llm_parse_stats.fallback + EXCLUDED.fallback,
+                failed = llm_parse_stats.failed + EXCLUDED.failed
+            "#,
+        )
+        .bind(prompt)
+        .bind(structured)
+        .bind(fallback)
+        .bind(failed)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Outcomes per prompt and day between two days, inclusive
+    pub async fn get_range(pool: &PgPool, start: NaiveDate, end: NaiveDate) -> Result<Vec<Self>> {
+        let stats = sqlx::query_as::<_, ParseStat>(
+            r#"
+            SELECT day, prompt, structured, fallback, failed
+            FROM llm_parse_stats
+            WHERE day BETWEEN $1 AND $2
+            ORDER BY day DESC, prompt
+            "#,
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(stats)
+    }
+}
@@ -0,0 +1,324 @@
+// models/rate_limit.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::debug;
+use utoipa::ToSchema;
+
+/// Plan users are on when they have no row in user_rate_limits
+pub const DEFAULT_PLAN: &str = "default";
+
+/// A named set of limits, NULL limits are unlimited
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct RateLimitPlan {
+    pub name: String,
+    pub requests_per_minute: Option<i32>,
+    /// How many requests can be made back to back, defaults to requests_per_minute
+    pub burst: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    /// When set, over-limit requests are served with this model instead of being rejected
+    pub downgrade_model: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A user's plan and per-user overrides of it
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserRateLimit {
+    pub user_id: String,
+    pub plan: String,
+    pub requests_per_minute: Option<i32>,
+    /// Without it, a requests_per_minute override also replaces the plan's burst
+    pub burst: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    pub downgrade_model: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The limits that apply to a user, their plan with overrides applied
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct RateLimit {
+    pub plan: String,
+    pub requests_per_minute: Option<i32>,
+    pub burst: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    pub downgrade_model: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Requests,
+    Tokens,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allow,
+    /// Over a limit, serve the request with this model instead
+    Downgrade(String),
+    /// Over a limit with no downgrade, retry after this many seconds
+    Reject {
+        kind: LimitKind,
+        retry_after_secs: u64,
+    },
+}
+
+impl RateLimit {
+    fn unlimited() -> Self {
+        RateLimit {
+            plan: DEFAULT_PLAN.to_string(),
+            requests_per_minute: None,
+            burst: None,
+            tokens_per_day: None,
+            downgrade_model: None,
+        }
+    }
+
+    /// Resolve a user's effective limits through the cache
+    pub async fn for_user(
+        pool: &PgPool,
+        user_id: &str,
+        rate_limit_cache: &Cache<String, RateLimit>,
+    ) -> Result<Self> {
+        if let Some(cached) = rate_limit_cache.get(user_id).await {
+            return Ok(cached);
+        }
+
+        let query_str = r#"
+            SELECT p.name AS plan,
+                COALESCE(u.requests_per_minute, p.requests_per_minute) AS requests_per_minute,
+                CASE
+                    WHEN u.burst IS NOT NULL THEN u.burst
+                    -- The plan's burst is sized for the plan's rate
+                    WHEN u.requests_per_minute IS NOT NULL THEN NULL
+                    ELSE p.burst
+                END AS burst,
+                COALESCE(u.tokens_per_day, p.tokens_per_day) AS tokens_per_day,
+                COALESCE(u.downgrade_model, p.downgrade_model) AS downgrade_model
+            FROM rate_limit_plans p
+            LEFT JOIN user_rate_limits u ON u.user_id = $1 AND u.plan = p.name
+            WHERE p.name = COALESCE((SELECT plan FROM user_rate_limits WHERE user_id = $1), $2)
+        "#;
+
+        // No default plan configured means no limits
+        let limit = sqlx::query_as::<_, RateLimit>(query_str)
+            .bind(user_id)
+            .bind(DEFAULT_PLAN)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or_else(Self::unlimited);
+
+        rate_limit_cache
+            .insert(user_id.to_string(), limit.clone())
+            .await;
+
+        Ok(limit)
+    }
+
+    /// Take a request from the user's bucket and check their daily token allowance
+    pub async fn check(&self, pool: &PgPool, user_id: &str) -> Result<RateLimitDecision> {
+        let mut exceeded = self.take_request(pool, user_id).await?;
+        if exceeded.is_none() {
+            exceeded = self.check_tokens(pool, user_id).await?;
+        }
+
+        let decision = match (exceeded, &self.downgrade_model) {
+            (None, _) => RateLimitDecision::Allow,
+            (Some(_), Some(model)) => RateLimitDecision::Downgrade(model.clone()),
+            (Some((kind, retry_after_secs)), None) => RateLimitDecision::Reject {
+                kind,
+                retry_after_secs,
+            },
+        };
+
+        if decision != RateLimitDecision::Allow {
+            debug!("Rate limited user {}: {:?}", user_id, decision);
+        }
+        Ok(decision)
+    }
+
+    /// Token bucket on requests per minute, refilled lazily. The refill and take happen in one
+    /// statement so concurrent requests can't both take the last token
+    async fn take_request(&self, pool: &PgPool, user_id: &str) -> Result<Option<(LimitKind, u64)>> {
+        let requests_per_minute = match self.requests_per_minute {
+            Some(requests_per_minute) => requests_per_minute,
+            None => return Ok(None),
+        };
+        if requests_per_minute <= 0 {
+            return Ok(Some((LimitKind::Requests, 60)));
+        }
+
+        let capacity = self.burst.unwrap_or(requests_per_minute).max(1) as f64;
+        let refill_per_sec = requests_per_minute as f64 / 60.0;
+
+        let taken = sqlx::query_scalar::<_, f64>(
+            r#"
+            INSERT INTO rate_limit_buckets (user_id, tokens, refilled_at)
+            VALUES ($1, $2 - 1, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET
+                tokens = LEAST($2, rate_limit_buckets.tokens + EXTRACT(EPOCH FROM NOW() - rate_limit_buckets.refilled_at) * $3) - 1,
+                refilled_at = NOW()
+            WHERE LEAST($2, rate_limit_buckets.tokens + EXTRACT(EPOCH FROM NOW() - rate_limit_buckets.refilled_at) * $3) >= 1
+            RETURNING tokens
+            "#,
+        )
+        .bind(user_id)
+        .bind(capacity)
+        .bind(refill_per_sec)
+        .fetch_optional(pool)
+        .await?;
+
+        if taken.is_some() {
+            return Ok(None);
+        }
+
+        let available = sqlx::query_scalar::<_, f64>(
+            r#"
+            SELECT LEAST($2, tokens + EXTRACT(EPOCH FROM NOW() - refilled_at) * $3)
+            FROM rate_limit_buckets
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(capacity)
+        .bind(refill_per_sec)
+        .fetch_one(pool)
+        .await?;
+
+        let retry_after_secs = ((1.0 - available).max(0.0) / refill_per_sec)
+            .ceil()
+            .max(1.0) as u64;
+        Ok(Some((LimitKind::Requests, retry_after_secs)))
+    }
+
+    /// Tokens used today, from the usage rollup, against the daily allowance. Resets at UTC midnight
+    async fn check_tokens(&self, pool: &PgPool, user_id: &str) -> Result<Option<(LimitKind, u64)>> {
+        let tokens_per_day = match self.tokens_per_day {
+            Some(tokens_per_day) => tokens_per_day,
+            None => return Ok(None),
+        };
+
+        let used = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COALESCE(SUM(input_tokens + output_tokens), 0)::BIGINT
+            FROM usage_daily
+            WHERE user_id = $1 AND day = (NOW() AT TIME ZONE 'UTC')::date
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        if used < tokens_per_day {
+            return Ok(None);
+        }
+
+        let now = Utc::now();
+        let midnight = (now.date_naive() + Duration::days(1))
+            .and_hms_opt(0, 0, 0)
+            .unwrap_or_default()
+            .and_utc();
+        let retry_after_secs = (midnight - now).num_seconds().max(1) as u64;
+        Ok(Some((LimitKind::Tokens, retry_after_secs)))
+    }
+}
+
+impl RateLimitPlan {
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        let plans =
+            sqlx::query_as::<_, RateLimitPlan>("SELECT * FROM rate_limit_plans ORDER BY name")
+                .fetch_all(pool)
+                .await?;
+
+        Ok(plans)
+    }
+
+    /// Insert or replace a plan. Every cached user limit may derive from it, so the whole cache is dropped
+    pub async fn upsert(
+        pool: &PgPool,
+        plan: &RateLimitPlan,
+        rate_limit_cache: &Cache<String, RateLimit>,
+    ) -> Result<Self> {
+        let query_str = r#"
+            INSERT INTO rate_limit_plans (name, requests_per_minute, burst, tokens_per_day, downgrade_model)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (name) DO UPDATE SET
+                requests_per_minute = EXCLUDED.requests_per_minute,
+                burst = EXCLUDED.burst,
+                tokens_per_day = EXCLUDED.tokens_per_day,
+                downgrade_model = EXCLUDED.downgrade_model
+            RETURNING *
+        "#;
+
+        let plan = sqlx::query_as::<_, RateLimitPlan>(query_str)
+            .bind(&plan.name)
+            .bind(plan.requests_per_minute)
+            .bind(plan.burst)
+            .bind(plan.tokens_per_day)
+            .bind(&plan.downgrade_model)
+            .fetch_one(pool)
+            .await?;
+
+        rate_limit_cache.invalidate_all();
+
+        debug!("Rate limit plan upserted: {:?}", plan);
+        Ok(plan)
+    }
+}
+
+impl UserRateLimit {
+    /// Put a user on a plan, with optional overrides
+    pub async fn upsert(
+        pool: &PgPool,
+        user_limit: &UserRateLimit,
+        rate_limit_cache: &Cache<String, RateLimit>,
+    ) -> Result<Self> {
+        let query_str = r#"
+            INSERT INTO user_rate_limits (user_id, plan, requests_per_minute, burst, tokens_per_day, downgrade_model)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id) DO UPDATE SET
+                plan = EXCLUDED.plan,
+                requests_per_minute = EXCLUDED.requests_per_minute,
+                burst = EXCLUDED.burst,
+                tokens_per_day = EXCLUDED.tokens_per_day,
+                downgrade_model = EXCLUDED.downgrade_model
+            RETURNING *
+        "#;
+
+        let user_limit = sqlx::query_as::<_, UserRateLimit>(query_str)
+            .bind(&user_limit.user_id)
+            .bind(&user_limit.plan)
+            .bind(user_limit.requests_per_minute)
+            .bind(user_limit.burst)
+            .bind(user_limit.tokens_per_day)
+            .bind(&user_limit.downgrade_model)
+            .fetch_one(pool)
+            .await?;
+
+        rate_limit_cache.invalidate(&user_limit.user_id).await;
+
+        debug!("User rate limit upserted: {:?}", user_limit);
+        Ok(user_limit)
+    }
+
+    /// Move a user back to the default plan
+    pub async fn delete(
+        pool: &PgPool,
+        user_id: &str,
+        rate_limit_cache: &Cache<String, RateLimit>,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM user_rate_limits WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        rate_limit_cache.invalidate(user_id).await;
+
+        debug!("User rate limit deleted: {}", user_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// models/role.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::debug;
+use utoipa::ToSchema;
+
+/// The role that implies every other role
+pub const ADMIN_ROLE: &str = "admin";
+/// Permission that grants every other permission
+pub const ALL_PERMISSIONS: &str = "*";
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    /// Permissions carried by the role, "*" for all of them
+    pub permissions: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserRole {
+    pub user_id: String,
+    pub role: String,
+    /// The admin who granted the role, None for seeded grants
+    pub granted_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Role {
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        let roles = sqlx::query_as::<_, Role>("SELECT * FROM roles ORDER BY name")
+            .fetch_all(pool)
+            .await?;
+
+        Ok(roles)
+    }
+
+    /// The roles held by a user, with their permissions
+    pub async fn get_for_user(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let roles = sqlx::query_as::<_, Role>(
+            r#"
+            SELECT r.*
+            FROM roles r
+            JOIN user_roles ur ON ur.role = r.name
+            WHERE ur.user_id = $1
+            ORDER BY r.name
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(roles)
+    }
+}
+
+impl UserRole {
+    /// Permissions carried by the roles a user holds, through the cache. Checked on every guarded request
+    pub async fn permissions(
+        pool: &PgPool,
+        user_id: &str,
+        role_cache: &Cache<String, Vec<String>>,
+    ) -> Result<Vec<String>> {
+        if let Some(cached) = role_cache.get(user_id).await {
+            return Ok(cached);
+        }
+
+        let permissions = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT DISTINCT UNNEST(r.permissions)
+            FROM roles r
+            JOIN user_roles ur ON ur.role = r.name
+            WHERE ur.user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        role_cache
+            .insert(user_id.to_string(), permissions.clone())
+            .await;
+
+        Ok(permissions)
+    }
+
+    pub async fn grant(
+        pool: &PgPool,
+        user_id: &str,
+        role: &str,
+        granted_by: &str,
+        role_cache: &Cache<String, Vec<String>>,
+    ) -> Result<Self> {
+        let user_role = sqlx::query_as::<_, UserRole>(
+            r#"
+            INSERT INTO user_roles (user_id, role, granted_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, role) DO UPDATE SET granted_by = EXCLUDED.granted_by
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(role)
+        .bind(granted_by)
+        .fetch_one(pool)
+        .await?;
+
+        role_cache.invalidate(user_id).await;
+
+        debug!("Role granted: {:?}", user_role);
+        Ok(user_role)
+    }
+
+    pub async fn revoke(
+        pool: &PgPool,
+        user_id: &str,
+        role: &str,
+        role_cache: &Cache<String, Vec<String>>,
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM user_roles WHERE user_id = $1 AND role = $2")
+            .bind(user_id)
+            .bind(role)
+            .execute(pool)
+            .await?;
+
+        role_cache.invalidate(user_id).await;
+
+        debug!("Role {} revoked from user {}", role, user_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+// models/search.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::message::Role;
+use crate::types::SearchQuery;
+
+/// Markers around the matched words in snippets. The rest of the snippet is the raw text,
+/// clients must escape it before rendering the markers as HTML
+const HEADLINE_OPTIONS: &str =
+    "StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15, MaxFragments=2";
+
+/// A message or chat name matching a search
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct SearchResult {
+    pub chat_id: Uuid,
+    pub chat_name: String,
+    /// None when the chat name matched
+    pub message_id: Option<Uuid>,
+    pub role: Option<Role>,
+    pub model_id: Option<String>,
+    /// Number of messages before this one in the chat, to scroll to it
+    pub position: Option<i64>,
+    /// The matching part of the text with the matched words in <mark> tags
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SearchResult {
+    /// Search a user's messages and chat names, best match first. Deleted chats and regenerated
+    /// messages are left out. Snippets and positions are only worked out for the returned page
+    pub async fn search(
+        pool: &PgPool,
+        user_id: &str,
+        query: &SearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Self>> {
+        let results = sqlx::query_as::<_, SearchResult>(
+            r#"
+            WITH query AS (
+                SELECT websearch_to_tsquery('english', $2) AS q
+            ),
+            matches AS (
+                SELECT m.chat_id, m.id AS message_id, m.role, m.model_id, m.text AS matched_text,
+                    m.created_at, ts_rank(to_tsvector('english', m.text), query.q) AS rank
+                FROM messages m
+                JOIN chats c ON c.id = m.chat_id
+                CROSS JOIN query
+                WHERE m.user_id = $1 AND m.regenerated = false AND c.deleted_at IS NULL
+                AND to_tsvector('english', m.text) @@ query.q
+                AND ($3::role_enum IS NULL OR m.role = $3)
+                AND ($4::TEXT IS NULL OR m.model_id = $4)
+                AND ($5::TIMESTAMPTZ IS NULL OR m.created_at >= $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR m.created_at < $6)
+                UNION ALL
+                SELECT c.id, NULL, NULL, NULL, c.name,
+                    c.created_at, ts_rank(to_tsvector('english', c.name), query.q)
+                FROM chats c
+                CROSS JOIN query
+                WHERE c.user_id = $1 AND c.deleted_at IS NULL
+                AND to_tsvector('english', c.name) @@ query.q
+                AND $3::role_enum IS NULL AND $4::TEXT IS NULL
+                AND ($5::TIMESTAMPTZ IS NULL OR c.created_at >= $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR c.created_at < $6)
+                ORDER BY rank DESC, created_at DESC
+                LIMIT $7 OFFSET $8
+            )
+            SELECT matches.chat_id, chats.name AS chat_name, matches.message_id, matches.role,
+                matches.model_id,
+                CASE WHEN matches.message_id IS NULL THEN NULL ELSE (
+                    SELECT COUNT(*) FROM messages p
+                    WHERE p.chat_id = matches.chat_id
+                    AND (p.created_at, p.id) < (matches.created_at, matches.message_id)
+                ) END AS position,
+                ts_headline('english', matches.matched_text, query.q, $9) AS snippet,
+                matches.rank, matches.created_at
+            FROM matches
+            JOIN chats ON chats.id = matches.chat_id
+            CROSS JOIN query
+            ORDER BY matches.rank DESC, matches.created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(&query.q)
+        .bind(&query.role)
+        .bind(&query.model_id)
+        .bind(query.after)
+        .bind(query.before)
+        .bind(limit)
+        .bind(offset)
+        .bind(HEADLINE_OPTIONS)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(results)
+    }
+}
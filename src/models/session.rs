@@ -0,0 +1,281 @@
+// models/session.rs
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use moka::future::Cache;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// How long access tokens live. Short, since they are checked against the sessions table only through a cache
+pub const ACCESS_TOKEN_TTL_SECS: usize = 3600;
+/// How long a refresh token lives, pushed back on every refresh so active devices stay signed in
+const REFRESH_TOKEN_TTL_DAYS: i64 = 60;
+const REFRESH_TOKEN_LEN: usize = 48;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    #[serde(skip_serializing)]
+    pub refresh_token_hash: String,
+    #[serde(skip_serializing)]
+    pub previous_refresh_token_hash: Option<String>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Session {
+    fn generate_refresh_token() -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(REFRESH_TOKEN_LEN)
+            .map(char::from)
+            .collect()
+    }
+
+    fn hash(refresh_token: &str) -> String {
+        hex::encode(Sha256::digest(refresh_token.as_bytes()))
+    }
+
+    /// Start a session for a login. Returns the plaintext refresh token, which is never stored
+    pub async fn create(
+        pool: &PgPool,
+        user_id: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+    ) -> Result<(Self, String)> {
+        let refresh_token = Self::generate_refresh_token();
+
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            INSERT INTO sessions (id, user_id, user_agent, ip_address, refresh_token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(user_agent)
+        .bind(ip_address)
+        .bind(Self::hash(&refresh_token))
+        .bind(Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS))
+        .fetch_one(pool)
+        .await?;
+
+        debug!("Session created: {:?}", session);
+        Ok((session, refresh_token))
+    }
+
+    /// Swap a refresh token for a new one. None if the token is unknown, expired or revoked.
+    /// Presenting an already rotated token revokes the whole session, since someone else has a copy of it
+    pub async fn rotate(
+        pool: &PgPool,
+        refresh_token: &str,
+        session_cache: &Cache<Uuid, bool>,
+    ) -> Result<Option<(Self, String)>> {
+        let refresh_token_hash = Self::hash(refresh_token);
+        let new_refresh_token = Self::generate_refresh_token();
+
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions
+            SET previous_refresh_token_hash = refresh_token_hash,
+                refresh_token_hash = $2,
+                expires_at = $3,
+                last_seen_at = NOW()
+            WHERE refresh_token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(&refresh_token_hash)
+        .bind(Self::hash(&new_refresh_token))
+        .bind(Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS))
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(session) = session {
+            return Ok(Some((session, new_refresh_token)));
+        }
+
+        let reused = sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE previous_refresh_token_hash = $1
+            RETURNING *
+            "#,
+        )
+        .bind(&refresh_token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(session) = reused {
+            warn!(
+                "Refresh token reused for session {} of user {}, revoking it",
+                session.id, session.user_id
+            );
+            session_cache.invalidate(&session.id).await;
+        }
+
+        Ok(None)
+    }
+
+    /// Whether access tokens for the session should still be accepted, through the cache
+    pub async fn is_active(
+        pool: &PgPool,
+        id: Uuid,
+        session_cache: &Cache<Uuid, bool>,
+    ) -> Result<bool> {
+        if let Some(active) = session_cache.get(&id).await {
+            return Ok(active);
+        }
+
+        let active = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM sessions
+                WHERE id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            )
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+
+        session_cache.insert(id, active).await;
+
+        Ok(active)
+    }
+
+    /// When the user last revoked all sessions, legacy tokens issued before it aren't accepted.
+    /// None if they never did
+    pub async fn legacy_tokens_valid_after(
+        pool: &PgPool,
+        user_id: &str,
+        legacy_token_cache: &Cache<String, Option<DateTime<Utc>>>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        if let Some(valid_after) = legacy_token_cache.get(user_id).await {
+            return Ok(valid_after);
+        }
+
+        let valid_after = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT tokens_valid_after FROM legacy_token_cutoffs WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        legacy_token_cache
+            .insert(user_id.to_string(), valid_after)
+            .await;
+
+        Ok(valid_after)
+    }
+
+    /// Record that the session was used, at most once a minute so busy clients don't write on every request
+    pub async fn touch(pool: &PgPool, id: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE sessions SET last_seen_at = NOW()
+            WHERE id = $1 AND last_seen_at < NOW() - INTERVAL '1 minute'
+            "#,
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The user's sessions that can still be refreshed, most recently used first
+    pub async fn get_active_for_user(pool: &PgPool, user_id: &str) -> Result<Vec<Self>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            r#"
+            SELECT * FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Revoke one of the user's sessions, None if they have no such session
+    pub async fn revoke(
+        pool: &PgPool,
+        id: Uuid,
+        user_id: &str,
+        session_cache: &Cache<Uuid, bool>,
+    ) -> Result<Option<Self>> {
+        let session = sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND user_id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(session) = &session {
+            session_cache.invalidate(&session.id).await;
+            debug!("Session revoked: {:?}", session);
+        }
+        Ok(session)
+    }
+
+    /// Revoke every session of the user, returning how many were revoked. Legacy tokens issued
+    /// until now stop working too
+    pub async fn revoke_all_for_user(
+        pool: &PgPool,
+        user_id: &str,
+        session_cache: &Cache<Uuid, bool>,
+        legacy_token_cache: &Cache<String, Option<DateTime<Utc>>>,
+    ) -> Result<usize> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO legacy_token_cutoffs (user_id, tokens_valid_after)
+            VALUES ($1, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET tokens_valid_after = EXCLUDED.tokens_valid_after
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE sessions SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        for id in &ids {
+            session_cache.invalidate(id).await;
+        }
+        legacy_token_cache.invalidate(user_id).await;
+
+        debug!("Revoked {} sessions for user {}", ids.len(), user_id);
+        Ok(ids.len())
+    }
+}
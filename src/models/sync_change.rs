@@ -0,0 +1,273 @@
+// models/sync_change.rs
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The Postgres channel the change log triggers NOTIFY on
+const SYNC_CHANNEL: &str = "sync_changes";
+/// Events buffered per user before their slowest stream lags and is told to resync
+const USER_EVENTS_CAPACITY: usize = 256;
+/// Changes deleted per transaction when pruning the log
+const PRUNE_BATCH_SIZE: i64 = 10000;
+
+pub const CHAT_ENTITY: &str = "chat";
+pub const MESSAGE_ENTITY: &str = "message";
+pub const FILE_ENTITY: &str = "file";
+pub const MEMORY_ENTITY: &str = "memory";
+
+/// A position in a user's change log. Opaque to clients, serialized as `<txid>-<id>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SyncCursor {
+    pub txid: i64,
+    pub id: i64,
+}
+
+impl fmt::Display for SyncCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.txid, self.id)
+    }
+}
+
+impl FromStr for SyncCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (txid, id) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid sync cursor: {}", s))?;
+        Ok(SyncCursor {
+            txid: txid.parse()?,
+            id: id.parse()?,
+        })
+    }
+}
+
+/// A committed write to a synced table, as published by the change log triggers
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SyncEvent {
+    #[serde(skip_serializing)]
+    pub user_id: String,
+    /// chat, message, file or memory
+    pub entity: String,
+    pub id: Uuid,
+    /// created, updated or deleted
+    pub action: String,
+    /// Position of the change in the log, pass as `until` to /sync/changes to fetch up to it
+    pub cursor: String,
+}
+
+/// In-process fan-out of sync events, one channel per user with an open stream, so a burst of
+/// writes for one user only lags that user's streams
+#[derive(Clone, Default)]
+pub struct SyncBus {
+    senders: Arc<Mutex<HashMap<String, broadcast::Sender<SyncEvent>>>>,
+}
+
+impl SyncBus {
+    pub fn subscribe(&self, user_id: &str) -> broadcast::Receiver<SyncEvent> {
+        let mut senders = self.senders.lock().unwrap();
+        senders
+            .entry(user_id.to_string())
+            .or_insert_with(|| broadcast::channel(USER_EVENTS_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Deliver an event to the user's streams on this instance. The user's channel is dropped once
+    /// nobody listens on it
+    pub fn publish(&self, event: SyncEvent) {
+        let mut senders = self.senders.lock().unwrap();
+        if let Some(sender) = senders.get(&event.user_id) {
+            let user_id = event.user_id.clone();
+            if sender.send(event).is_err() {
+                senders.remove(&user_id);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct SyncChange {
+    pub id: i64,
+    pub txid: i64,
+    pub user_id: String,
+    pub entity: String,
+    pub entity_id: Uuid,
+    pub deleted: bool,
+    pub created_at: DateTime<Utc>,
+    /// Older than every in-flight transaction, so nothing can still land before it
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub settled: bool,
+}
+
+impl SyncChange {
+    pub fn cursor(&self) -> SyncCursor {
+        SyncCursor {
+            txid: self.txid,
+            id: self.id,
+        }
+    }
+
+    /// The user's changes after the cursor, oldest first. Changes from transactions that might still have
+    /// in-flight siblings are held back until those finish, so cursors only ever move past settled history.
+    /// Committed changes up to `until`, the cursor of an event, are returned early but not marked settled
+    pub async fn get_since(
+        pool: &PgPool,
+        user_id: &str,
+        since: SyncCursor,
+        until: Option<SyncCursor>,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let changes = sqlx::query_as::<_, SyncChange>(
+            r#"
+            SELECT *, txid < txid_snapshot_xmin(txid_current_snapshot()) AS settled
+            FROM sync_changes
+            WHERE user_id = $1
+                AND (txid, id) > ($2, $3)
+                AND (
+                    txid < txid_snapshot_xmin(txid_current_snapshot())
+                    OR (txid, id) <= ($5, $6)
+                )
+            ORDER BY txid, id
+            LIMIT $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(since.txid)
+        .bind(since.id)
+        .bind(limit)
+        .bind(until.map(|until| until.txid))
+        .bind(until.map(|until| until.id))
+        .fetch_all(pool)
+        .await?;
+
+        Ok(changes)
+    }
+
+    /// The cursor a client should resume from after a full sync read now.
+    /// Settled history only, anything later is delivered again by /sync/changes, which is harmless
+    pub async fn current_cursor(pool: &PgPool, user_id: &str) -> Result<SyncCursor> {
+        let cursor = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+            SELECT txid, id FROM sync_changes
+            WHERE user_id = $1 AND txid < txid_snapshot_xmin(txid_current_snapshot())
+            ORDER BY txid DESC, id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        let cursor = cursor
+            .map(|(txid, id)| SyncCursor { txid, id })
+            .unwrap_or_default();
+        // A user without recent changes resumes from the pruned horizon, so the next read isn't rejected as stale
+        Ok(cursor.max(Self::pruned_through(pool).await?))
+    }
+
+    /// The newest pruned position. Cursors before it may have missed deleted changes and need a full sync
+    pub async fn pruned_through(pool: &PgPool) -> Result<SyncCursor> {
+        let cursor = sqlx::query_as::<_, (i64, i64)>("SELECT txid, id FROM sync_changes_pruned")
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(cursor
+            .map(|(txid, id)| SyncCursor { txid, id })
+            .unwrap_or_default())
+    }
+
+    /// Delete settled changes created before `older_than` in batches, moving the pruned horizon along.
+    /// Returns the number of changes deleted
+    pub async fn prune(pool: &PgPool, older_than: DateTime<Utc>) -> Result<u64> {
+        let mut pruned = 0;
+        loop {
+            let mut tx = pool.begin().await?;
+            let (deleted, horizon) = sqlx::query_as::<_, (i64, Option<i64>, Option<i64>)>(
+                r#"
+                WITH deleted AS (
+                    DELETE FROM sync_changes
+                    WHERE id IN (
+                        SELECT id FROM sync_changes
+                        WHERE created_at < $1 AND txid < txid_snapshot_xmin(txid_current_snapshot())
+                        ORDER BY txid, id
+                        LIMIT $2
+                    )
+                    RETURNING txid, id
+                ),
+                newest AS (
+                    SELECT txid, id FROM deleted ORDER BY txid DESC, id DESC LIMIT 1
+                )
+                SELECT (SELECT COUNT(*) FROM deleted), newest.txid, newest.id
+                FROM (SELECT 1) AS one LEFT JOIN newest ON TRUE
+                "#,
+            )
+            .bind(older_than)
+            .bind(PRUNE_BATCH_SIZE)
+            .fetch_one(&mut *tx)
+            .await
+            .map(|(deleted, txid, id)| (deleted, txid.zip(id)))?;
+
+            if let Some((txid, id)) = horizon {
+                sqlx::query(
+                    r#"
+                    INSERT INTO sync_changes_pruned (txid, id) VALUES ($1, $2)
+                    ON CONFLICT (singleton) DO UPDATE SET txid = EXCLUDED.txid, id = EXCLUDED.id
+                    WHERE (sync_changes_pruned.txid, sync_changes_pruned.id) < (EXCLUDED.txid, EXCLUDED.id)
+                    "#,
+                )
+                .bind(txid)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+
+            pruned += deleted as u64;
+            if deleted < PRUNE_BATCH_SIZE {
+                return Ok(pruned);
+            }
+        }
+    }
+
+    /// Forward every sync event published by any instance to the in-process bus, reconnecting forever
+    pub async fn listen(pool: PgPool, bus: SyncBus) {
+        loop {
+            if let Err(e) = Self::forward_notifications(&pool, &bus).await {
+                error!("Sync listener failed, reconnecting: {:?}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn forward_notifications(pool: &PgPool, bus: &SyncBus) -> Result<()> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(SYNC_CHANNEL).await?;
+        info!("Listening for sync events");
+
+        loop {
+            // None means the connection dropped, anything published meanwhile is only visible through /sync/changes
+            let Some(notification) = listener.try_recv().await? else {
+                warn!("Sync listener lost its connection, events may have been missed");
+                continue;
+            };
+
+            match serde_json::from_str::<SyncEvent>(notification.payload()) {
+                Ok(event) => bus.publish(event),
+                Err(e) => error!("Failed to parse sync event: {:?}", e),
+            }
+        }
+    }
+}
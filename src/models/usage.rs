@@ -0,0 +1,159 @@
+// models/usage.rs
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::debug;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Token counts, cost and timings for a single assistant message
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct MessageUsage {
+    pub message_id: Uuid,
+    pub user_id: String,
+    /// The model alias the client requested
+    pub model: String,
+    /// The model that actually served the completion, after routing and fallback
+    pub upstream_model: String,
+    pub input_tokens: i32,
+    pub output_tokens: i32,
+    /// True when the upstream didn't report usage and the counts were estimated with a tokenizer
+    pub estimated: bool,
+    pub cost_usd: f64,
+    /// Milliseconds from the request arriving to the first streamed token, None when not streamed
+    pub ttft_ms: Option<i32>,
+    pub latency_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One user's usage of one model on one day
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UsageDaily {
+    pub user_id: String,
+    pub day: NaiveDate,
+    pub model: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cost_usd: f64,
+}
+
+/// A user's usage summed over a range of days, for the admin overview
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UsageSummary {
+    pub user_id: String,
+    pub requests: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cost_usd: f64,
+}
+
+impl MessageUsage {
+    /// Store the usage for a message and add it to the user's daily rollup, in one transaction
+    pub async fn record(pool: &PgPool, usage: &MessageUsage) -> Result<()> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO message_usage (message_id, user_id, model, upstream_model, input_tokens, output_tokens, estimated, cost_usd, ttft_ms, latency_ms, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+        )
+        .bind(usage.message_id)
+        .bind(&usage.user_id)
+        .bind(&usage.model)
+        .bind(&usage.upstream_model)
+        .bind(usage.input_tokens)
+        .bind(usage.output_tokens)
+        .bind(usage.estimated)
+        .bind(usage.cost_usd)
+        .bind(usage.ttft_ms)
+        .bind(usage.latency_ms)
+        .bind(usage.created_at)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO usage_daily (user_id, day, model, requests, input_tokens, output_tokens, cost_usd)
+            VALUES ($1, ($2 AT TIME ZONE 'UTC')::date, $3, 1, $4, $5, $6)
+            ON CONFLICT (user_id, day, model) DO UPDATE SET
+                requests = usage_daily.requests + 1,
+                input_tokens = usage_daily.input_tokens + EXCLUDED.input_tokens,
+                output_tokens = usage_daily.output_tokens + EXCLUDED.output_tokens,
+                cost_usd = usage_daily.cost_usd + EXCLUDED.cost_usd
+            "#,
+        )
+        .bind(&usage.user_id)
+        .bind(usage.created_at)
+        .bind(&usage.model)
+        .bind(usage.input_tokens as i64)
+        .bind(usage.output_tokens as i64)
+        .bind(usage.cost_usd)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        debug!("Usage recorded: {:?}", usage);
+        Ok(())
+    }
+}
+
+impl UsageDaily {
+    /// Daily rows for a user between two days, inclusive, newest first
+    pub async fn get_for_user(
+        pool: &PgPool,
+        user_id: &str,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Vec<Self>> {
+        let usage = sqlx::query_as::<_, UsageDaily>(
+            r#"
+            SELECT user_id, day, model, requests, input_tokens, output_tokens, cost_usd
+            FROM usage_daily
+            WHERE user_id = $1 AND day BETWEEN $2 AND $3
+            ORDER BY day DESC, model
+            "#,
+        )
+        .bind(user_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    /// Usage per user between two days, inclusive, biggest spenders first
+    pub async fn summarize_by_user(
+        pool: &PgPool,
+        start: NaiveDate,
+        end: NaiveDate,
+        limit: i64,
+    ) -> Result<Vec<UsageSummary>> {
+        let summaries = sqlx::query_as::<_, UsageSummary>(
+            r#"
+            SELECT user_id,
+                SUM(requests)::BIGINT AS requests,
+                SUM(input_tokens)::BIGINT AS input_tokens,
+                SUM(output_tokens)::BIGINT AS output_tokens,
+                SUM(cost_usd) AS cost_usd
+            FROM usage_daily
+            WHERE day BETWEEN $1 AND $2
+            GROUP BY user_id
+            ORDER BY SUM(cost_usd) DESC, SUM(input_tokens + output_tokens) DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(start)
+        .bind(end)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(summaries)
+    }
+}
@@ -609,4 +609,10 @@ After I add a new record, I need to refresh my screen to see the updated record.
     {0}
     </message>
     "###;
+
+    pub const SUMMARIZE_DROPPED_TURNS: &'static str = r###"The following is the beginning of a conversation between a user and an AI assistant that no longer fits in the assistant's context window. Summarize it in a few short paragraphs so the assistant can continue the conversation. Keep names, decisions, open questions, and any code or facts the user may refer back to. Return only the summary.
+
+<conversation>
+{0}
+</conversation>"###;
 }
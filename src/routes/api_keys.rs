@@ -0,0 +1,134 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use std::sync::Arc;
+use tracing::{error, info};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::api_key::ApiKeyScope;
+use crate::models::ApiKey;
+use crate::types::{CreateApiKeyRequest, CreateApiKeyResponse, UpdateApiKeyRequest};
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_api_key, list_api_keys, update_api_key, revoke_api_key),
+    components(schemas(
+        ApiKey,
+        ApiKeyScope,
+        CreateApiKeyRequest,
+        CreateApiKeyResponse,
+        UpdateApiKeyRequest
+    ))
+)]
+pub struct ApiDoc;
+
+/// Create an API key for the authenticated user. The key is only returned once
+#[utoipa::path(
+    post,
+    request_body = CreateApiKeyRequest,
+    responses((status = 200, description = "The new key and its metadata", body = CreateApiKeyResponse, content_type = "application/json"))
+)]
+#[post("")]
+async fn create_api_key(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    web::Json(req_body): web::Json<CreateApiKeyRequest>,
+) -> Result<web::Json<CreateApiKeyResponse>, actix_web::Error> {
+    let scopes = req_body.scopes.unwrap_or_default();
+
+    let (api_key, key) = ApiKey::create(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        &req_body.name,
+        &scopes,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to create API key: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    info!(
+        "User {} created API key {}",
+        authenticated_user.user_id, api_key.id
+    );
+    Ok(web::Json(CreateApiKeyResponse { key, api_key }))
+}
+
+/// List the authenticated user's API keys, including revoked ones
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "The user's API keys", body = Vec<ApiKey>, content_type = "application/json"))
+)]
+#[get("")]
+async fn list_api_keys(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<Vec<ApiKey>>, actix_web::Error> {
+    let api_keys = ApiKey::get_all_for_user(&app_state.pool, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get API keys: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(api_keys))
+}
+
+/// Rename one of the authenticated user's API keys
+#[utoipa::path(
+    put,
+    request_body = UpdateApiKeyRequest,
+    responses((status = 200, description = "The renamed key", body = ApiKey, content_type = "application/json"))
+)]
+#[put("/{id}")]
+async fn update_api_key(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    web::Json(req_body): web::Json<UpdateApiKeyRequest>,
+) -> Result<web::Json<ApiKey>, actix_web::Error> {
+    let api_key = ApiKey::rename(
+        &app_state.pool,
+        *id,
+        &authenticated_user.user_id,
+        &req_body.name,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to rename API key {}: {:?}", id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("API key not found"))?;
+
+    Ok(web::Json(api_key))
+}
+
+/// Revoke one of the authenticated user's API keys
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "API key revoked"))
+)]
+#[delete("/{id}")]
+async fn revoke_api_key(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    ApiKey::revoke(
+        &app_state.pool,
+        *id,
+        &authenticated_user.user_id,
+        &app_state.api_key_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke API key {}: {:?}", id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("API key not found"))?;
+
+    info!("User {} revoked API key {}", authenticated_user.user_id, id);
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,7 +1,9 @@
 use actix_web::{
-    get,
+    delete, get,
+    http::header::USER_AGENT,
+    post,
     web::{self, Json},
-    Error, Responder,
+    Error, HttpRequest, HttpResponse, Responder,
 };
 use chrono::Utc;
 use futures::stream::{FuturesUnordered, StreamExt};
@@ -9,22 +11,48 @@ use jsonwebtoken::{encode, EncodingKey, Header};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sqlx::PgPool;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tracing::{error, info, warn};
 use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
 
-use crate::models::User;
+use crate::middleware::roles::{RequirePermission, UsersRead, UsersSync};
+use crate::models::session::ACCESS_TOKEN_TTL_SECS;
+use crate::models::{Session, User};
 use crate::types::{
-    AuthCallbackQuery, Claims, GetUserResponse, WorkOSAuthRequest, WorkOSAuthResponse, WorkOSUser,
+    AuthCallbackQuery, Claims, GetUserResponse, ListSessionsResponse, WorkOSAuthRequest,
+    WorkOSAuthResponse, WorkOSUser,
 };
 use crate::AppState;
-use crate::{middleware::auth::AuthenticatedUser, AppConfig};
+use crate::{
+    middleware::auth::{AuthMethod, AuthenticatedUser},
+    AppConfig,
+};
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(login, signup, refresh_token, get_user,),
-    components(schemas(GetUserResponse, WorkOSAuthRequest, WorkOSAuthResponse, WorkOSUser))
+    paths(
+        login,
+        signup,
+        refresh_token,
+        rotate_refresh_token,
+        get_user,
+        list_sessions,
+        revoke_session,
+        revoke_all_sessions
+    ),
+    components(schemas(
+        GetUserResponse,
+        ListSessionsResponse,
+        RefreshTokenRequest,
+        RefreshTokenResponse,
+        Session,
+        WorkOSAuthRequest,
+        WorkOSAuthResponse,
+        WorkOSUser
+    ))
 )]
 pub struct ApiDoc;
 
@@ -53,7 +81,9 @@ async fn signup() -> Result<impl Responder, Error> {
 /// The callback URL for the WorkOS authentication flow for the desktop app
 #[get("/workos/callback")]
 async fn auth_callback(
+    req: HttpRequest,
     app_config: web::Data<Arc<AppConfig>>,
+    app_state: web::Data<Arc<AppState>>,
     info: web::Query<AuthCallbackQuery>,
 ) -> Result<impl Responder, actix_web::Error> {
     let code = &info.code;
@@ -62,19 +92,23 @@ async fn auth_callback(
         .await
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
-    // Sign a JWT with the user info
-    let jwt = sign_jwt(&auth_response.user, app_config.get_ref().clone())
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    // Start a session and sign an access token for it
+    let tokens = start_session(&req, &app_state, &app_config, &auth_response.user.id).await?;
 
-    // Redirect to the invisibility deep link with the JWT
-    let redirect_url = format!("invisibility://auth_callback?token={}", jwt);
+    // Redirect to the invisibility deep link with the tokens
+    let redirect_url = format!(
+        "invisibility://auth_callback?token={}&refresh_token={}",
+        tokens.token, tokens.refresh_token
+    );
     Ok(web::Redirect::to(redirect_url))
 }
 
 /// The callback URL for the WorkOS authentication flow for the web app
 #[get("/workos/callback_nextweb")]
 async fn auth_callback_nextweb(
+    req: HttpRequest,
     app_config: web::Data<Arc<AppConfig>>,
+    app_state: web::Data<Arc<AppState>>,
     info: web::Query<AuthCallbackQuery>,
 ) -> Result<impl Responder, actix_web::Error> {
     let code = &info.code;
@@ -83,41 +117,152 @@ async fn auth_callback_nextweb(
         .await
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
-    // Sign a JWT with the user info
-    let jwt = sign_jwt(&auth_response.user, app_config.get_ref().clone())
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    // Start a session and sign an access token for it
+    let tokens = start_session(&req, &app_state, &app_config, &auth_response.user.id).await?;
 
-    // Redirect to the invisibility deep link with the JWT
-    let redirect_url = format!("https://chat.i.inc/auth_callback?token={}", jwt);
+    // Redirect to the invisibility deep link with the tokens
+    let redirect_url = format!(
+        "https://chat.i.inc/auth_callback?token={}&refresh_token={}",
+        tokens.token, tokens.refresh_token
+    );
     Ok(web::Redirect::to(redirect_url))
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 struct RefreshTokenResponse {
+    /// Short-lived access token, send it as the Bearer token
     token: String,
+    /// Exchange it at POST /auth/token/refresh for a new pair. Single use
+    refresh_token: String,
+}
+
+/// The token pair of a new session
+struct SessionTokens {
+    token: String,
+    refresh_token: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct RefreshTokenRequest {
+    refresh_token: String,
 }
 
-/// Refresh the token for an authenticated user, really just generates a new token
+/// Start a session for a user and sign its first access token
+async fn start_session(
+    req: &HttpRequest,
+    app_state: &AppState,
+    app_config: &AppConfig,
+    user_id: &str,
+) -> Result<SessionTokens, Error> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let ip_address = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(|addr| addr.to_string());
+
+    let (session, refresh_token) =
+        Session::create(&app_state.pool, user_id, user_agent, ip_address.as_deref())
+            .await
+            .map_err(|e| {
+                error!("Failed to create session: {:?}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?;
+
+    let token = sign_jwt(user_id, session.id, app_config)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+
+    Ok(SessionTokens {
+        token,
+        refresh_token,
+    })
+}
+
+/// Swap a token issued before sessions existed for a new session and a token pair. Session tokens
+/// are refreshed with POST /auth/token/refresh and their refresh token, and API keys can't start
+/// sessions. To be removed once the legacy tokens have expired
 #[utoipa::path(
     get,
-    responses((status = 200, description = "Refreshed token for user", body = RefreshTokenResponse, content_type = "application/json"))
+    responses(
+        (status = 200, description = "New access and refresh token", body = RefreshTokenResponse, content_type = "application/json"),
+        (status = 400, description = "Not a legacy token, refresh with POST /auth/token/refresh"),
+        (status = 403, description = "Authenticated with an API key")
+    )
 )]
 #[get("/token/refresh")]
 async fn refresh_token(
+    req: HttpRequest,
     authenticated_user: AuthenticatedUser,
     app_config: web::Data<Arc<AppConfig>>,
+    app_state: web::Data<Arc<AppState>>,
 ) -> Result<Json<RefreshTokenResponse>, Error> {
+    // An access token alone mustn't extend a session, that takes the rotating refresh token
+    match authenticated_user.method {
+        AuthMethod::LegacyJwt => {}
+        AuthMethod::Session => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Session tokens are refreshed with POST /auth/token/refresh",
+            ));
+        }
+        AuthMethod::ApiKey => {
+            return Err(actix_web::error::ErrorForbidden(
+                "API keys can't start sessions",
+            ));
+        }
+    }
+
     let user_id = authenticated_user.user_id.as_ref();
     let workos_user = user_id_to_user(user_id, app_config.get_ref().clone())
         .await
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
-    // Sign a JWT with the user info
-    let jwt = sign_jwt(&workos_user, app_config.get_ref().clone())
+    let tokens = start_session(&req, &app_state, &app_config, &workos_user.id).await?;
+
+    info!("Moved user {} to a session", workos_user.email);
+    Ok(web::Json(RefreshTokenResponse {
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+    }))
+}
+
+/// Exchange a refresh token for a new access token and refresh token. The old refresh token stops working,
+/// and presenting it again revokes the session
+#[utoipa::path(
+    post,
+    request_body = RefreshTokenRequest,
+    responses(
+        (status = 200, description = "New access and refresh token", body = RefreshTokenResponse, content_type = "application/json"),
+        (status = 401, description = "Refresh token unknown, expired or revoked")
+    )
+)]
+#[post("/token/refresh")]
+async fn rotate_refresh_token(
+    app_config: web::Data<Arc<AppConfig>>,
+    app_state: web::Data<Arc<AppState>>,
+    web::Json(req_body): web::Json<RefreshTokenRequest>,
+) -> Result<Json<RefreshTokenResponse>, Error> {
+    let (session, refresh_token) = Session::rotate(
+        &app_state.pool,
+        &req_body.refresh_token,
+        &app_state.session_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to rotate refresh token: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorUnauthorized("Invalid refresh token"))?;
+
+    let token = sign_jwt(&session.user_id, session.id, &app_config)
         .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
 
-    info!("Refreshed token for user {}", workos_user.email);
-    Ok(web::Json(RefreshTokenResponse { token: jwt }))
+    info!("Refreshed token for session {}", session.id);
+    Ok(web::Json(RefreshTokenResponse {
+        token,
+        refresh_token,
+    }))
 }
 
 /// Get the user information for the authenticated user
@@ -139,147 +284,220 @@ async fn get_user(
     Ok(web::Json(workos_user?))
 }
 
-/// Get all users, but only if the authenticated user is an admin
+/// List the authenticated user's active sessions, most recently used first
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "The user's sessions", body = ListSessionsResponse, content_type = "application/json"))
+)]
+#[get("/sessions")]
+async fn list_sessions(
+    authenticated_user: AuthenticatedUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<Json<ListSessionsResponse>, Error> {
+    let sessions = Session::get_active_for_user(&app_state.pool, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get sessions: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(ListSessionsResponse {
+        current_session_id: authenticated_user.session_id,
+        sessions,
+    }))
+}
+
+/// Revoke one of the authenticated user's sessions, signing that device out
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "Session revoked"))
+)]
+#[delete("/sessions/{id}")]
+async fn revoke_session(
+    authenticated_user: AuthenticatedUser,
+    app_state: web::Data<Arc<AppState>>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    Session::revoke(
+        &app_state.pool,
+        *id,
+        &authenticated_user.user_id,
+        &app_state.session_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke session {}: {:?}", id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Session not found"))?;
+
+    info!("User {} revoked session {}", authenticated_user.user_id, id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Revoke every session of the authenticated user, including the current one
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "All sessions revoked"))
+)]
+#[delete("/sessions")]
+async fn revoke_all_sessions(
+    authenticated_user: AuthenticatedUser,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, Error> {
+    let revoked = Session::revoke_all_for_user(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        &app_state.session_cache,
+        &app_state.legacy_token_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke sessions: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    info!(
+        "User {} revoked all {} sessions",
+        authenticated_user.user_id, revoked
+    );
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Get all users, needs users:read
 #[get("/users")]
 async fn get_users(
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<UsersRead>,
     app_config: web::Data<Arc<AppConfig>>,
 ) -> Result<Json<Vec<WorkOSUser>>, Error> {
-    if authenticated_user.is_admin() {
-        let users = fetch_all_users(app_config.get_ref().clone())
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
-        Ok(web::Json(users))
-    } else {
-        Err(actix_web::error::ErrorForbidden("You are not an admin"))
-    }
+    let users = fetch_all_users(app_config.get_ref().clone())
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    Ok(web::Json(users))
 }
 
 /// Get all users from workos, async then get, create, or update them into the database
 #[get("/users/sync/workos")]
 async fn sync_users_workos(
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<UsersSync>,
     app_config: web::Data<Arc<AppConfig>>,
     app_state: web::Data<Arc<AppState>>,
 ) -> Result<Json<Vec<User>>, Error> {
-    if authenticated_user.is_admin() {
-        let workos_users = fetch_all_users(app_config.get_ref().clone())
-            .await
-            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
-
-        match User::get_or_create_or_update_bulk_workos(&app_state.pool, workos_users).await {
-            Ok(users) => Ok(web::Json(users)),
-            Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string())),
-        }
-    } else {
-        Err(actix_web::error::ErrorForbidden("You are not an admin"))
-    }
+    sync_workos_users(app_config.get_ref().clone(), &app_state.pool)
+        .await
+        .map(web::Json)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
 }
 
 /// Get all users, async PATCH them all to KeywordsAI API
 #[get("/users/sync/keywords")]
 async fn sync_users_keywords(
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<UsersSync>,
     app_config: web::Data<Arc<AppConfig>>,
     app_state: web::Data<Arc<AppState>>,
 ) -> Result<Json<Vec<User>>, Error> {
-    if authenticated_user.is_admin() {
-        match User::get_all(&app_state.pool).await {
-            Ok(users) => {
-                let mut users_to_process: Vec<_> = users
-                    .clone()
-                    .into_iter()
-                    .filter(|user| !user.linked_to_keywords)
-                    .collect();
-                let shared_client = Arc::new(Client::new());
-                let futures = FuturesUnordered::new();
-                info!(
-                    "Attempting to sync {} users to KeywordsAI",
-                    users_to_process.len()
-                );
-
-                let max_concurrent_requests = 50;
-                let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
-                for user in users_to_process.iter() {
-                    // Wait until we can acquire a permit
-                    _ = semaphore.clone().acquire_owned().await.map_err(|e| {
-                        error!("Failed to acquire semaphore: {}", e);
-                        actix_web::error::ErrorInternalServerError(e.to_string())
-                    })?;
-
-                    if !user.linked_to_keywords {
-                        let user_id = user.id.clone();
-                        let user_email = user.email.clone();
-                        let user_name = user.full_name();
-                        let client_clone = Arc::clone(&shared_client);
-                        let api_key = app_config.keywords_api_key.clone();
-
-                        futures.push(async move {
-                            let url =
-                                format!("https://api.keywordsai.co/api/user/update/{}", user_id);
-
-                            let response = client_clone
-                                .patch(&url)
-                                .bearer_auth(&api_key)
-                                .json(&json!({
-                                    "name": user_name,
-                                    "email": user_email,
-                                }))
-                                .send()
-                                .await;
-
-                            match response {
-                                Ok(resp) => {
-                                    if resp.status().is_success() {
-                                        info!("User {} linked to KeywordsAI", user_email);
-                                        Ok(user_id)
-                                    } else {
-                                        let error_body = resp.text().await.unwrap_or_else(|_| {
-                                            "Failed to read response body".to_string()
-                                        });
-                                        warn!("Error response from KeywordsAI: {}", error_body);
-                                        Err(())
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("HTTP request error: {}", e);
-                                    Err(())
-                                }
-                            }
-                        });
-                    }
-                }
+    sync_keywords_users(&app_config, &app_state.pool)
+        .await
+        .map(web::Json)
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+}
 
-                let results: Vec<Result<String, ()>> = futures.collect().await;
+/// Get, create, or update every WorkOS user in the database. Shared by the endpoint and the sync job
+pub async fn sync_workos_users(
+    app_config: Arc<AppConfig>,
+    pool: &PgPool,
+) -> Result<Vec<User>, anyhow::Error> {
+    let workos_users = fetch_all_users(app_config).await?;
+    User::get_or_create_or_update_bulk_workos(pool, workos_users).await
+}
 
-                let mut users_to_update = Vec::new();
-                for (user, result) in users_to_process.iter_mut().zip(results) {
-                    if let Ok(user_id) = result {
-                        user.linked_to_keywords = true;
-                        users_to_update.push(user_id);
+/// PATCH every user not yet linked to KeywordsAI to its API. Shared by the endpoint and the sync job
+pub async fn sync_keywords_users(
+    app_config: &AppConfig,
+    pool: &PgPool,
+) -> Result<Vec<User>, anyhow::Error> {
+    let users = User::get_all(pool).await?;
+    let mut users_to_process: Vec<_> = users
+        .clone()
+        .into_iter()
+        .filter(|user| !user.linked_to_keywords)
+        .collect();
+    let shared_client = Arc::new(Client::new());
+    let futures = FuturesUnordered::new();
+    info!(
+        "Attempting to sync {} users to KeywordsAI",
+        users_to_process.len()
+    );
+
+    let max_concurrent_requests = 50;
+    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
+    for user in users_to_process.iter() {
+        // Wait until we can acquire a permit
+        _ = semaphore.clone().acquire_owned().await?;
+
+        if !user.linked_to_keywords {
+            let user_id = user.id.clone();
+            let user_email = user.email.clone();
+            let user_name = user.full_name();
+            let client_clone = Arc::clone(&shared_client);
+            let api_key = app_config.keywords_api_key.clone();
+
+            futures.push(async move {
+                let url = format!("https://api.keywordsai.co/api/user/update/{}", user_id);
+
+                let response = client_clone
+                    .patch(&url)
+                    .bearer_auth(&api_key)
+                    .json(&json!({
+                        "name": user_name,
+                        "email": user_email,
+                    }))
+                    .send()
+                    .await;
+
+                match response {
+                    Ok(resp) => {
+                        if resp.status().is_success() {
+                            info!("User {} linked to KeywordsAI", user_email);
+                            Ok(user_id)
+                        } else {
+                            let error_body = resp
+                                .text()
+                                .await
+                                .unwrap_or_else(|_| "Failed to read response body".to_string());
+                            warn!("Error response from KeywordsAI: {}", error_body);
+                            Err(())
+                        }
+                    }
+                    Err(e) => {
+                        error!("HTTP request error: {}", e);
+                        Err(())
                     }
                 }
+            });
+        }
+    }
 
-                info!("Updating {} users", users_to_update.len());
-
-                sqlx::query!(
-                    "UPDATE users SET linked_to_keywords = true WHERE id = ANY($1)",
-                    &users_to_update
-                )
-                .execute(&app_state.pool)
-                .await
-                .map_err(|e| {
-                    error!("Failed to update users: {}", e);
-                    actix_web::error::ErrorInternalServerError(e.to_string())
-                })?;
+    let results: Vec<Result<String, ()>> = futures.collect().await;
 
-                Ok(web::Json(users))
-            }
-            Err(err) => Err(actix_web::error::ErrorInternalServerError(err.to_string())),
+    let mut users_to_update = Vec::new();
+    for (user, result) in users_to_process.iter_mut().zip(results) {
+        if let Ok(user_id) = result {
+            user.linked_to_keywords = true;
+            users_to_update.push(user_id);
         }
-    } else {
-        Err(actix_web::error::ErrorForbidden("You are not an admin"))
     }
+
+    info!("Updating {} users", users_to_update.len());
+
+    sqlx::query!(
+        "UPDATE users SET linked_to_keywords = true WHERE id = ANY($1)",
+        &users_to_update
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(users)
 }
 
 /// Look up a user by ID using the WorkOS API and return the user information
@@ -453,16 +671,18 @@ async fn exchange_code_for_user(
     }
 }
 
-/// Sign a JWT with the user info. By default, the token expires after 5 weeks. Returns the JWT.
+/// Sign a short-lived access token for a session. Returns the JWT.
 fn sign_jwt(
-    user_info: &WorkOSUser,
-    app_config: Arc<AppConfig>,
+    user_id: &str,
+    session_id: Uuid,
+    app_config: &AppConfig,
 ) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now().timestamp() as usize;
     let claims = Claims {
-        sub: user_info.id.clone(),
-        exp: now + 3600 * 24 * 7 * 5, // Token expires after 5 weeks
+        sub: user_id.to_string(),
+        exp: now + ACCESS_TOKEN_TTL_SECS,
         iat: now,
+        jti: Some(session_id.to_string()),
     };
 
     encode(
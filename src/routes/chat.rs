@@ -1,38 +1,434 @@
-use actix_web::{delete, put, web, Error, HttpResponse};
-use async_openai::config::OpenAIConfig;
+use actix_web::{delete, get, post, put, web, Error, HttpResponse};
 use async_openai::types::{
     ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
     ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
     CreateChatCompletionRequest,
 };
-use async_openai::Client;
-use sqlx::query_scalar;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::error;
 use utoipa::OpenApi;
 use uuid::Uuid;
 
 use crate::middleware::auth::AuthenticatedUser;
-use crate::models::{file::Filetype, message::Role, Chat, File, Message};
+use crate::models::message::{Role, ThreadedMessage};
+use crate::models::{file::Filetype, Chat, ChatShare, File, Message, PageCursor};
 use crate::prompts::Prompts;
-use crate::types::{AutorenameChatRequest, UpdateChatRequest};
+use crate::types::{
+    AutorenameChatRequest, ChatMessagesQuery, ChatMessagesResponse, ChatTreeResponse, ChatsQuery,
+    MessageNode, ShareChatRequest, UpdateChatRequest,
+};
 use crate::AppState;
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(autorename_chat, update_chat, delete_chat),
+    paths(
+        list_chats,
+        get_chat,
+        get_chat_messages,
+        get_chat_tree,
+        activate_message,
+        share_chat,
+        list_chat_shares,
+        revoke_chat_share,
+        autorename_chat,
+        update_chat,
+        delete_chat
+    ),
     components(schemas(
         AutorenameChatRequest,
+        ChatMessagesQuery,
+        ChatMessagesResponse,
+        ChatTreeResponse,
+        ChatsQuery,
+        ShareChatRequest,
         UpdateChatRequest,
         Chat,
+        ChatShare,
         File,
         Filetype,
         Message,
+        MessageNode,
         Role
     ))
 )]
 pub struct ApiDoc;
 
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 200;
+
+/// Look up a chat of the user's or 404
+async fn find_chat(app_state: &AppState, chat_id: Uuid, user_id: &str) -> Result<Chat, Error> {
+    Chat::get(&app_state.pool, chat_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get chat {}: {:?}", chat_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Chat not found"))
+}
+
+/// List chats, most recently updated first
+#[utoipa::path(
+    get,
+    params(
+        ("cursor" = Option<String>, Query, description = "X-Next-Cursor from the previous page"),
+        ("limit" = Option<i64>, Query, description = "Max number of chats, defaults to 50, at most 200"),
+    ),
+    responses((
+        status = 200,
+        description = "A page of chats",
+        body = Vec<Chat>,
+        content_type = "application/json",
+        headers(("X-Next-Cursor" = String, description = "Cursor of the next page, absent on the last one"))
+    ))
+)]
+#[get("")]
+async fn list_chats(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    query: web::Query<ChatsQuery>,
+) -> Result<HttpResponse, Error> {
+    let after = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<PageCursor>)
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let (chats, next_cursor) =
+        Chat::list(&app_state.pool, &authenticated_user.user_id, after, limit)
+            .await
+            .map_err(|e| {
+                error!("Failed to list chats: {:?}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?;
+
+    let mut response = HttpResponse::Ok();
+    if let Some(next_cursor) = next_cursor {
+        response.insert_header(("X-Next-Cursor", next_cursor.to_string()));
+    }
+    Ok(response.json(chats))
+}
+
+/// Get a chat by id
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The chat", body = Chat, content_type = "application/json"),
+        (status = 404, description = "Chat not found")
+    )
+)]
+#[get("/{chat_id}")]
+async fn get_chat(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    chat_id: web::Path<Uuid>,
+) -> Result<web::Json<Chat>, Error> {
+    find_chat(
+        &app_state,
+        chat_id.into_inner(),
+        &authenticated_user.user_id,
+    )
+    .await
+    .map(web::Json)
+}
+
+/// Page through a chat's messages from newest to oldest, each page in chronological order with
+/// the files attached to its messages
+#[utoipa::path(
+    get,
+    params(
+        ("before" = Option<Uuid>, Query, description = "Id of the oldest message already loaded"),
+        ("limit" = Option<i64>, Query, description = "Max number of messages, defaults to 50, at most 200"),
+    ),
+    responses(
+        (status = 200, description = "A page of messages and their files", body = ChatMessagesResponse, content_type = "application/json"),
+        (status = 400, description = "The before message is not in the chat"),
+        (status = 404, description = "Chat not found")
+    )
+)]
+#[get("/{chat_id}/messages")]
+async fn get_chat_messages(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    chat_id: web::Path<Uuid>,
+    query: web::Query<ChatMessagesQuery>,
+) -> Result<web::Json<ChatMessagesResponse>, Error> {
+    let user_id = &authenticated_user.user_id;
+    let chat = find_chat(&app_state, chat_id.into_inner(), user_id).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+
+    let (messages, has_more) =
+        Message::get_page_for_chat(&app_state.pool, chat.id, user_id, query.before, limit)
+            .await
+            .map_err(|e| {
+                error!("Failed to get messages for chat {}: {:?}", chat.id, e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?
+            .ok_or_else(|| {
+                actix_web::error::ErrorBadRequest("before is not a message of this chat")
+            })?;
+
+    let message_ids: Vec<Uuid> = messages.iter().map(|message| message.id).collect();
+    let files = File::get_for_messages(&app_state.pool, &message_ids, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get files for chat {}: {:?}", chat.id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(ChatMessagesResponse {
+        messages,
+        files,
+        has_more,
+    }))
+}
+
+/// Load a chat, the chats branched off it and all their messages as a tree
+async fn load_tree(
+    app_state: &AppState,
+    chat_id: Uuid,
+    user_id: &str,
+) -> Result<ChatTreeResponse, Error> {
+    let chats = Chat::get_with_branches(&app_state.pool, chat_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get branches of chat {}: {:?}", chat_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    if chats.is_empty() {
+        return Err(actix_web::error::ErrorNotFound("Chat not found"));
+    }
+
+    let chat_ids: Vec<Uuid> = chats.iter().map(|chat| chat.id).collect();
+    let messages = Message::get_threaded_for_chats(&app_state.pool, &chat_ids, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get messages for chat {}: {:?}", chat_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    let message_ids: Vec<Uuid> = messages.iter().map(|m| m.message.id).collect();
+    let files = File::get_for_messages(&app_state.pool, &message_ids, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get files for chat {}: {:?}", chat_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(build_tree(chats, messages, files))
+}
+
+/// Link up messages, oldest first, with their children, alternatives and branches
+fn build_tree(
+    chats: Vec<Chat>,
+    messages: Vec<ThreadedMessage>,
+    files: Vec<File>,
+) -> ChatTreeResponse {
+    let mut children: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    // Messages following the same one in the same chat are alternatives for that spot
+    let mut alternatives: HashMap<(Uuid, Option<Uuid>), Vec<Uuid>> = HashMap::new();
+    for threaded in &messages {
+        if let Some(parent_id) = threaded.parent_id {
+            children
+                .entry(parent_id)
+                .or_default()
+                .push(threaded.message.id);
+        }
+        alternatives
+            .entry((threaded.message.chat_id, threaded.parent_id))
+            .or_default()
+            .push(threaded.message.id);
+    }
+
+    let mut branches: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for chat in &chats {
+        if let Some(parent_message_id) = chat.parent_message_id {
+            branches.entry(parent_message_id).or_default().push(chat.id);
+        }
+    }
+
+    let messages = messages
+        .into_iter()
+        .map(|threaded| {
+            let id = threaded.message.id;
+            let siblings = &alternatives[&(threaded.message.chat_id, threaded.parent_id)];
+            let variant = siblings.iter().position(|&s| s == id).map_or(1, |i| i + 1);
+            MessageNode {
+                parent_id: threaded.parent_id,
+                children: children.remove(&id).unwrap_or_default(),
+                active: !threaded.message.regenerated,
+                variant,
+                variants: siblings.len(),
+                branches: branches.remove(&id).unwrap_or_default(),
+                message: threaded.message,
+            }
+        })
+        .collect();
+
+    ChatTreeResponse {
+        chats,
+        messages,
+        files,
+    }
+}
+
+/// Get a chat's full message tree: regenerated replies as alternatives of the message they
+/// replaced, chats branched off its messages, and which messages are on the active path
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The chat, its branches and all their messages", body = ChatTreeResponse, content_type = "application/json"),
+        (status = 404, description = "Chat not found")
+    )
+)]
+#[get("/{chat_id}/tree")]
+async fn get_chat_tree(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    chat_id: web::Path<Uuid>,
+) -> Result<web::Json<ChatTreeResponse>, Error> {
+    load_tree(
+        &app_state,
+        chat_id.into_inner(),
+        &authenticated_user.user_id,
+    )
+    .await
+    .map(web::Json)
+}
+
+/// Switch the chat to the path through a message, making it the active alternative at its spot.
+/// Below it the path follows the alternatives that were active before, or else the newest
+#[utoipa::path(
+    post,
+    responses(
+        (status = 200, description = "The updated tree", body = ChatTreeResponse, content_type = "application/json"),
+        (status = 404, description = "Chat or message not found")
+    )
+)]
+#[post("/{chat_id}/messages/{message_id}/activate")]
+async fn activate_message(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<web::Json<ChatTreeResponse>, Error> {
+    let (chat_id, message_id) = path.into_inner();
+    let user_id = &authenticated_user.user_id;
+    let chat = find_chat(&app_state, chat_id, user_id).await?;
+
+    let activated = Message::activate(&app_state.pool, chat.id, user_id, message_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to activate message {}: {:?}", message_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    if !activated {
+        return Err(actix_web::error::ErrorNotFound("Message not found"));
+    }
+
+    load_tree(&app_state, chat.id, user_id).await.map(web::Json)
+}
+
+/// Share a read-only snapshot of the chat up to a message, by default its latest. Anyone with the
+/// token can read it at /share/{token}, without user ids or files hidden from the user
+#[utoipa::path(
+    post,
+    request_body = Option<ShareChatRequest>,
+    responses(
+        (status = 200, description = "The new share with its token", body = ChatShare, content_type = "application/json"),
+        (status = 404, description = "Chat or message not found")
+    )
+)]
+#[post("/{chat_id}/share")]
+async fn share_chat(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    chat_id: web::Path<Uuid>,
+    share_chat_request: Option<web::Json<ShareChatRequest>>,
+) -> Result<web::Json<ChatShare>, Error> {
+    let chat = find_chat(
+        &app_state,
+        chat_id.into_inner(),
+        &authenticated_user.user_id,
+    )
+    .await?;
+    let up_to_message_id = share_chat_request.and_then(|request| request.up_to_message_id);
+
+    let share = ChatShare::create(&app_state.pool, &chat, up_to_message_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to share chat {}: {:?}", chat.id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Message not found"))?;
+    Ok(web::Json(share))
+}
+
+/// List the chat's shares, newest first, including revoked ones
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The chat's shares", body = Vec<ChatShare>, content_type = "application/json"),
+        (status = 404, description = "Chat not found")
+    )
+)]
+#[get("/{chat_id}/shares")]
+async fn list_chat_shares(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    chat_id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<ChatShare>>, Error> {
+    let user_id = &authenticated_user.user_id;
+    let chat = find_chat(&app_state, chat_id.into_inner(), user_id).await?;
+
+    let shares = ChatShare::get_all_for_chat(&app_state.pool, chat.id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get shares of chat {}: {:?}", chat.id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    Ok(web::Json(shares))
+}
+
+/// Revoke a share, its link stops working
+#[utoipa::path(
+    delete,
+    responses(
+        (status = 200, description = "The revoked share", body = ChatShare, content_type = "application/json"),
+        (status = 404, description = "Share not found")
+    )
+)]
+#[delete("/{chat_id}/shares/{share_id}")]
+async fn revoke_chat_share(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<web::Json<ChatShare>, Error> {
+    let (chat_id, share_id) = path.into_inner();
+
+    let share = ChatShare::revoke(
+        &app_state.pool,
+        share_id,
+        chat_id,
+        &authenticated_user.user_id,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to revoke share {}: {:?}", share_id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Share not found"))?;
+    Ok(web::Json(share))
+}
+
 /// Autorename chat given the oldest non-regenerated message
 /// Body is optional for alternative autorename prompt target
 #[utoipa::path(
@@ -47,36 +443,55 @@ async fn autorename_chat(
     chat_id: web::Path<Uuid>,
     autorename_chat_request: Option<web::Json<AutorenameChatRequest>>,
 ) -> Result<web::Json<Chat>, Error> {
-    let client: Client<OpenAIConfig> = app_state.keywords_client.clone();
-    let user_id = authenticated_user.user_id;
-    let chat_id = chat_id.into_inner();
-
-    let message_text = if let Some(autorename_chat_request) = autorename_chat_request {
-        autorename_chat_request.text.clone()
-    } else {
-        // Get the text of the oldest non-regenerated message given the chat id and user id
-        let pool = &app_state.pool;
-
-        let result: String = query_scalar!(
-            r#"
-            SELECT text FROM messages
-            WHERE chat_id = $1
-            AND user_id = $2
-            AND regenerated = false
-            ORDER BY created_at ASC
-            LIMIT 1
-            "#,
-            chat_id,
-            user_id
-        )
-        .fetch_one(pool)
-        .await
-        .map_err(|e| {
-            error!("Database query returned no results: {}", e);
-            actix_web::error::ErrorNotFound(e)
-        })?;
+    let message_text = autorename_chat_request.map(|request| request.text.clone());
 
-        result
+    let chat = autorename(
+        &app_state,
+        chat_id.into_inner(),
+        &authenticated_user.user_id,
+        message_text,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to autorename chat: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Chat has no messages to name it from"))?;
+    Ok(web::Json(chat))
+}
+
+/// Name a chat after `message_text`, or its oldest non-regenerated message without it.
+/// None if there's no text to go on. Shared by the endpoint and the autorename job
+pub async fn autorename(
+    app_state: &AppState,
+    chat_id: Uuid,
+    user_id: &str,
+    message_text: Option<String>,
+) -> Result<Option<Chat>, anyhow::Error> {
+    let message_text = match message_text {
+        Some(message_text) => message_text,
+        None => {
+            // Get the text of the oldest non-regenerated message given the chat id and user id
+            let result = sqlx::query_scalar::<_, String>(
+                r#"
+                SELECT text FROM messages
+                WHERE chat_id = $1
+                AND user_id = $2
+                AND regenerated = false
+                ORDER BY created_at ASC
+                LIMIT 1
+                "#,
+            )
+            .bind(chat_id)
+            .bind(user_id)
+            .fetch_optional(&app_state.pool)
+            .await?;
+
+            match result {
+                Some(message_text) => message_text,
+                None => return Ok(None),
+            }
+        }
     };
 
     let request = CreateChatCompletionRequest {
@@ -119,10 +534,7 @@ async fn autorename_chat(
         ..Default::default()
     };
 
-    let response = client.chat().create(request).await.map_err(|e| {
-        error!("Failed to create chat: {:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
+    let response = app_state.llm.create(request).await?;
 
     let name = response
         .choices
@@ -130,13 +542,8 @@ async fn autorename_chat(
         .and_then(|choice| choice.message.content.clone())
         .unwrap_or("New Chat".to_string());
 
-    let chat = Chat::update_name(&app_state.pool, chat_id, &user_id, &name)
-        .await
-        .map_err(|e| {
-            error!("Failed to update chat: {:?}", e);
-            actix_web::error::ErrorInternalServerError(e)
-        })?;
-    Ok(web::Json(chat))
+    let chat = Chat::update_name(&app_state.pool, chat_id, user_id, &name).await?;
+    Ok(Some(chat))
 }
 
 /// Update chat details by id
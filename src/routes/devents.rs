@@ -6,6 +6,7 @@ use tracing::error;
 
 use crate::models::Devent;
 use crate::types::CreateDeventRequest;
+use crate::middleware::roles::{DeventsRead, RequirePermission};
 use crate::{middleware::auth::AuthenticatedUser, AppState};
 
 #[post("/create")]
@@ -37,15 +38,9 @@ async fn create_devent(
 #[get("/{id}")]
 async fn get_devent(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<DeventsRead>,
     id: web::Path<Uuid>,
 ) -> Result<web::Json<Devent>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
-
     let devent = Devent::get(&app_state.pool, id.into_inner())
         .await
         .map_err(|e|{
@@ -59,15 +54,9 @@ async fn get_devent(
 #[get("/session/{session_id}")]
 async fn get_devents_for_session(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<DeventsRead>,
     session_id: web::Path<Uuid>,
 ) -> Result<web::Json<Vec<Devent>>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
-
     let devents = Devent::get_all_for_session(&app_state.pool, session_id.into_inner())
         .await
         .map_err(|e|{
@@ -81,15 +70,9 @@ async fn get_devents_for_session(
 #[get("/recording/{recording_id}")]
 async fn get_devents_for_recording(
     app_state: web::Data<Arc<AppState>>,
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<DeventsRead>,
     recording_id: web::Path<Uuid>,
 ) -> Result<web::Json<Vec<Devent>>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
-
     let devents = Devent::get_all_for_recording(&app_state.pool, recording_id.into_inner())
         .await
         .map_err(|e|{
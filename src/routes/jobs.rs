@@ -0,0 +1,195 @@
+use actix_web::{get, post, web};
+use std::sync::Arc;
+use tracing::{error, info};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::middleware::roles::{JobsManage, RequirePermission};
+use crate::models::{Job, JobCount, JobPayload, JobStatus};
+use crate::types::{EnqueueJobRequest, JobsQuery};
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_jobs, job_stats, get_job, enqueue_job, retry_job, cancel_job),
+    components(schemas(Job, JobCount, JobPayload, JobStatus, JobsQuery, EnqueueJobRequest))
+)]
+pub struct ApiDoc;
+
+const DEFAULT_LIST_LIMIT: i64 = 100;
+const MAX_LIST_LIMIT: i64 = 1000;
+
+/// Look up a job or 404
+async fn find_job(app_state: &AppState, job_id: Uuid) -> Result<Job, actix_web::Error> {
+    Job::get(&app_state.pool, job_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get job {}: {:?}", job_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Job not found"))
+}
+
+/// List jobs, most recent first, needs jobs:manage
+#[utoipa::path(
+    get,
+    params(
+        ("status" = Option<JobStatus>, Query, description = "Only jobs in this status"),
+        ("kind" = Option<String>, Query, description = "Only jobs of this kind, e.g. generate_memories"),
+        ("user_id" = Option<String>, Query, description = "Only jobs for this user"),
+        ("limit" = Option<i64>, Query, description = "Max number of jobs, defaults to 100, at most 1000"),
+    ),
+    responses((status = 200, description = "Matching jobs", body = Vec<Job>, content_type = "application/json"))
+)]
+#[get("")]
+async fn list_jobs(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<JobsManage>,
+    query: web::Query<JobsQuery>,
+) -> Result<web::Json<Vec<Job>>, actix_web::Error> {
+    let jobs = Job::list(
+        &app_state.pool,
+        query.status,
+        query.kind.as_deref(),
+        query.user_id.as_deref(),
+        query
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to list jobs: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(jobs))
+}
+
+/// Count jobs per kind and status, needs jobs:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "Job counts per kind and status", body = Vec<JobCount>, content_type = "application/json"))
+)]
+#[get("/stats")]
+async fn job_stats(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<JobsManage>,
+) -> Result<web::Json<Vec<JobCount>>, actix_web::Error> {
+    let counts = Job::counts(&app_state.pool).await.map_err(|e| {
+        error!("Failed to count jobs: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(counts))
+}
+
+/// Get a job, including its last error, needs jobs:manage
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The job", body = Job, content_type = "application/json"),
+        (status = 404, description = "Job not found")
+    )
+)]
+#[get("/{job_id}")]
+async fn get_job(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<JobsManage>,
+    job_id: web::Path<Uuid>,
+) -> Result<web::Json<Job>, actix_web::Error> {
+    find_job(&app_state, job_id.into_inner())
+        .await
+        .map(web::Json)
+}
+
+/// Queue a job, needs jobs:manage. 409 if a job with the same dedupe key is already queued
+#[utoipa::path(
+    post,
+    request_body = EnqueueJobRequest,
+    responses(
+        (status = 200, description = "The queued job", body = Job, content_type = "application/json"),
+        (status = 409, description = "An identical job is already queued")
+    )
+)]
+#[post("")]
+async fn enqueue_job(
+    app_state: web::Data<Arc<AppState>>,
+    admin: RequirePermission<JobsManage>,
+    web::Json(req_body): web::Json<EnqueueJobRequest>,
+) -> Result<web::Json<Job>, actix_web::Error> {
+    let job = Job::enqueue(&app_state.pool, &req_body.payload, req_body.run_at)
+        .await
+        .map_err(|e| {
+            error!("Failed to enqueue job: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorConflict("An identical job is already queued"))?;
+
+    info!("User {} queued {} job {}", admin.user_id, job.kind, job.id);
+    Ok(web::Json(job))
+}
+
+/// Run a failed or cancelled job again with a fresh set of attempts, needs jobs:manage
+#[utoipa::path(
+    post,
+    responses(
+        (status = 200, description = "The requeued job", body = Job, content_type = "application/json"),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job isn't failed or cancelled, or an identical job is already queued")
+    )
+)]
+#[post("/{job_id}/retry")]
+async fn retry_job(
+    app_state: web::Data<Arc<AppState>>,
+    admin: RequirePermission<JobsManage>,
+    job_id: web::Path<Uuid>,
+) -> Result<web::Json<Job>, actix_web::Error> {
+    let job_id = job_id.into_inner();
+    find_job(&app_state, job_id).await?;
+
+    let job = Job::retry(&app_state.pool, job_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to retry job {}: {:?}", job_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| {
+            actix_web::error::ErrorConflict(
+                "Job isn't failed or cancelled, or an identical job is already queued",
+            )
+        })?;
+
+    info!("User {} retried job {}", admin.user_id, job.id);
+    Ok(web::Json(job))
+}
+
+/// Cancel a queued job, needs jobs:manage. Running jobs can't be cancelled
+#[utoipa::path(
+    post,
+    responses(
+        (status = 200, description = "The cancelled job", body = Job, content_type = "application/json"),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job isn't queued")
+    )
+)]
+#[post("/{job_id}/cancel")]
+async fn cancel_job(
+    app_state: web::Data<Arc<AppState>>,
+    admin: RequirePermission<JobsManage>,
+    job_id: web::Path<Uuid>,
+) -> Result<web::Json<Job>, actix_web::Error> {
+    let job_id = job_id.into_inner();
+    find_job(&app_state, job_id).await?;
+
+    let job = Job::cancel(&app_state.pool, job_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to cancel job {}: {:?}", job_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorConflict("Job isn't queued"))?;
+
+    info!("User {} cancelled job {}", admin.user_id, job.id);
+    Ok(web::Json(job))
+}
@@ -0,0 +1,101 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use std::sync::Arc;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::middleware::roles::{ModelsManage, RequirePermission};
+use crate::models::LlmModel;
+use crate::types::UpsertModelRequest;
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_models, upsert_model, delete_model),
+    components(schemas(LlmModel, UpsertModelRequest))
+)]
+pub struct ApiDoc;
+
+/// List every model route in the registry, needs models:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "All model routes", body = Vec<LlmModel>, content_type = "application/json"))
+)]
+#[get("")]
+async fn list_models(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<ModelsManage>,
+) -> Result<web::Json<Vec<LlmModel>>, actix_web::Error> {
+    let models = LlmModel::get_all(&app_state.pool).await.map_err(|e| {
+        error!("Failed to get models: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(models))
+}
+
+/// Create or replace the route for a model alias, needs models:manage
+#[utoipa::path(
+    put,
+    request_body = UpsertModelRequest,
+    responses((status = 200, description = "Upserted model route", body = LlmModel, content_type = "application/json"))
+)]
+#[put("/{alias:.*}")]
+async fn upsert_model(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<ModelsManage>,
+    alias: web::Path<String>,
+    web::Json(req_body): web::Json<UpsertModelRequest>,
+) -> Result<web::Json<LlmModel>, actix_web::Error> {
+    let defaults = LlmModel::default();
+    let model = LlmModel {
+        alias: alias.into_inner(),
+        upstream_id: req_body.upstream_id,
+        context_window: req_body.context_window.unwrap_or(defaults.context_window),
+        max_output_tokens: req_body
+            .max_output_tokens
+            .unwrap_or(defaults.max_output_tokens),
+        summarize_truncated: req_body
+            .summarize_truncated
+            .unwrap_or(defaults.summarize_truncated),
+        input_cost_per_mtok: req_body
+            .input_cost_per_mtok
+            .unwrap_or(defaults.input_cost_per_mtok),
+        output_cost_per_mtok: req_body
+            .output_cost_per_mtok
+            .unwrap_or(defaults.output_cost_per_mtok),
+        supports_vision: req_body.supports_vision.unwrap_or(defaults.supports_vision),
+        fallbacks: req_body.fallbacks.unwrap_or(defaults.fallbacks),
+        enabled: req_body.enabled.unwrap_or(defaults.enabled),
+        ..defaults
+    };
+
+    let model = LlmModel::upsert(&app_state.pool, &model, &app_state.model_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to upsert model: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(model))
+}
+
+/// Delete the route for a model alias, it will then be passed through as-is. Needs models:manage
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "Deleted model route"))
+)]
+#[delete("/{alias:.*}")]
+async fn delete_model(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<ModelsManage>,
+    alias: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    LlmModel::delete(&app_state.pool, &alias, &app_state.model_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete model: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,40 +1,68 @@
 // routes/memory.rs
 
 use actix_web::{delete, get, post, put, web, HttpResponse};
-use anyhow::{Context, Error, Result};
-use async_openai::config::OpenAIConfig;
+use anyhow::{bail, Context, Error, Result};
 use async_openai::types::{
     ChatCompletionRequestMessage, ChatCompletionRequestUserMessageArgs,
     CreateChatCompletionRequestArgs,
 };
-use async_openai::Client;
 use chrono::{DateTime, Utc};
 use futures::future::{join_all, try_join_all};
 use lazy_static::lazy_static;
 use moka::future::Cache;
 use regex::Regex;
+use serde::Deserialize;
 use serde_json::json;
 use sqlx::PgPool;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use tiktoken_rs::cl100k_base;
 use tokio::sync::Semaphore;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::llm::structured::{structured_completion, StructuredOutput};
+use crate::llm::LlmRouter;
 use crate::middleware::auth::AuthenticatedUser;
-use crate::models::{Memory, Message};
+use crate::middleware::roles::{MemoriesManage, RequirePermission};
+use crate::models::memory_revision::{
+    MemoryAuthor, MemoryChange, REASON_DELETE, REASON_EDIT, REASON_NEW, REASON_ROLLBACK,
+    REASON_UPDATE,
+};
+use crate::models::memory_settings::MAX_CUSTOM_GROUPS;
+use crate::models::{
+    CustomMemoryGroup, Job, JobPayload, Memory, MemoryEmbedding, MemoryRevision, MemorySettings,
+    MemorySource, Message, PageCursor, ParseOutcome, ParseStat, UserMemoryGroups,
+};
 use crate::prompts::Prompts;
-use crate::types::{CreateMemoryRequest, GenerateMemoriesRequest, UpdateMemoryRequest};
+use crate::types::{
+    CreateMemoryRequest, ExportFormat, ExportQuery, ExportedMemory, ExportedMemoryGroup,
+    GenerateMemoriesRequest, ImportMemoriesQueued, ImportQuery, ImportedMemories, MemoriesQuery,
+    MemoryExport, MemoryGroupRequest, MemoryGroupsResponse, MemoryHistoryResponse,
+    UpdateMemoryRequest, UpdateMemorySettingsRequest,
+};
 use crate::AppConfig;
 use crate::AppState;
 
+#[cfg(test)]
+mod eval;
+
+/// Largest page GET /memories returns when a limit is given
+const MAX_MEMORIES_PAGE: usize = 200;
+/// Version of the memory export format
+const MEMORY_EXPORT_VERSION: u32 = 1;
+/// Imported memories per increment prompt, so large imports don't blow up a single prompt
+const IMPORT_BATCH_SIZE: usize = 25;
+/// Most memories one import takes, bounds the job's payload and its increment prompts
+const MAX_IMPORT_MEMORIES: usize = 1000;
+
 async fn call_fn(
     pool: &PgPool,
     name: &str,
     args: &str,
     user_id: &str,
     memory_cache: &Cache<String, HashMap<Uuid, Memory>>,
+    change: &MemoryChange,
 ) -> Result<Vec<Memory>> {
     let function_args: serde_json::Value = args.parse()?;
 
@@ -43,23 +71,30 @@ async fn call_fn(
             let memory = function_args["memory"].as_str().unwrap();
             let grouping = function_args.get("grouping").and_then(|g| g.as_str());
             let new_memory =
-                Memory::add_memory(pool, memory, grouping, user_id, memory_cache).await?;
+                Memory::add_memory(pool, memory, grouping, user_id, memory_cache, change).await?;
             Ok(vec![new_memory])
         }
         "update_memory" => {
             let memory_id = Uuid::parse_str(function_args["memory_id"].as_str().unwrap())?;
             let new_memory = function_args["memory"].as_str().unwrap();
             let grouping = function_args.get("grouping").and_then(|g| g.as_str());
-            let updated_memory =
-                Memory::update_memory(pool, memory_id, new_memory, grouping, user_id, memory_cache)
-                    .await?;
+            let updated_memory = Memory::update_memory(
+                pool,
+                memory_id,
+                new_memory,
+                grouping,
+                user_id,
+                memory_cache,
+                change,
+            )
+            .await?;
 
             Ok(vec![updated_memory])
         }
         "delete_memory" => {
             let memory_id = Uuid::parse_str(function_args["memory_id"].as_str().unwrap())?;
             let deleted_memory =
-                Memory::delete_memory(pool, memory_id, user_id, memory_cache).await?;
+                Memory::delete_memory(pool, memory_id, user_id, memory_cache, change).await?;
 
             Ok(vec![deleted_memory])
         }
@@ -69,8 +104,15 @@ async fn call_fn(
 
             for memory in memory_strings {
                 memories.push(
-                    Memory::add_memory(pool, memory.as_str().unwrap(), None, user_id, memory_cache)
-                        .await?,
+                    Memory::add_memory(
+                        pool,
+                        memory.as_str().unwrap(),
+                        None,
+                        user_id,
+                        memory_cache,
+                        change,
+                    )
+                    .await?,
                 );
             }
 
@@ -80,6 +122,25 @@ async fn call_fn(
     }
 }
 
+/// Embed new or edited memories off the request path, so retrieval at chat time rarely has to wait on it
+fn embed_in_background(app_state: &AppState, memories: Vec<Memory>) {
+    let memories: Vec<Memory> = memories
+        .into_iter()
+        .filter(|memory| memory.deleted_at.is_none())
+        .collect();
+    if memories.is_empty() {
+        return;
+    }
+
+    let pool = app_state.pool.clone();
+    let embedder = app_state.embedder.clone();
+    tokio::spawn(async move {
+        if let Err(e) = MemoryEmbedding::ensure(&pool, embedder.as_ref(), &memories).await {
+            error!("Failed to embed memories: {:?}", e);
+        }
+    });
+}
+
 #[allow(dead_code)]
 pub async fn get_all_user_memories(
     pool: Arc<PgPool>,
@@ -100,12 +161,14 @@ async fn create_memory(
     authenticated_user: AuthenticatedUser,
     req_body: web::Json<CreateMemoryRequest>,
 ) -> Result<web::Json<Memory>, actix_web::Error> {
+    let groups = get_user_groups(&app_state, &authenticated_user.user_id).await?;
     let memory = Memory::add_memory(
         &app_state.pool,
         &req_body.content,
-        req_body.grouping.as_deref(),
+        Some(&groups.valid_group(req_body.grouping.as_deref())),
         &authenticated_user.user_id,
         &app_state.memory_cache,
+        &MemoryChange::by_user(REASON_NEW),
     )
     .await
     .map_err(|e| {
@@ -113,19 +176,35 @@ async fn create_memory(
         actix_web::error::ErrorInternalServerError(e)
     })?;
 
+    embed_in_background(&app_state, vec![memory.clone()]);
     Ok(web::Json(memory))
 }
 
-// read
+// read, optionally searched, filtered and paged. The next page's cursor is in X-Next-Cursor
 #[get("/")]
 async fn get_memories(
     app_state: web::Data<Arc<AppState>>,
     authenticated_user: AuthenticatedUser,
-) -> Result<web::Json<Vec<Memory>>, actix_web::Error> {
-    let memories = Memory::get_all_memories(
+    query: web::Query<MemoriesQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let after = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<PageCursor>)
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let limit = query.limit.map(|limit| limit.clamp(1, MAX_MEMORIES_PAGE));
+    let query = MemoriesQuery {
+        limit,
+        ..query.into_inner()
+    };
+
+    let (memories, next) = Memory::filter_page(
         &app_state.pool,
         &authenticated_user.user_id,
         &app_state.memory_cache,
+        &query,
+        after,
     )
     .await
     .map_err(|e| {
@@ -133,7 +212,11 @@ async fn get_memories(
         actix_web::error::ErrorInternalServerError(e)
     })?;
 
-    Ok(web::Json(memories))
+    let mut response = HttpResponse::Ok();
+    if let Some(next) = next {
+        response.insert_header(("X-Next-Cursor", next.to_string()));
+    }
+    Ok(response.json(memories))
 }
 
 // update
@@ -151,6 +234,7 @@ async fn update_memory(
         req_body.grouping.as_deref(),
         &authenticated_user.user_id,
         &app_state.memory_cache,
+        &MemoryChange::by_user(REASON_EDIT),
     )
     .await
     .map_err(|e| {
@@ -158,6 +242,7 @@ async fn update_memory(
         actix_web::error::ErrorInternalServerError(e)
     })?;
 
+    embed_in_background(&app_state, vec![memory.clone()]);
     Ok(web::Json(memory))
 }
 
@@ -170,32 +255,718 @@ async fn delete_memory(
 ) -> Result<HttpResponse, actix_web::Error> {
     Memory::delete_memory(
         &app_state.pool,
-        memory_id.into_inner(),
+        memory_id.into_inner(),
+        &authenticated_user.user_id,
+        &app_state.memory_cache,
+        &MemoryChange::by_user(REASON_DELETE),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to get memories: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn get_user_groups(
+    app_state: &AppState,
+    user_id: &str,
+) -> Result<UserMemoryGroups, actix_web::Error> {
+    UserMemoryGroups::for_user(&app_state.pool, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory groups: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })
+}
+
+/// Check a custom group name: not blank, not too long and not clashing with a built in group
+fn validate_group_name(name: &str) -> Result<&str, actix_web::Error> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 50 {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Group names must be 1 to 50 characters",
+        ));
+    }
+    if name.eq_ignore_ascii_case(&Memory::get_valid_group(None))
+        || Memory::allowed_groups()
+            .iter()
+            .any(|group| group.eq_ignore_ascii_case(name))
+    {
+        return Err(actix_web::error::ErrorConflict(
+            "A built in group already has that name",
+        ));
+    }
+    Ok(name)
+}
+
+// settings
+#[get("/settings")]
+async fn get_memory_settings(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<MemorySettings>, actix_web::Error> {
+    let settings = MemorySettings::get(&app_state.pool, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory settings: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(settings))
+}
+
+#[put("/settings")]
+async fn update_memory_settings(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: web::Json<UpdateMemorySettingsRequest>,
+) -> Result<web::Json<MemorySettings>, actix_web::Error> {
+    if let Some(excluded_groups) = &req_body.excluded_groups {
+        let groups = get_user_groups(&app_state, &authenticated_user.user_id).await?;
+        if let Some(unknown) = excluded_groups.iter().find(|g| !groups.contains(g)) {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "Unknown memory group: {}",
+                unknown
+            )));
+        }
+    }
+
+    let settings = MemorySettings::update(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        req_body.memory_enabled,
+        req_body.realtime_enabled,
+        req_body.excluded_groups.as_deref(),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to update memory settings: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(settings))
+}
+
+// groups
+#[get("/groups")]
+async fn get_memory_groups(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<MemoryGroupsResponse>, actix_web::Error> {
+    let groups = get_user_groups(&app_state, &authenticated_user.user_id).await?;
+
+    Ok(web::Json(MemoryGroupsResponse {
+        built_in: Memory::allowed_groups().to_vec(),
+        custom: groups.custom,
+        excluded: groups.settings.excluded_groups,
+    }))
+}
+
+#[post("/groups")]
+async fn create_memory_group(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    req_body: web::Json<MemoryGroupRequest>,
+) -> Result<web::Json<CustomMemoryGroup>, actix_web::Error> {
+    let name = validate_group_name(&req_body.name)?;
+    let groups = get_user_groups(&app_state, &authenticated_user.user_id).await?;
+    if groups.custom.len() >= MAX_CUSTOM_GROUPS {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "At most {} custom groups are allowed",
+            MAX_CUSTOM_GROUPS
+        )));
+    }
+
+    let group = CustomMemoryGroup::create(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        name,
+        req_body.description.as_deref(),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to create memory group: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorConflict("A group with that name already exists"))?;
+
+    Ok(web::Json(group))
+}
+
+#[put("/groups/{group_id}")]
+async fn update_memory_group(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    group_id: web::Path<Uuid>,
+    req_body: web::Json<MemoryGroupRequest>,
+) -> Result<web::Json<CustomMemoryGroup>, actix_web::Error> {
+    let name = validate_group_name(&req_body.name)?;
+    let group = CustomMemoryGroup::update(
+        &app_state.pool,
+        &app_state.memory_cache,
+        &authenticated_user.user_id,
+        group_id.into_inner(),
+        name,
+        req_body.description.as_deref(),
+    )
+    .await
+    .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => {
+            actix_web::error::ErrorConflict("A group with that name already exists")
+        }
+        _ => {
+            error!("Failed to update memory group: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        }
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Memory group not found"))?;
+
+    Ok(web::Json(group))
+}
+
+/// Delete a custom group, its memories move to the generic group
+#[delete("/groups/{group_id}")]
+async fn delete_memory_group(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    group_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    CustomMemoryGroup::delete(
+        &app_state.pool,
+        &app_state.memory_cache,
+        &authenticated_user.user_id,
+        group_id.into_inner(),
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to delete memory group: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Memory group not found"))?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn set_pinned(
+    app_state: &AppState,
+    user_id: &str,
+    memory_id: Uuid,
+    pinned: bool,
+) -> Result<web::Json<Memory>, actix_web::Error> {
+    Memory::set_pinned(
+        &app_state.pool,
+        memory_id,
+        user_id,
+        pinned,
+        &app_state.memory_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to pin memory: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .map(web::Json)
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Memory not found"))
+}
+
+// pin, always injected and never decayed
+#[post("/{memory_id}/pin")]
+async fn pin_memory(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    memory_id: web::Path<Uuid>,
+) -> Result<web::Json<Memory>, actix_web::Error> {
+    set_pinned(
+        &app_state,
+        &authenticated_user.user_id,
+        memory_id.into_inner(),
+        true,
+    )
+    .await
+}
+
+#[post("/{memory_id}/unpin")]
+async fn unpin_memory(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    memory_id: web::Path<Uuid>,
+) -> Result<web::Json<Memory>, actix_web::Error> {
+    set_pinned(
+        &app_state,
+        &authenticated_user.user_id,
+        memory_id.into_inner(),
+        false,
+    )
+    .await
+}
+
+// archived, left out of chats after going unconfirmed and unused for too long
+#[get("/archived")]
+async fn get_archived_memories(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<Vec<Memory>>, actix_web::Error> {
+    let memories = Memory::get_archived(&app_state.pool, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get archived memories: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(memories))
+}
+
+#[post("/{memory_id}/unarchive")]
+async fn unarchive_memory(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    memory_id: web::Path<Uuid>,
+) -> Result<web::Json<Memory>, actix_web::Error> {
+    let memory = Memory::unarchive(
+        &app_state.pool,
+        memory_id.into_inner(),
+        &authenticated_user.user_id,
+        &app_state.memory_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to unarchive memory: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Archived memory not found"))?;
+
+    embed_in_background(&app_state, vec![memory.clone()]);
+    Ok(web::Json(memory))
+}
+
+/// Export a user's memories, archived ones included, with timestamps and provenance, grouped by grouping
+async fn build_export(app_state: &AppState, user_id: &str) -> Result<MemoryExport> {
+    let mut memories =
+        Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache).await?;
+    memories.extend(Memory::get_archived(&app_state.pool, user_id).await?);
+    let creators = MemoryRevision::creators_for_user(&app_state.pool, user_id).await?;
+    let mut sources: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for source in MemorySource::get_for_user(&app_state.pool, user_id).await? {
+        sources
+            .entry(source.memory_id)
+            .or_default()
+            .push(source.message_id);
+    }
+
+    let mut grouped: BTreeMap<String, Vec<Memory>> = BTreeMap::new();
+    for memory in memories {
+        let grouping = memory
+            .grouping
+            .clone()
+            .unwrap_or_else(|| Memory::get_valid_group(None));
+        grouped.entry(grouping).or_default().push(memory);
+    }
+
+    let groups = grouped
+        .into_iter()
+        .filter(|(_, memories)| !memories.is_empty())
+        .map(|(grouping, mut memories)| {
+            memories.sort_by_key(|memory| (memory.created_at, memory.id));
+            ExportedMemoryGroup {
+                grouping,
+                memories: memories
+                    .into_iter()
+                    .map(|memory| ExportedMemory {
+                        created_by: creators.get(&memory.id).cloned(),
+                        source_message_ids: sources.remove(&memory.id).unwrap_or_default(),
+                        content: memory.content,
+                        pinned: memory.pinned,
+                        created_at: Some(memory.created_at),
+                        updated_at: Some(memory.updated_at),
+                        last_confirmed_at: Some(memory.last_confirmed_at),
+                        archived_at: memory.archived_at,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(MemoryExport {
+        version: MEMORY_EXPORT_VERSION,
+        exported_at: Utc::now(),
+        groups,
+    })
+}
+
+fn export_markdown(export: &MemoryExport) -> String {
+    let mut markdown = format!(
+        "# Memories\n\nExported {}\n",
+        export.exported_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    for group in &export.groups {
+        markdown.push_str(&format!("\n## {}\n\n", group.grouping));
+        for memory in &group.memories {
+            let pin = if memory.pinned { " 📌" } else { "" };
+            markdown.push_str(&format!("- {}{}\n", memory.content, pin));
+
+            let mut provenance = Vec::new();
+            if let Some(created_at) = memory.created_at {
+                provenance.push(format!("created {}", created_at.format("%Y-%m-%d")));
+            }
+            if let Some(updated_at) = memory.updated_at {
+                provenance.push(format!("updated {}", updated_at.format("%Y-%m-%d")));
+            }
+            if let Some(archived_at) = memory.archived_at {
+                provenance.push(format!("archived {}", archived_at.format("%Y-%m-%d")));
+            }
+            if let Some(created_by) = &memory.created_by {
+                provenance.push(format!("by {}", created_by));
+            }
+            if !memory.source_message_ids.is_empty() {
+                provenance.push(format!(
+                    "from {} message(s)",
+                    memory.source_message_ids.len()
+                ));
+            }
+            if !provenance.is_empty() {
+                markdown.push_str(&format!("  _{}_\n", provenance.join(", ")));
+            }
+        }
+    }
+    markdown
+}
+
+// export, as JSON or Markdown
+#[get("/export")]
+async fn export_memories(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let export = build_export(&app_state, &authenticated_user.user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to export memories: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(match query.format {
+        ExportFormat::Json => HttpResponse::Ok()
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"memories.json\"",
+            ))
+            .json(export),
+        ExportFormat::Markdown => HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"memories.md\"",
+            ))
+            .body(export_markdown(&export)),
+    })
+}
+
+/// Trimmed and lowercased, what two memories must share to count as duplicates on import
+fn normalize_content(content: &str) -> String {
+    content.trim().to_lowercase()
+}
+
+/// Import memories from an export. Exact duplicates of existing memories are skipped, the rest go
+/// through the increment prompt in batches so they merge with what the user has, or are added as
+/// they are when `merge` is false. Pins carry over to memories that come out with the same content.
+/// Archived memories are added as they are and archived again. Memories in groups the user excluded
+/// go to the generic group, and are skipped if that is excluded too.
+/// Runs as an import_memories job, nothing is imported if the user turned memory off since queueing it
+pub async fn import_memories_for_user(
+    app_state: &web::Data<Arc<AppState>>,
+    user_id: &str,
+    export: MemoryExport,
+    merge: bool,
+) -> Result<ImportedMemories> {
+    let groups = UserMemoryGroups::for_user(&app_state.pool, user_id).await?;
+    if !groups.settings.memory_enabled {
+        info!("Memory is turned off for user {}, skipping import", user_id);
+        return Ok(ImportedMemories {
+            memories: Vec::new(),
+            duplicates: 0,
+        });
+    }
+    let mut existing_memories =
+        Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache).await?;
+    existing_memories.extend(Memory::get_archived(&app_state.pool, user_id).await?);
+
+    let mut seen: HashSet<String> = existing_memories
+        .iter()
+        .map(|memory| normalize_content(&memory.content))
+        .collect();
+    let mut pinned: HashSet<String> = HashSet::new();
+    let mut archived: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut duplicates = 0;
+    let mut new_memories = Vec::new();
+    let mut archived_memories = Vec::new();
+    for group in export.groups {
+        let mut grouping = groups.valid_group(Some(&group.grouping));
+        if groups.is_excluded(&grouping) {
+            grouping = Memory::get_valid_group(None);
+            if groups.is_excluded(&grouping) {
+                info!(
+                    "Skipping {} memories in excluded group {}",
+                    group.memories.len(),
+                    group.grouping
+                );
+                continue;
+            }
+        }
+        for memory in group.memories {
+            let content = memory.content.trim();
+            if content.is_empty() {
+                continue;
+            }
+            if !seen.insert(normalize_content(content)) {
+                duplicates += 1;
+                continue;
+            }
+            if memory.pinned {
+                pinned.insert(normalize_content(content));
+            }
+            let new_memory = Memory::new(Uuid::new_v4(), user_id, content, None, Some(&grouping));
+            // Merging would bring archived memories back, so they are added as they are
+            match memory.archived_at {
+                Some(archived_at) => {
+                    archived.insert(normalize_content(content), archived_at);
+                    archived_memories.push(new_memory);
+                }
+                None => new_memories.push(new_memory),
+            }
+        }
+    }
+    info!(
+        "Importing {} memories and {} archived for user {}, {} duplicates skipped",
+        new_memories.len(),
+        archived_memories.len(),
+        user_id,
+        duplicates
+    );
+
+    let mut imported = Vec::new();
+    if merge {
+        for batch in new_memories.chunks(IMPORT_BATCH_SIZE) {
+            let existing_memories =
+                Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache).await?;
+            imported.extend(
+                increment_memory(
+                    app_state,
+                    user_id,
+                    &groups,
+                    None,
+                    batch,
+                    &existing_memories,
+                    MemoryAuthor::Import,
+                    &[],
+                )
+                .await?,
+            );
+        }
+    } else {
+        let change = MemoryChange::new(MemoryAuthor::Import, REASON_NEW, &[]);
+        imported = process_memories(
+            app_state,
+            "create_memory",
+            user_id,
+            None,
+            &new_memories,
+            &change,
+        )
+        .await?;
+    }
+
+    if !archived_memories.is_empty() {
+        let change = MemoryChange::new(MemoryAuthor::Import, REASON_NEW, &[]);
+        let created = process_memories(
+            app_state,
+            "create_memory",
+            user_id,
+            None,
+            &archived_memories,
+            &change,
+        )
+        .await?;
+        for memory in created {
+            let archived_at = archived
+                .get(&normalize_content(&memory.content))
+                .copied()
+                .unwrap_or_else(Utc::now);
+            let archived_memory = Memory::archive(
+                &app_state.pool,
+                memory.id,
+                user_id,
+                archived_at,
+                &app_state.memory_cache,
+            )
+            .await?;
+            imported.push(archived_memory.unwrap_or(memory));
+        }
+    }
+
+    for memory in imported.iter_mut() {
+        if pinned.contains(&normalize_content(&memory.content)) {
+            if let Some(pinned_memory) = Memory::set_pinned(
+                &app_state.pool,
+                memory.id,
+                user_id,
+                true,
+                &app_state.memory_cache,
+            )
+            .await?
+            {
+                *memory = pinned_memory;
+            }
+        }
+    }
+
+    embed_in_background(app_state, imported.clone());
+    Ok(ImportedMemories {
+        memories: imported,
+        duplicates,
+    })
+}
+
+// import, from the export format, queued as a job
+#[post("/import")]
+async fn import_memories(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    query: web::Query<ImportQuery>,
+    web::Json(export): web::Json<MemoryExport>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = &authenticated_user.user_id;
+    let groups = UserMemoryGroups::for_user(&app_state.pool, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory settings: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    if !groups.settings.memory_enabled {
+        return Err(actix_web::error::ErrorForbidden("Memory is turned off"));
+    }
+
+    let count: usize = export.groups.iter().map(|group| group.memories.len()).sum();
+    if count > MAX_IMPORT_MEMORIES {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Can't import more than {} memories at once",
+            MAX_IMPORT_MEMORIES
+        )));
+    }
+
+    let payload = JobPayload::ImportMemories {
+        user_id: user_id.clone(),
+        export,
+        merge: query.merge.unwrap_or(true),
+    };
+    let job = Job::enqueue(&app_state.pool, &payload, None)
+        .await
+        .map_err(|e| {
+            error!("Failed to queue memory import: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorConflict("An import is already queued"))?;
+    info!("Queued import of {} memories for user {}", count, user_id);
+
+    Ok(HttpResponse::Accepted().json(ImportMemoriesQueued { job_id: job.id }))
+}
+
+// history
+#[get("/{memory_id}/history")]
+async fn get_memory_history(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    memory_id: web::Path<Uuid>,
+) -> Result<web::Json<MemoryHistoryResponse>, actix_web::Error> {
+    let memory_id = memory_id.into_inner();
+    let user_id = &authenticated_user.user_id;
+
+    // Deleted memories keep their history, so it can be rolled back
+    let owned = Memory::exists_for_user(&app_state.pool, memory_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory {}: {:?}", memory_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    if !owned {
+        return Err(actix_web::error::ErrorNotFound("Memory not found"));
+    }
+
+    let revisions = MemoryRevision::get_for_memory(&app_state.pool, memory_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory revisions: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    let sources = MemorySource::get_for_memory(&app_state.pool, memory_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get memory sources: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(MemoryHistoryResponse {
+        memory_id,
+        revisions,
+        sources,
+    }))
+}
+
+// rollback
+#[post("/{memory_id}/revisions/{revision_id}/rollback")]
+async fn rollback_memory(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<web::Json<Memory>, actix_web::Error> {
+    let (memory_id, revision_id) = path.into_inner();
+
+    let revision = MemoryRevision::get(
+        &app_state.pool,
+        revision_id,
+        memory_id,
         &authenticated_user.user_id,
-        &app_state.memory_cache,
     )
     .await
     .map_err(|e| {
-        error!("Failed to get memories: {:?}", e);
+        error!("Failed to get memory revision: {:?}", e);
         actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Revision not found"))?;
+
+    // Rolling back a deleted memory restores it
+    let memory = Memory::rollback(
+        &app_state.pool,
+        memory_id,
+        &revision.content,
+        revision.grouping.as_deref(),
+        &authenticated_user.user_id,
+        &app_state.memory_cache,
+        &MemoryChange::by_user(REASON_ROLLBACK),
+    )
+    .await
+    .map_err(|e| match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::RowNotFound) => actix_web::error::ErrorNotFound("Memory not found"),
+        _ => {
+            error!("Failed to roll back memory: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        }
     })?;
 
-    Ok(HttpResponse::Ok().finish())
+    embed_in_background(&app_state, vec![memory.clone()]);
+    Ok(web::Json(memory))
 }
 
 #[post("/generate_from_chat")]
 pub async fn generate_memories_from_chat_history_endpoint(
     app_state: web::Data<Arc<AppState>>,
     _app_config: web::Data<Arc<AppConfig>>,
-    authenticated_user: AuthenticatedUser,
+    _permission: RequirePermission<MemoriesManage>,
     req_body: web::Json<GenerateMemoriesRequest>,
 ) -> Result<web::Json<Vec<Memory>>, actix_web::Error> {
-    if !authenticated_user.is_admin() {
-        return Err(actix_web::error::ErrorUnauthorized(
-            "Unauthorized".to_string(),
-        ));
-    }
-
     let user_id = req_body.user_id.clone();
     let range = req_body.range.map(|(start, end)| {
         (
@@ -211,6 +982,7 @@ pub async fn generate_memories_from_chat_history_endpoint(
         req_body.max_samples,
         req_body.samples_per_query,
         range,
+        MemoryAuthor::AdminJob,
     )
     .await
     .map(web::Json)
@@ -232,22 +1004,33 @@ pub async fn generate_memories_from_chat_history(
     max_samples: Option<u32>,
     samples_per_query: Option<u32>,
     range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    author: MemoryAuthor,
 ) -> Result<Vec<Memory>> {
+    let groups = UserMemoryGroups::for_user(&app_state.pool, user_id).await?;
+    if !groups.settings.memory_enabled
+        || (author == MemoryAuthor::RealtimeJob && !groups.settings.realtime_enabled)
+    {
+        info!("Memory is turned off for user {}, skipping", user_id);
+        return Ok(Vec::new());
+    }
+
     // get most recent message
     let latest_msg = Message::get_latest_message_by_user_id(&app_state.pool, user_id).await?;
 
-    // skip users who haven't sent message in last 14 days
+    // skip users who haven't sent message in last 14 days. Expected for most of a nightly sample,
+    // so it's not an error and the job isn't retried
     if range.is_some() {
         match latest_msg {
             Some(msg) if msg.created_at < Utc::now() - chrono::Duration::days(13) => {
-                return Err(anyhow::anyhow!(
-                    "Invalid User: User has not sent a message in the last 13 days, which is required for memory generation"
-                ));
+                info!(
+                    "User {} has not sent a message in the last 13 days, skipping",
+                    user_id
+                );
+                return Ok(Vec::new());
             }
             None => {
-                return Err(anyhow::anyhow!(
-                    "Invalid User: No messages found for this user"
-                ));
+                info!("No messages found for user {}, skipping", user_id);
+                return Ok(Vec::new());
             }
             _ => {} // User has a message within the last 13 days
         }
@@ -258,9 +1041,8 @@ pub async fn generate_memories_from_chat_history(
 
     // Skip users with no messages to generate memory for
     if user_messages.is_empty() {
-        return Err(anyhow::anyhow!(
-            "Invalid User: No messages found for this user. At least one message is required to generate memories."
-        ));
+        info!("No messages in range for user {}, skipping", user_id);
+        return Ok(Vec::new());
     }
 
     let max_samples = match max_samples {
@@ -282,9 +1064,32 @@ pub async fn generate_memories_from_chat_history(
 
     user_messages.reverse();
 
+    // Everything sampled in this run, linked to the memories it creates or updates
+    let source_message_ids: Vec<Uuid> = user_messages.iter().map(|msg| msg.id).collect();
+
+    let generated_samples = sample_messages(user_messages, samples_per_query);
+
+    let generated_memories = process_memory_context(
+        app_state,
+        sem,
+        user_id,
+        &groups,
+        &generated_samples,
+        author,
+        &source_message_ids,
+    )
+    .await?;
+
+    embed_in_background(app_state, generated_memories.clone());
+    Ok(generated_memories)
+}
+
+/// Split messages, oldest first, into transcripts of at most `samples_per_query` messages and
+/// `max_sample_toks` tokens each. Messages that don't fit are carried over into the next sample
+fn sample_messages(user_messages: Vec<Message>, samples_per_query: u32) -> Vec<String> {
     let estimate_token_count = |text: &str| text.chars().count() / 4;
     let bpe = cl100k_base().context("Failed to initialize tokenizer");
-    let mut msgs_queue: VecDeque<Message> = VecDeque::from(user_messages.clone());
+    let mut msgs_queue: VecDeque<Message> = VecDeque::from(user_messages);
     let mut generated_samples = Vec::new();
     let mut memory_content = String::new();
     let mut i = 0;
@@ -413,10 +1218,7 @@ pub async fn generate_memories_from_chat_history(
 
     info!("Generated {} samples", generated_samples.len());
 
-    let generated_memories =
-        process_memory_context(app_state, sem, user_id, &generated_samples).await?;
-
-    Ok(generated_memories)
+    generated_samples
 }
 
 // add memory_metadata
@@ -424,14 +1226,53 @@ async fn process_memory_context(
     app_state: &web::Data<Arc<AppState>>,
     sem: Option<Arc<Semaphore>>,
     user_id: &str,
+    groups: &UserMemoryGroups,
+    samples: &[String],
+    author: MemoryAuthor,
+    source_message_ids: &[Uuid],
+) -> Result<Vec<Memory>> {
+    let formatted_memories = extract_memories(
+        &app_state.llm,
+        Some(&app_state.pool),
+        user_id,
+        groups,
+        samples,
+    )
+    .await?;
+    if formatted_memories.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let existing_memories =
+        Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache).await?;
+
+    increment_memory(
+        app_state,
+        user_id,
+        groups,
+        sem,
+        &formatted_memories,
+        &existing_memories,
+        author,
+        source_message_ids,
+    )
+    .await
+}
+
+/// Run the generate prompt over every sample and the format prompt over what they found, giving
+/// new memories filed under the user's groups. Nothing is saved, parse stats are recorded if a
+/// pool is given
+async fn extract_memories(
+    llm: &LlmRouter,
+    pool: Option<&PgPool>,
+    user_id: &str,
+    groups: &UserMemoryGroups,
     samples: &[String],
 ) -> Result<Vec<Memory>> {
-    let mut generated_memories: Vec<Memory> = Vec::new();
     // NOTE: using gpt-4o tokenizer since claude's is not open source
     let bpe = cl100k_base().unwrap();
 
     let futures: Vec<_> = samples.iter().enumerate().map(|(index, sample)| {
-        let app_state = app_state.clone();
         let bpe = bpe.clone();
 
         async move {
@@ -453,45 +1294,68 @@ async fn process_memory_context(
                 )
             };
 
-            get_chat_completion(&app_state.keywords_client, "gemini/gemini-1.5-flash", &message_content).await
+            memory_step(
+                llm,
+                pool,
+                GENERATE_MEMORY_PROMPT,
+                "gemini/gemini-1.5-flash",
+                &message_content,
+                parse_user_information,
+            )
+            .await
         }
     }).collect();
 
-    let results: Vec<Result<String>> = join_all(futures).await;
+    let results: Vec<Result<Option<UserInformation>>> = join_all(futures).await;
+    let mut user_information: Vec<String> = Vec::new();
     for result in results {
         match result {
-            Ok(result_content) => {
-                let now_utc = Utc::now();
-                let memory_id = Uuid::new_v4();
-                let new_memory = Memory::new(
-                    memory_id,
-                    user_id,
-                    result_content.as_str(),
-                    Some(now_utc),
-                    None,
-                );
-                generated_memories.push(new_memory);
+            Ok(Some(sample_information)) => {
+                user_information.extend(sample_information.user_information)
             }
+            Ok(None) => {}
             Err(e) => error!("Error processing memory: {:?}", e),
         }
     }
 
-    info!("Generated memories: {}", generated_memories.len());
+    info!("Generated user information: {}", user_information.len());
+    if user_information.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    let formatted_memories: String = Memory::format_memories(generated_memories);
+    let user_information = user_information
+        .iter()
+        .map(|information| format!("* {}", information))
+        .collect::<Vec<_>>()
+        .join("\n");
 
     let format_memory_prompt = Prompts::FORMATTING_MEMORY
-        .replace("{0}", &Memory::formated_allowed_groups())
-        .replace("{1}", &serde_json::to_string(&formatted_memories).unwrap());
+        .replace("{0}", &groups.prompt_list())
+        .replace("{1}", &serde_json::to_string(&user_information).unwrap());
 
-    let formatted_content = get_chat_completion(
-        &app_state.keywords_client,
+    let formatted_memories: Vec<Memory> = memory_step(
+        llm,
+        pool,
+        FORMAT_MEMORY_PROMPT,
         "gemini/gemini-1.5-flash",
         &format_memory_prompt,
+        parse_memory_groups,
     )
-    .await?;
-
-    let formatted_memories = process_formatted_memories(user_id, &formatted_content).await?;
+    .await?
+    .map(|memory_groups: MemoryGroups| {
+        memory_groups
+            .groups
+            .into_iter()
+            .map(|group| (groups.valid_group(Some(&group.grouping)), group.memories))
+            .filter(|(grouping, _)| !groups.is_excluded(grouping))
+            .flat_map(|(grouping, memories)| {
+                memories.into_iter().map(move |memory| {
+                    Memory::new(Uuid::new_v4(), user_id, &memory, None, Some(&grouping))
+                })
+            })
+            .collect()
+    })
+    .unwrap_or_default();
 
     info!("formatted memories:");
     for memory in &formatted_memories {
@@ -501,73 +1365,39 @@ async fn process_memory_context(
         );
     }
 
-    let existing_memories =
-        Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache).await?;
-
-    increment_memory(
-        app_state,
-        user_id,
-        sem,
-        &formatted_memories,
-        &existing_memories,
-    )
-    .await
+    Ok(formatted_memories)
 }
 
-async fn process_formatted_memories(user_id: &str, formatted_content: &str) -> Result<Vec<Memory>> {
-    let memory_regex = regex::Regex::new(r"(?s)<memory>(.*?)</memory>").unwrap();
-    let mut inserted_memories = Vec::new();
-
-    for capture in memory_regex.captures_iter(formatted_content) {
-        if let Some(content) = capture.get(1) {
-            let memory_content = content.as_str().trim();
-            let mut lines = memory_content.lines();
-            let grouping = lines.next().unwrap_or("").trim().to_string();
-            let memories: Vec<String> = lines
-                .filter_map(|line| {
-                    let trimmed = line.trim();
-                    trimmed.strip_prefix('-').map(|s| s.trim().to_string())
-                })
-                .collect();
-
-            inserted_memories.extend(memories.into_iter().map(|memory| {
-                Memory::new(Uuid::new_v4(), user_id, &memory, None, Some(&grouping))
-            }));
-        }
-    }
-
-    Ok(inserted_memories)
+/// What the increment prompt decided to do with a batch of new memories
+struct IncrementPlan {
+    /// Existing memories with their merged content, under their existing ids
+    updates: Vec<Memory>,
+    additions: Vec<Memory>,
+    /// Existing memories the batch found again
+    repeated_ids: Vec<Uuid>,
 }
 
-async fn increment_memory(
-    app_state: &web::Data<Arc<AppState>>,
+/// Run the increment prompt, and the update prompt for every UPDATE verdict, over `new_memories`
+/// against `existing_memories`. Nothing is saved, parse stats are recorded if a pool is given
+async fn plan_increment(
+    llm: &LlmRouter,
+    pool: Option<&PgPool>,
     user_id: &str,
-    sem: Option<Arc<Semaphore>>,
+    groups: &UserMemoryGroups,
     new_memories: &[Memory],
     existing_memories: &Vec<Memory>,
-) -> Result<Vec<Memory>> {
-    let new_memory_count = new_memories.len();
-    let existing_memory_count = existing_memories.len();
-
-    info!("Memory Stats:");
-    info!("  • New memories:     {:>5}", new_memory_count);
-    info!("  • Existing memories:{:>5}", existing_memory_count);
-
+) -> Result<IncrementPlan> {
     if existing_memories.is_empty() {
-        let added_memories =
-            process_memories(app_state, "create_memory", user_id, sem, new_memories).await?;
-        let added_memory_count = added_memories.len();
-        let new_total_count = added_memory_count;
-
-        info!("  • Added memories:   {:>5}", added_memory_count);
-        info!("  • New total count:  {:>5}", new_total_count);
-
-        return Ok(added_memories);
+        return Ok(IncrementPlan {
+            updates: Vec::new(),
+            additions: new_memories.to_vec(),
+            repeated_ids: Vec::new(),
+        });
     }
 
     let format_with_id = true;
     let formatted_memories = Memory::format_grouped_memories(existing_memories, format_with_id);
-    let allowed_groups_list_str = Memory::formated_allowed_groups();
+    let allowed_groups_list_str = groups.prompt_list();
     let new_memories_str = new_memories
         .iter()
         .map(|m| format!("- {}", m.content))
@@ -578,37 +1408,98 @@ async fn increment_memory(
         .replace("{1}", &new_memories_str)
         .replace("{2}", &allowed_groups_list_str);
 
-    let response = get_chat_completion(
-        &app_state.keywords_client,
+    let verdicts = memory_step(
+        llm,
+        pool,
+        INCREMENT_MEMORY_PROMPT,
         "gemini/gemini-1.5-flash",
         &prompt,
+        parse_memory_verdicts,
     )
-    .await?;
-    let filtered_memories =
-        parse_ai_response(app_state, user_id, existing_memories, &response).await?;
+    .await?
+    .map(|memory_verdicts: MemoryVerdicts| memory_verdicts.verdicts)
+    .unwrap_or_default();
 
-    let memories_to_update: Vec<Memory> = filtered_memories
+    // Finding an existing memory again confirms it, UPDATE verdicts are confirmed by the update itself
+    let repeated_ids: Vec<Uuid> = verdicts
         .iter()
-        .filter(|memory| existing_memories.iter().any(|m| m.id == memory.id))
-        .cloned()
+        .filter(|verdict| verdict.verdict == Verdict::Repeat)
+        .filter_map(|verdict| verdict.memory_id)
+        .filter(|memory_id| existing_memories.iter().any(|m| m.id == *memory_id))
         .collect();
 
-    let memories_to_add: Vec<Memory> = filtered_memories
-        .iter()
-        .filter(|memory| !memories_to_update.iter().any(|m| m.id == memory.id))
-        .cloned()
-        .collect();
+    let filtered_memories =
+        apply_verdicts(llm, pool, user_id, groups, existing_memories, verdicts).await;
+
+    let (updates, additions) = filtered_memories
+        .into_iter()
+        .partition(|memory| existing_memories.iter().any(|m| m.id == memory.id));
+
+    Ok(IncrementPlan {
+        updates,
+        additions,
+        repeated_ids,
+    })
+}
+
+async fn increment_memory(
+    app_state: &web::Data<Arc<AppState>>,
+    user_id: &str,
+    groups: &UserMemoryGroups,
+    sem: Option<Arc<Semaphore>>,
+    new_memories: &[Memory],
+    existing_memories: &Vec<Memory>,
+    author: MemoryAuthor,
+    source_message_ids: &[Uuid],
+) -> Result<Vec<Memory>> {
+    let new_change = MemoryChange::new(author, REASON_NEW, source_message_ids);
+    let update_change = MemoryChange::new(author, REASON_UPDATE, source_message_ids);
+    let new_memory_count = new_memories.len();
+    let existing_memory_count = existing_memories.len();
+
+    info!("Memory Stats:");
+    info!("  • New memories:     {:>5}", new_memory_count);
+    info!("  • Existing memories:{:>5}", existing_memory_count);
+
+    let plan = plan_increment(
+        &app_state.llm,
+        Some(&app_state.pool),
+        user_id,
+        groups,
+        new_memories,
+        existing_memories,
+    )
+    .await?;
+
+    if let Err(e) = Memory::confirm(
+        &app_state.pool,
+        &plan.repeated_ids,
+        user_id,
+        &app_state.memory_cache,
+    )
+    .await
+    {
+        error!("Failed to confirm repeated memories: {:?}", e);
+    }
 
     let updated_memories = process_memories(
         app_state,
         "update_memory",
         user_id,
         sem.clone(),
-        &memories_to_update,
+        &plan.updates,
+        &update_change,
+    )
+    .await?;
+    let added_memories = process_memories(
+        app_state,
+        "create_memory",
+        user_id,
+        sem,
+        &plan.additions,
+        &new_change,
     )
     .await?;
-    let added_memories =
-        process_memories(app_state, "create_memory", user_id, sem, &memories_to_add).await?;
     let updated_memory_count = updated_memories.len();
     let added_memory_count = added_memories.len();
     let new_total_count = existing_memory_count + added_memory_count;
@@ -626,6 +1517,7 @@ async fn process_memories(
     user_id: &str,
     sem: Option<Arc<Semaphore>>,
     memories: &[Memory],
+    change: &MemoryChange,
 ) -> Result<Vec<Memory>> {
     let futures = memories.iter().map(|memory| {
         let app_state = app_state.clone();
@@ -649,6 +1541,7 @@ async fn process_memories(
                 &args,
                 user_id,
                 &app_state.memory_cache,
+                change,
             )
             .await?
             .into_iter()
@@ -661,6 +1554,7 @@ async fn process_memories(
 }
 
 lazy_static! {
+    static ref MEMORY_GROUP_REGEX: Regex = Regex::new(r"(?s)<memory>(.*?)</memory>").unwrap();
     static ref FILTERED_MEMORY_REGEX: Regex =
         Regex::new(r"(?s)<filtered memory>(.*?)</filtered memory>").unwrap();
     static ref CONTENT_REGEX: Regex = Regex::new(r"Content:\s*(.+)").unwrap();
@@ -671,126 +1565,449 @@ lazy_static! {
         Regex::new(r"(?is)<classification>\s*(.*?)\s*</classification>").unwrap();
 }
 
-async fn parse_ai_response(
-    app_state: &web::Data<Arc<AppState>>,
-    user_id: &str,
-    existing_memories: &[Memory],
-    response: &str,
-) -> Result<Vec<Memory>, Error> {
-    let futures = FILTERED_MEMORY_REGEX
-        .captures_iter(response)
-        .enumerate()
-        .map(|(idx, capture)| async move {
-            let memory_block = match capture.get(1) {
-                Some(m) => m.as_str().trim(),
-                None => return None,
-            };
+// Names the memory pipeline's prompts are counted under in the parse stats
+const GENERATE_MEMORY_PROMPT: &str = "generate_memory";
+const FORMAT_MEMORY_PROMPT: &str = "format_memory";
+const INCREMENT_MEMORY_PROMPT: &str = "increment_memory";
+const UPDATE_MEMORY_PROMPT: &str = "update_memory";
 
-            let content = match CONTENT_REGEX
-                .captures(memory_block)
-                .and_then(|cap| cap.get(1))
-            {
-                Some(m) => m.as_str().trim(),
-                None => return None,
-            };
+/// Most pieces of user information the generate prompt may return per sample
+const MAX_USER_INFORMATION: usize = 3;
 
-            let verdict = match VERDICT_REGEX
-                .captures(memory_block)
-                .and_then(|cap| cap.get(1))
-            {
-                Some(m) => m.as_str().trim(),
-                None => return None,
-            };
+/// What the generate prompt extracts from a sample of chats
+#[derive(Debug, Deserialize)]
+struct UserInformation {
+    user_information: Vec<String>,
+}
+
+impl StructuredOutput for UserInformation {
+    const TOOL_NAME: &'static str = "record_user_information";
+    const TOOL_DESCRIPTION: &'static str =
+        "Record the user information extracted from the chat messages";
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "reasoning": {
+                    "type": "string",
+                    "description": "Step by step reasoning on which user information to choose and why"
+                },
+                "user_information": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "maxItems": MAX_USER_INFORMATION,
+                    "description": "Up to 3 pieces of user information, one sentence each"
+                },
+                "citations": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "The chats each piece of user information came from, in the same order"
+                }
+            },
+            "required": ["reasoning", "user_information", "citations"]
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.user_information.len() > MAX_USER_INFORMATION {
+            bail!(
+                "{} pieces of user information, at most {} are allowed",
+                self.user_information.len(),
+                MAX_USER_INFORMATION
+            );
+        }
+        if self
+            .user_information
+            .iter()
+            .any(|info| info.trim().is_empty())
+        {
+            bail!("Blank user information");
+        }
+        Ok(())
+    }
+}
+
+/// The generated user information, deduplicated and grouped by the format prompt
+#[derive(Debug, Deserialize)]
+struct MemoryGroups {
+    groups: Vec<MemoryGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MemoryGroup {
+    grouping: String,
+    memories: Vec<String>,
+}
+
+impl StructuredOutput for MemoryGroups {
+    const TOOL_NAME: &'static str = "record_memory_groups";
+    const TOOL_DESCRIPTION: &'static str =
+        "Record the user descriptions without redundancies, grouped into the allowed categories";
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "groups": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "grouping": {
+                                "type": "string",
+                                "description": "One of the grouping categories given in the instructions"
+                            },
+                            "memories": { "type": "array", "items": { "type": "string" } }
+                        },
+                        "required": ["grouping", "memories"]
+                    }
+                }
+            },
+            "required": ["groups"]
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.groups.is_empty() {
+            bail!("No memory groups");
+        }
+        for group in &self.groups {
+            if group.memories.iter().any(|memory| memory.trim().is_empty()) {
+                bail!("Blank memory in grouping {}", group.grouping);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum Verdict {
+    New,
+    Old,
+    Update,
+    Repeat,
+}
+
+/// The increment prompt's decision on how a new memory fits the existing ones
+#[derive(Debug, Deserialize)]
+struct MemoryVerdict {
+    content: String,
+    verdict: Verdict,
+    /// The grouping for NEW and OLD verdicts
+    grouping: Option<String>,
+    /// The existing memory an UPDATE verdict folds into, or a REPEAT verdict repeats
+    memory_id: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MemoryVerdicts {
+    verdicts: Vec<MemoryVerdict>,
+}
+
+impl StructuredOutput for MemoryVerdicts {
+    const TOOL_NAME: &'static str = "record_memory_verdicts";
+    const TOOL_DESCRIPTION: &'static str =
+        "Record a verdict on how each new memory fits into the existing memories";
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "verdicts": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "content": { "type": "string", "description": "Exact memory content" },
+                            "reasoning": {
+                                "type": "string",
+                                "description": "Step by step reasoning, explicitly referencing each rule applied"
+                            },
+                            "verdict": { "type": "string", "enum": ["NEW", "OLD", "UPDATE", "REPEAT"] },
+                            "grouping": {
+                                "type": "string",
+                                "description": "Grouping name from the allowed names, for NEW and OLD verdicts"
+                            },
+                            "memory_id": {
+                                "type": "string",
+                                "description": "UUID of the existing memory to update for UPDATE verdicts, or that the memory repeats for REPEAT verdicts"
+                            }
+                        },
+                        "required": ["content", "reasoning", "verdict"]
+                    }
+                }
+            },
+            "required": ["verdicts"]
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        for verdict in &self.verdicts {
+            if verdict.content.trim().is_empty() {
+                bail!("Blank memory content");
+            }
+            match verdict.verdict {
+                Verdict::New | Verdict::Old if verdict.grouping.is_none() => {
+                    bail!("{:?} verdict without a grouping", verdict.verdict)
+                }
+                Verdict::Update if verdict.memory_id.is_none() => {
+                    bail!("UPDATE verdict without a memory_id")
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An existing memory rewritten to take in a new one
+#[derive(Debug, Deserialize)]
+struct UpdatedMemory {
+    updated_memory: String,
+}
+
+impl StructuredOutput for UpdatedMemory {
+    const TOOL_NAME: &'static str = "record_updated_memory";
+    const TOOL_DESCRIPTION: &'static str =
+        "Record the old memory updated to incorporate the new memory";
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "reasoning": {
+                    "type": "string",
+                    "description": "How the update conforms to the minimum description principle"
+                },
+                "updated_memory": { "type": "string" }
+            },
+            "required": ["reasoning", "updated_memory"]
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.updated_memory.trim().is_empty() {
+            bail!("Blank updated memory");
+        }
+        Ok(())
+    }
+}
+
+/// Run one step of the memory pipeline as a forced tool call. When the tool call is missing or
+/// invalid, ask again for plain text and parse it with `parse_text`, the prompts' original format.
+/// Which of the two worked, if any, is counted per prompt in the parse stats when a pool is given
+async fn memory_step<T: StructuredOutput>(
+    llm: &LlmRouter,
+    pool: Option<&PgPool>,
+    prompt_name: &str,
+    model: &str,
+    prompt: &str,
+    parse_text: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>> {
+    let (outcome, output) = match structured_completion::<T>(llm, model, prompt).await? {
+        Some(output) => (ParseOutcome::Structured, Some(output)),
+        None => {
+            let content = get_chat_completion(llm, model, prompt).await?;
+            match parse_text(&content) {
+                Some(output) => (ParseOutcome::Fallback, Some(output)),
+                None => {
+                    warn!("Couldn't parse {} reply: {}", prompt_name, content);
+                    (ParseOutcome::Failed, None)
+                }
+            }
+        }
+    };
+
+    if let Some(pool) = pool {
+        if let Err(e) = ParseStat::record(pool, prompt_name, outcome).await {
+            error!("Failed to record parse stat for {}: {:?}", prompt_name, e);
+        }
+    }
+    Ok(output)
+}
+
+/// Fallback for the generate prompt, the bullet points inside `<user information>` tags
+fn parse_user_information(content: &str) -> Option<UserInformation> {
+    let user_information = Memory::extract_user_information(content)?
+        .lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*', '•']).trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Some(UserInformation { user_information })
+}
+
+/// Fallback for the format prompt, `<memory>` blocks of a grouping line followed by `- memory` lines
+fn parse_memory_groups(content: &str) -> Option<MemoryGroups> {
+    let groups: Vec<MemoryGroup> = MEMORY_GROUP_REGEX
+        .captures_iter(content)
+        .filter_map(|capture| capture.get(1))
+        .map(|block| {
+            let mut lines = block.as_str().trim().lines();
+            let grouping = lines.next().unwrap_or("").trim().to_string();
+            let memories = lines
+                .filter_map(|line| line.trim().strip_prefix('-').map(|s| s.trim().to_string()))
+                .collect();
+            MemoryGroup { grouping, memories }
+        })
+        .collect();
+
+    if groups.is_empty() {
+        None
+    } else {
+        Some(MemoryGroups { groups })
+    }
+}
 
-            let (verdict_type, grouping_or_memory) = match verdict.split_once(',') {
-                Some((v, g)) => (v.trim(), Some(g.trim())),
-                None if verdict == "REPEAT" => ("REPEAT", None),
+/// Fallback for the increment prompt, `<filtered memory>` blocks with `Content:` and `Verdict:` lines
+fn parse_memory_verdicts(content: &str) -> Option<MemoryVerdicts> {
+    let verdicts: Vec<MemoryVerdict> = FILTERED_MEMORY_REGEX
+        .captures_iter(content)
+        .filter_map(|capture| {
+            let memory_block = capture.get(1)?.as_str().trim();
+            let content = CONTENT_REGEX
+                .captures(memory_block)?
+                .get(1)?
+                .as_str()
+                .trim();
+            let verdict = VERDICT_REGEX
+                .captures(memory_block)?
+                .get(1)?
+                .as_str()
+                .trim();
+
+            let parts = verdict
+                .split_once(',')
+                .map(|(verdict, rest)| (verdict.trim(), rest.trim()));
+            let (verdict, grouping, memory_id) = match parts {
+                Some(("NEW", grouping)) => (Verdict::New, Some(grouping.to_string()), None),
+                Some(("OLD", grouping)) => (Verdict::Old, Some(grouping.to_string()), None),
+                Some(("UPDATE", memory_id)) => (
+                    Verdict::Update,
+                    None,
+                    Some(Uuid::parse_str(memory_id).ok()?),
+                ),
+                Some(("REPEAT", memory_id)) => {
+                    (Verdict::Repeat, None, Uuid::parse_str(memory_id).ok())
+                }
+                None if verdict == "REPEAT" => (Verdict::Repeat, None, None),
                 _ => return None,
             };
 
+            Some(MemoryVerdict {
+                content: content.to_string(),
+                verdict,
+                grouping,
+                memory_id,
+            })
+        })
+        .collect();
+
+    if verdicts.is_empty() {
+        None
+    } else {
+        Some(MemoryVerdicts { verdicts })
+    }
+}
+
+/// Fallback for the update prompt, the contents of the `<updated memory>` tags
+fn parse_updated_memory(content: &str) -> Option<UpdatedMemory> {
+    let updated_memory = UPDATED_MEMORY_REGEX
+        .captures(content)?
+        .get(1)?
+        .as_str()
+        .trim();
+
+    Some(UpdatedMemory {
+        updated_memory: updated_memory.to_string(),
+    })
+}
+
+/// Turn the increment prompt's verdicts into memories to add or update. UPDATE verdicts go through
+/// the update prompt to merge the new memory into the existing one, REPEAT verdicts are dropped
+async fn apply_verdicts(
+    llm: &LlmRouter,
+    pool: Option<&PgPool>,
+    user_id: &str,
+    groups: &UserMemoryGroups,
+    existing_memories: &[Memory],
+    verdicts: Vec<MemoryVerdict>,
+) -> Vec<Memory> {
+    let futures = verdicts
+        .into_iter()
+        .enumerate()
+        .map(|(idx, verdict)| async move {
             info!(
-                "Memory {}:\n Content: {},\n Verdict: {}",
-                idx, content, verdict_type
+                "Memory {}:\n Content: {},\n Verdict: {:?}",
+                idx, verdict.content, verdict.verdict
             );
 
-            match verdict_type {
-                "NEW" | "OLD" => {
-                    let grouping = Memory::get_valid_group(grouping_or_memory);
+            match verdict.verdict {
+                Verdict::New | Verdict::Old => {
+                    let grouping = groups.valid_group(verdict.grouping.as_deref());
+                    if groups.is_excluded(&grouping) {
+                        return None;
+                    }
                     Some(Memory::new(
                         Uuid::new_v4(),
                         user_id,
-                        content,
+                        &verdict.content,
                         None,
                         Some(&grouping),
                     ))
                 }
-                "UPDATE" => {
-                    let memory_id = match grouping_or_memory.and_then(|id| Uuid::parse_str(id).ok())
-                    {
-                        Some(id) => id,
-                        None => return None,
-                    };
-
+                Verdict::Update => {
+                    let memory_id = verdict.memory_id?;
                     info!("Memory ID to update: {}", memory_id);
 
-                    let existing_memory = match existing_memories.iter().find(|m| m.id == memory_id)
-                    {
-                        Some(memory) => memory,
-                        None => return None,
-                    };
+                    let existing_memory = existing_memories.iter().find(|m| m.id == memory_id)?;
 
                     let message_content = format!(
                         "{}\n\nOLD MEMORY:\n{}\nNEW MEMORY:\n{}",
                         Prompts::UPDATE_MEMORY,
                         existing_memory.content,
-                        content
+                        verdict.content
                     );
 
                     info!("Update prompt:\n{}", message_content);
 
-                    let updated_content = match get_chat_completion(
-                        &app_state.keywords_client,
+                    let updated_content = match memory_step(
+                        llm,
+                        pool,
+                        UPDATE_MEMORY_PROMPT,
                         "gemini/gemini-1.5-flash",
                         &message_content,
+                        parse_updated_memory,
                     )
                     .await
                     {
-                        Ok(content) => {
-                            info!("Chat completion result: {}", content);
-                            content
-                        }
+                        Ok(updated) => updated?.updated_memory,
                         Err(e) => {
                             info!("Failed to get chat completion at idx {}: {:?}", idx, e);
                             return None;
                         }
                     };
 
-                    let updated_memory = match UPDATED_MEMORY_REGEX
-                        .captures(&updated_content)
-                        .and_then(|updated_cap| updated_cap.get(1))
-                        .map(|updated_memory| {
-                            Memory::new(
-                                existing_memory.id,
-                                user_id,
-                                updated_memory.as_str().trim(),
-                                None,
-                                existing_memory.grouping.as_deref(),
-                            )
-                        }) {
-                        Some(memory) => memory,
-                        None => return None,
-                    };
+                    let updated_memory = Memory::new(
+                        existing_memory.id,
+                        user_id,
+                        updated_content.trim(),
+                        None,
+                        existing_memory.grouping.as_deref(),
+                    );
 
                     info!("Updated memory: {:?}", updated_memory);
                     Some(updated_memory)
                 }
-                "REPEAT" => None,
-                _ => None,
+                Verdict::Repeat => None,
             }
         });
 
     let results = futures::future::join_all(futures).await;
-    Ok(results.into_iter().flatten().collect())
+    results.into_iter().flatten().collect()
 }
 
 pub async fn use_message_for_memory(
@@ -799,13 +2016,7 @@ pub async fn use_message_for_memory(
 ) -> Result<bool, Error> {
     let classify_prompt = Prompts::CLASSIFY_INSTRUCTION.replace("{0}", message_content);
     info!("classify_prompt:\n{}", classify_prompt);
-    match get_chat_completion(
-        &app_state.keywords_client,
-        "groq/llama3-70b-8192",
-        &classify_prompt,
-    )
-    .await
-    {
+    match get_chat_completion(&app_state.llm, "groq/llama3-70b-8192", &classify_prompt).await {
         Ok(res) => {
             info!("AI output:\n{}", res);
             let captures = CLASSIFY_MESSAGE_REGEX.captures(&res);
@@ -829,11 +2040,7 @@ pub async fn use_message_for_memory(
     }
 }
 // Add this utility function at the top of the file, after imports
-async fn get_chat_completion(
-    client: &Client<OpenAIConfig>,
-    model: &str,
-    content: &str,
-) -> Result<String, Error> {
+async fn get_chat_completion(llm: &LlmRouter, model: &str, content: &str) -> Result<String, Error> {
     let ai_messages: Vec<ChatCompletionRequestMessage> =
         vec![ChatCompletionRequestUserMessageArgs::default()
             .content(content)
@@ -853,7 +2060,7 @@ async fn get_chat_completion(
             e
         })?;
 
-    let response = client.chat().create(request).await.map_err(|e| {
+    let response = llm.create(request).await.map_err(|e| {
         error!("Failed to get chat completion response: {:?}", e);
         e
     })?;
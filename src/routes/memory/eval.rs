@@ -0,0 +1,471 @@
+// routes/memory/eval.rs
+//
+// Offline evaluation of the memory pipeline. Every fixture in evals/memory/ is a chat history
+// with recorded LLM replies and the memories it should produce. Each one runs through sampling,
+// the generate and format prompts and the increment prompt against the mock provider, and is
+// scored on precision, recall, duplicates and groupings. Replies recorded as tool call arguments
+// exercise the structured path, text replies the fallback parsers. Run with
+// `cargo test memory_eval -- --nocapture` to see the reports. With MEMORY_EVAL_KEYWORDS_API_KEY set,
+// the recorded replies are ignored and the fixtures run against the real models instead, scored
+// against the looser live thresholds, to check prompt edits before recording new replies
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{
+    extract_memories, plan_increment, sample_messages, IncrementPlan, FORMAT_MEMORY_PROMPT,
+    GENERATE_MEMORY_PROMPT, INCREMENT_MEMORY_PROMPT, UPDATE_MEMORY_PROMPT,
+};
+use crate::llm::{KeywordsProvider, LlmRouter, MockProvider};
+use crate::models::message::Role;
+use crate::models::{CustomMemoryGroup, Memory, MemorySettings, Message, UserMemoryGroups};
+use crate::prompts::Prompts;
+
+const FIXTURES_DIR: &str = "evals/memory";
+const EVAL_USER_ID: &str = "memory-eval";
+/// Word overlap above which a produced memory counts as an expected one, or as a duplicate
+const MATCH_SIMILARITY: f64 = 0.5;
+
+#[derive(Deserialize)]
+struct Fixture {
+    messages: Vec<FixtureMessage>,
+    #[serde(default)]
+    samples_per_query: Option<u32>,
+    #[serde(default)]
+    existing_memories: Vec<FixtureMemory>,
+    #[serde(default)]
+    custom_groups: Vec<FixtureGroup>,
+    #[serde(default)]
+    excluded_groups: Vec<String>,
+    responses: Vec<RecordedResponse>,
+    expected: Vec<ExpectedMemory>,
+    #[serde(default)]
+    thresholds: Thresholds,
+    /// Used instead of `thresholds` against the real models, which don't reply the same way every run
+    #[serde(default = "Thresholds::live")]
+    live_thresholds: Thresholds,
+}
+
+#[derive(Deserialize)]
+struct FixtureMessage {
+    role: Role,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct FixtureMemory {
+    id: Uuid,
+    content: String,
+    grouping: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FixtureGroup {
+    name: String,
+    description: Option<String>,
+}
+
+/// A recorded reply to one pipeline step, as the arguments of the step's tool call, in the prompts'
+/// tagged text format, or both. A step with only a text reply answers the tool call with text, so
+/// the pipeline falls back to parsing it
+#[derive(Deserialize)]
+struct RecordedResponse {
+    /// generate_memory, format_memory, increment_memory or update_memory
+    step: String,
+    /// For update_memory, the existing memory being updated. Without it the reply is used for every update
+    #[serde(default)]
+    memory_id: Option<Uuid>,
+    #[serde(default)]
+    tool_arguments: Option<serde_json::Value>,
+    #[serde(default)]
+    response: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExpectedMemory {
+    content: String,
+    grouping: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Thresholds {
+    precision: f64,
+    recall: f64,
+    grouping_accuracy: f64,
+    max_duplicates: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            precision: 1.0,
+            recall: 1.0,
+            grouping_accuracy: 1.0,
+            max_duplicates: 0,
+        }
+    }
+}
+
+impl Thresholds {
+    /// What a live run must reach when the fixture doesn't set `live_thresholds`
+    fn live() -> Self {
+        Thresholds {
+            precision: 0.5,
+            recall: 0.5,
+            grouping_accuracy: 0.5,
+            max_duplicates: 1,
+        }
+    }
+}
+
+struct Report {
+    produced: usize,
+    precision: f64,
+    recall: f64,
+    grouping_accuracy: f64,
+    duplicates: Vec<String>,
+    missed: Vec<String>,
+    unexpected: Vec<String>,
+    misgrouped: Vec<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} produced, precision {:.2}, recall {:.2}, grouping accuracy {:.2}, {} duplicates",
+            self.produced,
+            self.precision,
+            self.recall,
+            self.grouping_accuracy,
+            self.duplicates.len()
+        )?;
+        for (label, memories) in [
+            ("missed", &self.missed),
+            ("unexpected", &self.unexpected),
+            ("misgrouped", &self.misgrouped),
+            ("duplicate", &self.duplicates),
+        ] {
+            for memory in memories {
+                write!(f, "\n    {}: {}", label, memory)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Report {
+    fn failures(&self, thresholds: &Thresholds) -> Vec<String> {
+        let mut failures = Vec::new();
+        if self.precision < thresholds.precision {
+            failures.push(format!(
+                "precision {:.2} below {:.2}",
+                self.precision, thresholds.precision
+            ));
+        }
+        if self.recall < thresholds.recall {
+            failures.push(format!(
+                "recall {:.2} below {:.2}",
+                self.recall, thresholds.recall
+            ));
+        }
+        if self.grouping_accuracy < thresholds.grouping_accuracy {
+            failures.push(format!(
+                "grouping accuracy {:.2} below {:.2}",
+                self.grouping_accuracy, thresholds.grouping_accuracy
+            ));
+        }
+        if self.duplicates.len() > thresholds.max_duplicates {
+            failures.push(format!(
+                "{} duplicates, at most {} allowed",
+                self.duplicates.len(),
+                thresholds.max_duplicates
+            ));
+        }
+        failures
+    }
+}
+
+impl Fixture {
+    fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid fixture {:?}", path))
+    }
+
+    fn groups(&self) -> UserMemoryGroups {
+        let mut settings = MemorySettings::default_for(EVAL_USER_ID);
+        settings.excluded_groups = self.excluded_groups.clone();
+        let custom = self
+            .custom_groups
+            .iter()
+            .map(|group| CustomMemoryGroup {
+                id: Uuid::new_v4(),
+                user_id: EVAL_USER_ID.to_string(),
+                name: group.name.clone(),
+                description: group.description.clone(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            })
+            .collect();
+
+        UserMemoryGroups { settings, custom }
+    }
+
+    fn existing_memories(&self) -> Vec<Memory> {
+        self.existing_memories
+            .iter()
+            .map(|memory| {
+                Memory::new(
+                    memory.id,
+                    EVAL_USER_ID,
+                    &memory.content,
+                    None,
+                    memory.grouping.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    /// Oldest first, as the pipeline samples them
+    fn messages(&self) -> Vec<Message> {
+        self.messages
+            .iter()
+            .map(|message| Message {
+                user_id: EVAL_USER_ID.to_string(),
+                role: message.role.clone(),
+                text: message.text.clone(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// A mock that answers each step with its recorded reply. Steps are told apart by a piece of
+    /// text only their prompt contains, taken from the prompts themselves so it follows their edits
+    fn mock_router(&self) -> Result<LlmRouter> {
+        let existing_memories = self.existing_memories();
+        let mut provider = MockProvider::new();
+        // Replies for a specific update first, the mock answers with the first trigger that matches
+        let mut responses: Vec<&RecordedResponse> = self.responses.iter().collect();
+        responses.sort_by_key(|response| response.memory_id.is_none());
+
+        for response in responses {
+            let trigger = match (response.step.as_str(), response.memory_id) {
+                (UPDATE_MEMORY_PROMPT, Some(memory_id)) => {
+                    let memory = existing_memories
+                        .iter()
+                        .find(|memory| memory.id == memory_id)
+                        .with_context(|| format!("No existing memory {}", memory_id))?;
+                    format!("OLD MEMORY:\n{}\nNEW MEMORY:", memory.content)
+                }
+                (UPDATE_MEMORY_PROMPT, None) => "\nNEW MEMORY:\n".to_string(),
+                (GENERATE_MEMORY_PROMPT, _) => prompt_marker(Prompts::GENERATE_MEMORY),
+                (FORMAT_MEMORY_PROMPT, _) => prompt_marker(Prompts::FORMATTING_MEMORY),
+                (INCREMENT_MEMORY_PROMPT, _) => prompt_marker(Prompts::INCREMENT_MEMORY),
+                (step, _) => anyhow::bail!("Unknown step {}", step),
+            };
+            if response.tool_arguments.is_none() && response.response.is_none() {
+                anyhow::bail!("No tool_arguments or response for step {}", response.step);
+            }
+            if let Some(arguments) = &response.tool_arguments {
+                provider = provider.with_tool_call(&trigger, &arguments.to_string());
+            }
+            if let Some(text) = &response.response {
+                provider = provider.with_response(&trigger, text);
+            }
+        }
+
+        Ok(LlmRouter::new(Arc::new(provider)))
+    }
+}
+
+/// The part of a prompt before its first placeholder, which every prompt built from it contains
+fn prompt_marker(prompt: &str) -> String {
+    prompt.split('{').next().unwrap_or(prompt).to_string()
+}
+
+fn live_router() -> Option<LlmRouter> {
+    let api_key = std::env::var("MEMORY_EVAL_KEYWORDS_API_KEY").ok()?;
+    Some(LlmRouter::new(Arc::new(KeywordsProvider::new(&api_key))))
+}
+
+fn words(content: &str) -> HashSet<String> {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard similarity of the words in two memories
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        1.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Score what a run produced, its updates and additions, against the fixture's expected memories
+fn score(fixture: &Fixture, existing_memories: &[Memory], plan: &IncrementPlan) -> Report {
+    let produced: Vec<&Memory> = plan.updates.iter().chain(&plan.additions).collect();
+
+    // Pair every expected memory with the most similar produced one not already taken
+    let mut taken = vec![false; produced.len()];
+    let mut missed = Vec::new();
+    let mut misgrouped = Vec::new();
+    let mut grouped = 0;
+    let mut grouped_correctly = 0;
+    for expected in &fixture.expected {
+        let best = produced
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !taken[*index])
+            .map(|(index, memory)| (index, similarity(&expected.content, &memory.content)))
+            .filter(|(_, similarity)| *similarity >= MATCH_SIMILARITY)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((index, _)) = best else {
+            missed.push(expected.content.clone());
+            continue;
+        };
+        taken[index] = true;
+
+        if let Some(grouping) = expected.grouping.as_deref() {
+            grouped += 1;
+            let actual = produced[index].grouping.as_deref().unwrap_or_default();
+            if actual.eq_ignore_ascii_case(grouping) {
+                grouped_correctly += 1;
+            } else {
+                misgrouped.push(format!(
+                    "{} in {}, expected {}",
+                    produced[index].content, actual, grouping
+                ));
+            }
+        }
+    }
+    let matched = taken.iter().filter(|taken| **taken).count();
+    let unexpected = produced
+        .iter()
+        .zip(&taken)
+        .filter(|(_, taken)| !**taken)
+        .map(|(memory, _)| memory.content.clone())
+        .collect();
+
+    // Additions that restate an untouched existing memory or an earlier addition
+    let updated_ids: HashSet<Uuid> = plan.updates.iter().map(|memory| memory.id).collect();
+    let mut kept: Vec<&Memory> = existing_memories
+        .iter()
+        .filter(|memory| !updated_ids.contains(&memory.id))
+        .chain(&plan.updates)
+        .collect();
+    let mut duplicates = Vec::new();
+    for addition in &plan.additions {
+        if kept
+            .iter()
+            .any(|memory| similarity(&memory.content, &addition.content) >= MATCH_SIMILARITY)
+        {
+            duplicates.push(addition.content.clone());
+        }
+        kept.push(addition);
+    }
+
+    Report {
+        produced: produced.len(),
+        precision: ratio(matched, produced.len()),
+        recall: ratio(matched, fixture.expected.len()),
+        grouping_accuracy: ratio(grouped_correctly, grouped),
+        duplicates,
+        missed,
+        unexpected,
+        misgrouped,
+    }
+}
+
+async fn run_fixture(llm: &LlmRouter, fixture: &Fixture) -> Result<Report> {
+    let groups = fixture.groups();
+    let existing_memories = fixture.existing_memories();
+    let messages = fixture.messages();
+    let samples_per_query = fixture.samples_per_query.unwrap_or(messages.len() as u32);
+
+    let samples = sample_messages(messages, samples_per_query);
+    let new_memories = extract_memories(llm, None, EVAL_USER_ID, &groups, &samples).await?;
+    let plan = if new_memories.is_empty() {
+        IncrementPlan {
+            updates: Vec::new(),
+            additions: Vec::new(),
+            repeated_ids: Vec::new(),
+        }
+    } else {
+        plan_increment(
+            llm,
+            None,
+            EVAL_USER_ID,
+            &groups,
+            &new_memories,
+            &existing_memories,
+        )
+        .await?
+    };
+
+    Ok(score(fixture, &existing_memories, &plan))
+}
+
+#[tokio::test]
+async fn memory_eval() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURES_DIR);
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .expect("Failed to read memory eval fixtures")
+        .map(|entry| entry.expect("Failed to read fixture entry").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No fixtures in {:?}", dir);
+
+    let live = live_router();
+    let mut failures = Vec::new();
+    for path in paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let fixture = Fixture::load(&path).unwrap();
+        let mock;
+        let llm = match &live {
+            Some(llm) => llm,
+            None => {
+                mock = fixture.mock_router().unwrap();
+                &mock
+            }
+        };
+
+        let report = run_fixture(llm, &fixture).await.unwrap();
+        println!("{}: {}", name, report);
+        let thresholds = match live {
+            Some(_) => &fixture.live_thresholds,
+            None => &fixture.thresholds,
+        };
+        failures.extend(
+            report
+                .failures(thresholds)
+                .into_iter()
+                .map(|failure| format!("{}: {}", name, failure)),
+        );
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
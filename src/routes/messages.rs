@@ -1,10 +1,12 @@
-use actix_web::{put, web, HttpResponse, Responder};
+use actix_web::{get, put, web, HttpResponse, Responder};
 use uuid::Uuid;
 use std::sync::Arc;
 use tracing::error;
 
 use crate::middleware::auth::AuthenticatedUser;
-use crate::models::message::Message;
+use crate::models::message::{Message, Role};
+use crate::models::MessageEdit;
+use crate::types::EditMessageRequest;
 use crate::AppState;
 
 
@@ -38,4 +40,77 @@ async fn downvote_message(
         })?;
     
     Ok(HttpResponse::Ok().finish())
-}
\ No newline at end of file
+}
+
+/// Edit the text of one of the user's own messages, keeping the previous text. Optionally marks
+/// the messages after it regenerated so the chat can be re-run from the edited message
+#[put("/{message_id}")]
+async fn edit_message(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    message_id: web::Path<Uuid>,
+    req_body: web::Json<EditMessageRequest>,
+) -> Result<web::Json<Message>, actix_web::Error> {
+    let message_id = message_id.into_inner();
+    let user_id = &authenticated_user.user_id;
+    if req_body.text.trim().is_empty() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Message text can't be empty",
+        ));
+    }
+
+    let message = Message::get(&app_state.pool, message_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get message {}: {:?}", message_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Message not found"))?;
+    if message.role != Role::User {
+        return Err(actix_web::error::ErrorBadRequest(
+            "Only user messages can be edited",
+        ));
+    }
+
+    Message::edit(
+        &app_state.pool,
+        message_id,
+        user_id,
+        &req_body.text,
+        req_body.regenerate_following,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to edit message {}: {:?}", message_id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("Message not found"))
+    .map(web::Json)
+}
+
+/// Previous texts of a message, newest edit first
+#[get("/{message_id}/edits")]
+async fn get_message_edits(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    message_id: web::Path<Uuid>,
+) -> Result<web::Json<Vec<MessageEdit>>, actix_web::Error> {
+    let message_id = message_id.into_inner();
+    let user_id = &authenticated_user.user_id;
+    Message::get(&app_state.pool, message_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get message {}: {:?}", message_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Message not found"))?;
+
+    let edits = MessageEdit::get_for_message(&app_state.pool, message_id, user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get edits of message {}: {:?}", message_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(edits))
+}
@@ -1,11 +1,19 @@
+pub mod api_keys;
 pub mod auth;
 pub mod chat;
 pub mod hello;
+pub mod jobs;
+pub mod llm_models;
 pub mod memory;
 pub mod messages;
 pub mod oai;
 pub mod pay;
+pub mod rate_limits;
+pub mod roles;
+pub mod search;
+pub mod share;
 pub mod sidekick;
 pub mod sync;
+pub mod usage;
 pub mod webhook;
 pub mod devents;
\ No newline at end of file
@@ -1,27 +1,34 @@
+use actix_web::http::header::RETRY_AFTER;
 use actix_web::{post, web, HttpResponse, Responder};
-use async_openai::config::OpenAIConfig;
 use async_openai::error::OpenAIError;
 use async_openai::types::{
     ChatCompletionFunctionCall, ChatCompletionRequestMessage,
     ChatCompletionRequestMessageContentPart, ChatCompletionRequestMessageContentPartText,
     ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessageContent,
     ChatCompletionResponseFormat, ChatCompletionStreamOptions, ChatCompletionTool,
-    ChatCompletionToolChoiceOption, CreateChatCompletionRequest, InvisibilityMetadata,
+    ChatCompletionToolChoiceOption, CompletionUsage, CreateChatCompletionRequest,
+    InvisibilityMetadata,
 };
-use async_openai::Client;
 use bytes::Bytes;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::lock::Mutex;
 use futures::stream::StreamExt;
 use futures::TryStreamExt;
-use serde_json::to_string;
+use serde_json::{json, to_string};
 use std::sync::Arc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use utoipa::OpenApi;
+use uuid::Uuid;
 
+use crate::llm::{context, message_text};
 use crate::middleware::auth::AuthenticatedUser;
+use crate::models::memory_revision::MemoryAuthor;
 use crate::models::message::Role;
-use crate::models::{Chat, Memory, Message};
+use crate::models::rate_limit::{LimitKind, RateLimitDecision};
+use crate::models::{
+    Chat, Job, JobPayload, LlmModel, Memory, MemorySettings, Message, MessageUsage, RateLimit,
+    UserMemoryGroups,
+};
 use crate::routes;
 use crate::{prompts::Prompts, AppState};
 
@@ -45,15 +52,27 @@ pub struct ApiDoc;
 async fn create_system_prompt(
     app_state: &web::Data<Arc<AppState>>,
     user_id: &str,
+    query: &str,
     start_time: chrono::DateTime<chrono::Utc>,
 ) -> Result<String, actix_web::Error> {
-    // Fetch user memories
-    let memories = Memory::get_all_memories(&app_state.pool, user_id, &app_state.memory_cache)
+    // Fetch the user memories relevant to the latest turn, within their memory settings
+    let memories = async {
+        let groups = UserMemoryGroups::for_user(&app_state.pool, user_id).await?;
+        Memory::get_relevant_memories(
+            &app_state.pool,
+            user_id,
+            query,
+            app_state.embedder.as_ref(),
+            &app_state.memory_cache,
+            &groups,
+        )
         .await
-        .map_err(|e| {
-            error!("Failed to get memories: {:?}", e);
-            actix_web::error::ErrorInternalServerError(e)
-        })?;
+    }
+    .await
+    .map_err(|e| {
+        error!("Failed to get memories: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
 
     info!("got {} memories", memories.len());
     // Format memories
@@ -66,7 +85,7 @@ async fn create_system_prompt(
         .replace("{1}", &formatted_memories))
 }
 
-/// The primary oai mocked streaming chat completion endpoint, with all i.inc features
+/// The primary oai mocked chat completion endpoint, with all i.inc features. Streams unless `stream` is false
 #[utoipa::path(
     get,
     responses(
@@ -76,6 +95,7 @@ async fn create_system_prompt(
         (status = 200, description = "Chat completion API (streaming)",  content_type = "text/event-stream"),
         (status = 400, description = "Bad Request"),
         (status = 401, description = "Unauthorized"),
+        (status = 429, description = "Rate limit exceeded, see the Retry-After header"),
         (status = 500, description = "Internal Server Error")
     )
 )]
@@ -93,8 +113,60 @@ async fn chat(
 
     let mut request_args = req_body.into_inner();
 
-    // Attempt to create the system prompt
-    match create_system_prompt(&app_state, &authenticated_user.user_id, start_time).await {
+    // Enforce the user's rate limit. Depending on their plan, over-limit users are downgraded or get a 429.
+    // A broken limiter shouldn't take chat down with it, so errors let the request through
+    let rate_limit_decision = match RateLimit::for_user(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        &app_state.rate_limit_cache,
+    )
+    .await
+    {
+        Ok(rate_limit) => rate_limit
+            .check(&app_state.pool, &authenticated_user.user_id)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to check rate limit: {:?}", e);
+                RateLimitDecision::Allow
+            }),
+        Err(e) => {
+            error!("Failed to get rate limit: {:?}", e);
+            RateLimitDecision::Allow
+        }
+    };
+
+    let requested_model = match rate_limit_decision {
+        RateLimitDecision::Allow => request_args.model.clone(),
+        RateLimitDecision::Downgrade(model) => {
+            info!(
+                "User {} is over their rate limit, downgrading to {}",
+                &authenticated_user.user_id, model
+            );
+            model
+        }
+        RateLimitDecision::Reject {
+            kind,
+            retry_after_secs,
+        } => return Ok(rate_limited_response(kind, retry_after_secs)),
+    };
+
+    // Attempt to create the system prompt, with the memories relevant to the latest user turn
+    let latest_user_turn = request_args
+        .messages
+        .iter()
+        .rev()
+        .find(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+        .map(message_text)
+        .unwrap_or_default();
+
+    match create_system_prompt(
+        &app_state,
+        &authenticated_user.user_id,
+        &latest_user_turn,
+        start_time,
+    )
+    .await
+    {
         Ok(system_prompt) => {
             // Log the system prompt
             info!("System prompt created: {}", system_prompt);
@@ -117,51 +189,40 @@ async fn chat(
         }
     }
 
-    // For now, we only support streaming completions
-    request_args.stream = Some(true);
+    // Clients that omit `stream` have always been served SSE, so only an explicit false opts out
+    let stream = request_args.stream.unwrap_or(true);
+    request_args.stream = Some(stream);
 
     // Set the user ID
     request_args.customer_identifier = Some(authenticated_user.user_id.clone());
 
-    // If we want to use claude, use the openrouter client, otherwise use the standard openai client
-    let client: Client<OpenAIConfig> = app_state.keywords_client.clone();
-
-    // Max tokens as 4096
-    request_args.max_tokens = Some(4096);
+    // Conform the model id to what's expected by the provider, via the model registry
+    let model = LlmModel::resolve(&app_state.pool, &requested_model, &app_state.model_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to resolve model {}: {:?}", requested_model, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
 
-    // Check if user is rate limited
-    if authenticated_user.is_rate_limited() {
-        request_args.model = "groq/llama3-70b-8192".to_string();
-    } else {
-        // Conform the model id to what's expected by the provider
-        request_args.model = match request_args.model.as_str() {
-            "perplexity/mixtral-8x7b-instruct" => {
-                "openrouter/mistralai/mixtral-8x7b-instruct".to_string()
-            }
-            "perplexity/sonar-medium-online" => {
-                "openrouter/perplexity/llama-3-sonar-large-32k-online".to_string()
-            }
-            "openrouter/google/gemini-pro-1.5" => "gemini-1.5-flash-001".to_string(),
-            "claude-3-5-sonnet-20240620" => {
-                "bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0".to_string()
-            }
-            "claude-3-5-sonnet-20241022" => {
-                "bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0".to_string()
-            }
-            "bedrock/anthropic.claude-3-opus-20240229-v1:0" => {
-                "bedrock/anthropic.claude-3-5-sonnet-20241022-v2:0".to_string()
-            }
-            _ => request_args.model,
-        };
+    if !model.enabled {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "Model {} is disabled",
+            model.alias
+        )));
     }
 
+    let model_supports_vision = model.supports_vision;
+    request_args.model = model.upstream_id.clone();
+    request_args.max_tokens = Some(model.max_output_tokens as u32);
+
     info!("Model set to: {}", request_args.model);
 
     // Set fallback models
-    request_args.fallback = Some(vec![
-        "gpt-4o".to_string(),
-        "claude-3-5-sonnet-20240620".to_string(),
-    ]);
+    request_args.fallback = if model.fallbacks.is_empty() {
+        None
+    } else {
+        Some(model.fallbacks.clone())
+    };
 
     // Ensure we have at least one message, else return an error
     if request_args.messages.is_empty() {
@@ -170,20 +231,6 @@ async fn chat(
         ));
     }
 
-    // Truncate messages
-    // Include the last x messages, where x is the number of messages we want to keep
-    let num_messages: i32 = match request_args.model.as_str() {
-        "gpt-4-vision-preview" => 3,
-        "openrouter/perplexity/llama-3-sonar-large-32k-online" => 5,
-        _ => 15,
-    };
-
-    if request_args.messages.len() > num_messages as usize {
-        request_args.messages = request_args
-            .messages
-            .split_off(request_args.messages.len() - num_messages as usize);
-    }
-
     // For each message, ensure the content is not empty, put a - at the start of the message if it's empty
     for message in &mut request_args.messages {
         match message {
@@ -222,29 +269,200 @@ async fn chat(
     }
 
     // Get the last message from the request
-    let last_message_option = request_args.messages.last().cloned();
-    // Clone the user_id for use in the async block
-    let user_id = authenticated_user.user_id.clone();
+    let last_message = request_args.messages.last().cloned();
+
+    // Models without vision get the text only, image-only turns keep the placeholder added above.
+    // The images are still saved with the message from `last_message`. Stripped before truncation so
+    // the budget and the token estimate only count what is sent
+    if !model_supports_vision {
+        let stripped = strip_images(&mut request_args.messages);
+        if stripped > 0 {
+            info!(
+                "Stripped {} images for model {} without vision",
+                stripped, request_args.model
+            );
+        }
+    }
+
+    // Truncate messages to fit the model's context window, always keeping the system prompt and latest user turn
+    let mut budget = model.prompt_budget();
+    if model.summarize_truncated {
+        budget = budget.saturating_sub(context::SUMMARY_MAX_TOKENS);
+    }
+    let truncation =
+        context::truncate_to_budget(std::mem::take(&mut request_args.messages), budget);
+    request_args.messages = truncation.messages;
+
+    if !truncation.dropped.is_empty() {
+        info!(
+            "Dropped {} messages to fit {} in {} tokens",
+            truncation.dropped.len(),
+            model.alias,
+            budget
+        );
+
+        if model.summarize_truncated {
+            match context::summarize_dropped(&app_state.llm, &truncation.dropped).await {
+                Ok(summary) => {
+                    // Place the summary right after the system prompt(s), before the kept history
+                    let position = request_args
+                        .messages
+                        .iter()
+                        .position(|m| !matches!(m, ChatCompletionRequestMessage::System(_)))
+                        .unwrap_or(request_args.messages.len());
+                    request_args.messages.insert(position, summary);
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to summarize dropped messages: {:?}. Continuing without summary.",
+                        e
+                    );
+                }
+            }
+        }
+    }
+
     // Get an optional chat_id from the invisibility field if it exists
     let chat_id = request_args
         .invisibility
         .as_ref()
         .map(|invisibility| invisibility.chat_id);
-    // Clone the invisibility metadata for use in the async block
-    let invisibility_metadata = request_args.invisibility.clone();
-
-    // Remove the invisibility field from the request_args
 
-    // This field is not part of the OpenAI API and is only used internally
-    request_args.invisibility = None;
-
-    let model_id = request_args.model.clone();
+    let exchange = ChatExchange {
+        user_id: authenticated_user.user_id.clone(),
+        chat_id,
+        // Clone the invisibility metadata for use in the async block
+        invisibility_metadata: request_args.invisibility.clone(),
+        last_message,
+        model_id: request_args.model.clone(),
+        // Fallback for upstreams that don't report usage
+        estimated_input_tokens: request_args
+            .messages
+            .iter()
+            .map(context::count_message_tokens)
+            .sum(),
+        model,
+        start_time,
+    };
 
     // Use the chat id to track threads
     request_args.thread_identifier = chat_id.map(|id| id.to_string());
 
-    let response = client
-        .chat()
+    if stream {
+        stream_completion(app_state, request_args, exchange).await
+    } else {
+        create_completion(app_state, request_args, exchange).await
+    }
+}
+
+/// Remove image parts from user messages. Returns how many were removed
+fn strip_images(messages: &mut [ChatCompletionRequestMessage]) -> usize {
+    let mut stripped = 0;
+    for message in messages {
+        if let ChatCompletionRequestMessage::User(user_message) = message {
+            if let ChatCompletionRequestUserMessageContent::Array(array) = &mut user_message.content
+            {
+                let before = array.len();
+                array.retain(|part| {
+                    !matches!(part, ChatCompletionRequestMessageContentPart::ImageUrl(_))
+                });
+                stripped += before - array.len();
+            }
+        }
+    }
+    stripped
+}
+
+/// A 429 in the OpenAI error shape, so clients' existing retry handling applies
+fn rate_limited_response(kind: LimitKind, retry_after_secs: u64) -> HttpResponse {
+    let (limit_type, message) = match kind {
+        LimitKind::Requests => ("requests", "Rate limit reached for requests per minute"),
+        LimitKind::Tokens => ("tokens", "Rate limit reached for tokens per day"),
+    };
+
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after_secs.to_string()))
+        .json(json!({
+            "error": {
+                "message": format!("{}. Please try again in {}s.", message, retry_after_secs),
+                "type": limit_type,
+                "param": null,
+                "code": "rate_limit_exceeded",
+            }
+        }))
+}
+
+/// Everything needed to persist a prompt and its completion once the upstream call is done
+struct ChatExchange {
+    user_id: String,
+    chat_id: Option<Uuid>,
+    invisibility_metadata: Option<InvisibilityMetadata>,
+    last_message: Option<ChatCompletionRequestMessage>,
+    model_id: String,
+    model: LlmModel,
+    estimated_input_tokens: usize,
+    start_time: DateTime<Utc>,
+}
+
+/// What the upstream reported about a completion, filled in as it arrives
+#[derive(Default)]
+struct CompletionStats {
+    /// The model that actually served the completion, after provider failover and fallbacks
+    upstream_model: Option<String>,
+    usage: Option<CompletionUsage>,
+    ttft_ms: Option<i32>,
+    latency_ms: i32,
+}
+
+/// Non-streaming completion, returns a standard chat completion JSON body
+async fn create_completion(
+    app_state: web::Data<Arc<AppState>>,
+    request_args: CreateChatCompletionRequest,
+    exchange: ChatExchange,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The provider strips any fields its upstream doesn't understand, e.g. invisibility
+    let response = app_state.llm.create(request_args).await.map_err(|e| {
+        error!("Error creating chat completion: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+
+    let content = response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default();
+
+    let stats = CompletionStats {
+        upstream_model: Some(response.model.clone()),
+        usage: response.usage.clone(),
+        ttft_ms: None,
+        latency_ms: (Utc::now() - exchange.start_time).num_milliseconds() as i32,
+    };
+
+    // Spawn a new task to store the results in the database asynchronously
+    actix_web::rt::spawn(persist_exchange(app_state, exchange, content, stats));
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Streaming completion, proxies the upstream chunks as server sent events
+async fn stream_completion(
+    app_state: web::Data<Arc<AppState>>,
+    mut request_args: CreateChatCompletionRequest,
+    exchange: ChatExchange,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Always ask for the trailing usage chunk, but only forward it to clients that asked for it
+    let client_wants_usage = request_args
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
+    request_args.stream_options = Some(ChatCompletionStreamOptions {
+        include_usage: true,
+    });
+
+    // The provider strips any fields its upstream doesn't understand, e.g. invisibility
+    let response = app_state
+        .llm
         .create_stream(request_args)
         .await
         .map_err(|e| {
@@ -254,19 +472,14 @@ async fn chat(
 
     // This logging has a non-zero cost, but its essentially trivial, less than 5 microseconds theoretically
     let response_content = Arc::new(Mutex::new(String::new()));
+    let stats = Arc::new(Mutex::new(CompletionStats::default()));
+    let start_time = exchange.start_time;
 
-    // Create a stream that processes the response from llm host and stores the resulting assistant message
+    // Create a stream that processes the response from llm host and stores the resulting assistant message.
+    // The usage chunk arrives after the finish reason, so read until the upstream closes the stream
     let stream = response
         .take_while(|item_result| match item_result {
-            Ok(item) => {
-                if let Some(choice) = item.choices.first() {
-                    if choice.finish_reason.is_some() {
-                        debug!("Chat completion finished");
-                        return futures::future::ready(false);
-                    }
-                }
-                futures::future::ready(true)
-            }
+            Ok(_) => futures::future::ready(true),
             Err(e) => {
                 match e {
                     OpenAIError::StreamError(ref err) if err == "Stream ended" => {
@@ -279,28 +492,54 @@ async fn chat(
                 futures::future::ready(false)
             }
         })
-        .then({
+        .filter_map({
             let response_content = Arc::clone(&response_content);
+            let stats = Arc::clone(&stats);
             move |item_result| {
                 let response_content = Arc::clone(&response_content);
+                let stats = Arc::clone(&stats);
                 async move {
                     match item_result {
                         Ok(item) => {
-                            if let Some(chat_choice_stream) = item.choices.first() {
-                                if let Some(new_response_content) =
-                                    &chat_choice_stream.delta.content
-                                {
-                                    let mut content = response_content.lock().await;
-                                    content.push_str(new_response_content);
+                            let mut stats = stats.lock().await;
+                            if stats.upstream_model.is_none() {
+                                stats.upstream_model = Some(item.model.clone());
+                            }
+                            if let Some(usage) = &item.usage {
+                                stats.usage = Some(usage.clone());
+                            }
+
+                            match item.choices.first() {
+                                Some(chat_choice_stream) => {
+                                    if let Some(new_response_content) =
+                                        &chat_choice_stream.delta.content
+                                    {
+                                        if stats.ttft_ms.is_none() {
+                                            stats.ttft_ms =
+                                                Some((Utc::now() - start_time).num_milliseconds()
+                                                    as i32);
+                                        }
+                                        let mut content = response_content.lock().await;
+                                        content.push_str(new_response_content);
+                                    }
+                                    if chat_choice_stream.finish_reason.is_some() {
+                                        debug!("Chat completion finished");
+                                    }
                                 }
+                                // A usage-only chunk
+                                None if !client_wants_usage => return None,
+                                None => {}
                             }
-                            to_string(&item)
-                                .map(|json_string| {
-                                    Bytes::from(format!("data: {}\n\n", json_string))
-                                })
-                                .map_err(anyhow::Error::from)
+
+                            Some(
+                                to_string(&item)
+                                    .map(|json_string| {
+                                        Bytes::from(format!("data: {}\n\n", json_string))
+                                    })
+                                    .map_err(anyhow::Error::from),
+                            )
                         }
-                        Err(e) => Err(anyhow::Error::from(e)),
+                        Err(e) => Some(Err(anyhow::Error::from(e))),
                     }
                 }
             }
@@ -311,146 +550,16 @@ async fn chat(
         })
         .chain(futures::stream::once({
             let response_content_clone = Arc::clone(&response_content);
+            let stats = Arc::clone(&stats);
 
             async move {
                 let content = response_content_clone.lock().await.clone();
+                let mut stats = std::mem::take(&mut *stats.lock().await);
+                stats.latency_ms = (Utc::now() - start_time).num_milliseconds() as i32;
                 debug!("Stream processing completed");
 
                 // Spawn a new task to store the results in the database asynchronously
-                actix_web::rt::spawn({
-                    async move {
-                        // Determine the chat to use, either from invisibility or by creating new
-                        let chat = match Chat::get_or_create_by_user_id_and_chat_id(
-                            &app_state.pool,
-                            user_id.clone().as_str(),
-                            chat_id,
-                            match invisibility_metadata.as_ref() {
-                                Some(metadata) => metadata.branch_from_message_id,
-                                None => None,
-                            },
-                        )
-                        .await
-                        {
-                            Ok(chat) => chat,
-                            Err(e) => {
-                                error!("Error getting or creating chat: {:?}", e);
-                                return;
-                            }
-                        };
-
-                        // If the metadata includes a regenerate_from_message_id, mark the messages after that in the chat as regenerated
-                        if let Some(metadata) = invisibility_metadata.as_ref() {
-                            if let Some(regenerate_from_message_id) =
-                                metadata.regenerate_from_message_id
-                            {
-                                if let Err(e) = Message::mark_regenerated_from_message_id(
-                                    &app_state.pool,
-                                    regenerate_from_message_id,
-                                )
-                                .await
-                                {
-                                    error!("Error marking chat as regenerated: {:?}", e);
-                                }
-                            }
-                        }
-
-                        // Insert into db a message, the last OAI message (prompt). This should always be a user message
-                        if let Some(last_oai_message) = last_message_option {
-
-                            let (content, role, files) = match last_oai_message {
-                                ChatCompletionRequestMessage::User(user_message) => match user_message.content {
-                                    ChatCompletionRequestUserMessageContent::Text(text) => (text, Role::User, vec![]),
-                                    ChatCompletionRequestUserMessageContent::Array(array) => {
-                                        let mut concatenated_text = String::new();
-                                        let mut file_urls = Vec::new();
-
-                                        for part in &array {
-                                            match part {
-                                                ChatCompletionRequestMessageContentPart::Text(text_part) => {
-                                                    if !text_part.text.trim().is_empty() {
-                                                        concatenated_text.push_str(&text_part.text);
-                                                    }
-                                                }
-                                                ChatCompletionRequestMessageContentPart::ImageUrl(image_part) => {
-                                                    file_urls.push(image_part.image_url.url.clone());
-                                                }
-                                            }
-                                        }
-                                        (concatenated_text, Role::User, file_urls)
-                                    }
-                                },
-                                ChatCompletionRequestMessage::Assistant(assistant_message) => {
-                                    if let Some(content) = &assistant_message.content {
-                                        (content.clone(), Role::Assistant, vec![])
-                                    } else {
-                                        ("".to_string(), Role::Assistant, vec![])
-                                    }
-                                },
-                                _ => {
-                                    error!("Unsupported message type");
-                                    return;
-                                }
-                            };
-
-                            match Message::from_oai(
-                                &app_state.pool,
-                                content.clone(),
-                                role.clone(),
-                                files.clone(),
-                                chat.id,
-                                &user_id.clone(),
-                                Some(model_id.clone()),
-                                invisibility_metadata,
-                                Some(start_time),
-                            )
-                            .await
-                            {
-                                Ok(message) => {
-                                    debug!("Message created from OAI message: {:?}", message);
-                                }
-                                Err(e) => {
-                                    error!("Error creating message from OAI message: {:?}", e);
-                                }
-                            };
-
-                            if role == Role::User && routes::memory::use_message_for_memory(&app_state, &content).await.unwrap_or(false) {
-                                let last_msg_range = (start_time, Utc::now());
-                                match routes::memory::generate_memories_from_chat_history(
-                                    &app_state,
-                                    None,
-                                    &user_id,
-                                    Some(1),
-                                    Some(1),
-                                    Some(last_msg_range)
-                                )
-                                .await
-                                {
-                                    Ok(memories) => {
-                                        info!("Real-time memories generated successfully for user: {}. Count: {}", user_id, memories.len());
-                                    },
-                                    Err(e) => {
-                                        error!("Error generating memories for user {}: {:?}", user_id, e);
-                                    }
-                                }
-                            }
-                        } else {
-                            error!("No messages found in request_args.messages");
-                        }
-
-                        if let Err(err) = Message::new(
-                            &app_state.pool,
-                            chat.id,
-                            &chat.user_id,
-                            Some(model_id.clone()),
-                            &content,
-                            Role::Assistant,
-                        )
-                        .await
-                        {
-                            error!("Failed to create message: {:?}", err);
-                        }
-                    }
-                });
+                actix_web::rt::spawn(persist_exchange(app_state, exchange, content, stats));
 
                 Ok(Bytes::new()) as Result<Bytes, anyhow::Error>
             }
@@ -464,3 +573,213 @@ async fn chat(
 
     Ok(response)
 }
+
+/// Store the user prompt, its files, the assistant reply and its usage, then run real-time memory
+/// generation. Shared by the streaming and non-streaming paths.
+async fn persist_exchange(
+    app_state: web::Data<Arc<AppState>>,
+    exchange: ChatExchange,
+    content: String,
+    stats: CompletionStats,
+) {
+    let ChatExchange {
+        user_id,
+        chat_id,
+        invisibility_metadata,
+        last_message,
+        model_id,
+        model,
+        estimated_input_tokens,
+        start_time,
+    } = exchange;
+
+    // Determine the chat to use, either from invisibility or by creating new
+    let chat = match Chat::get_or_create_by_user_id_and_chat_id(
+        &app_state.pool,
+        user_id.clone().as_str(),
+        chat_id,
+        match invisibility_metadata.as_ref() {
+            Some(metadata) => metadata.branch_from_message_id,
+            None => None,
+        },
+    )
+    .await
+    {
+        Ok(chat) => chat,
+        Err(e) => {
+            error!("Error getting or creating chat: {:?}", e);
+            return;
+        }
+    };
+
+    // If the metadata includes a regenerate_from_message_id, mark the messages after that in the chat as regenerated
+    if let Some(metadata) = invisibility_metadata.as_ref() {
+        if let Some(regenerate_from_message_id) = metadata.regenerate_from_message_id {
+            if let Err(e) = Message::mark_regenerated_from_message_id(
+                &app_state.pool,
+                regenerate_from_message_id,
+            )
+            .await
+            {
+                error!("Error marking chat as regenerated: {:?}", e);
+            }
+        }
+    }
+
+    // Insert into db a message, the last OAI message (prompt). This should always be a user message
+    if let Some(last_oai_message) = last_message {
+        let (prompt, role, files) = match last_oai_message {
+            ChatCompletionRequestMessage::User(user_message) => match user_message.content {
+                ChatCompletionRequestUserMessageContent::Text(text) => (text, Role::User, vec![]),
+                ChatCompletionRequestUserMessageContent::Array(array) => {
+                    let mut concatenated_text = String::new();
+                    let mut file_urls = Vec::new();
+
+                    for part in &array {
+                        match part {
+                            ChatCompletionRequestMessageContentPart::Text(text_part) => {
+                                if !text_part.text.trim().is_empty() {
+                                    concatenated_text.push_str(&text_part.text);
+                                }
+                            }
+                            ChatCompletionRequestMessageContentPart::ImageUrl(image_part) => {
+                                file_urls.push(image_part.image_url.url.clone());
+                            }
+                        }
+                    }
+                    (concatenated_text, Role::User, file_urls)
+                }
+            },
+            ChatCompletionRequestMessage::Assistant(assistant_message) => {
+                if let Some(content) = &assistant_message.content {
+                    (content.clone(), Role::Assistant, vec![])
+                } else {
+                    ("".to_string(), Role::Assistant, vec![])
+                }
+            }
+            _ => {
+                error!("Unsupported message type");
+                return;
+            }
+        };
+
+        match Message::from_oai(
+            &app_state.pool,
+            prompt.clone(),
+            role.clone(),
+            files.clone(),
+            chat.id,
+            &user_id.clone(),
+            Some(model_id.clone()),
+            invisibility_metadata,
+            Some(start_time),
+        )
+        .await
+        {
+            Ok(message) => {
+                debug!("Message created from OAI message: {:?}", message);
+            }
+            Err(e) => {
+                error!("Error creating message from OAI message: {:?}", e);
+            }
+        };
+
+        if role == Role::User
+            && MemorySettings::get(&app_state.pool, &user_id)
+                .await
+                .map(|settings| settings.realtime_active())
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to get memory settings for user {}: {:?}",
+                        user_id, e
+                    );
+                    false
+                })
+            && routes::memory::use_message_for_memory(&app_state, &prompt)
+                .await
+                .unwrap_or(false)
+        {
+            // Covers the prompt and anything sent before the job runs, a job already queued for the user absorbs it
+            let payload = JobPayload::GenerateMemories {
+                user_id: user_id.clone(),
+                begin: Some(start_time),
+                end: None,
+                max_samples: Some(1),
+                samples_per_query: Some(1),
+                author: MemoryAuthor::RealtimeJob,
+            };
+            if let Err(e) = Job::enqueue(&app_state.pool, &payload, None).await {
+                error!("Error queueing memories for user {}: {:?}", user_id, e);
+            }
+        }
+    } else {
+        error!("No messages found in request_args.messages");
+    }
+
+    let message = match Message::new(
+        &app_state.pool,
+        chat.id,
+        &chat.user_id,
+        Some(model_id.clone()),
+        &content,
+        Role::Assistant,
+    )
+    .await
+    {
+        Ok(message) => message,
+        Err(err) => {
+            error!("Failed to create message: {:?}", err);
+            return;
+        }
+    };
+
+    // Not every upstream reports usage, estimate with the tokenizer when it doesn't
+    let (input_tokens, output_tokens, estimated) = match &stats.usage {
+        Some(usage) => (usage.prompt_tokens, usage.completion_tokens, false),
+        None => (
+            estimated_input_tokens as u32,
+            context::count_tokens(&content) as u32,
+            true,
+        ),
+    };
+
+    // Price from the model that served the completion, which differs from the requested one
+    // when the router failed over or the upstream used a fallback
+    let upstream_model = stats.upstream_model.unwrap_or(model_id);
+    let cost_usd = if upstream_model == model.upstream_id || upstream_model == model.alias {
+        model.cost(input_tokens, output_tokens)
+    } else {
+        match LlmModel::find_served(&app_state.pool, &upstream_model).await {
+            Ok(Some(served)) => served.cost(input_tokens, output_tokens),
+            Ok(None) => {
+                warn!(
+                    "No prices for model {} which served {}, using the requested model's",
+                    upstream_model, model.alias
+                );
+                model.cost(input_tokens, output_tokens)
+            }
+            Err(e) => {
+                error!("Failed to look up served model {}: {:?}", upstream_model, e);
+                model.cost(input_tokens, output_tokens)
+            }
+        }
+    };
+
+    let usage = MessageUsage {
+        message_id: message.id,
+        user_id: chat.user_id.clone(),
+        model: model.alias.clone(),
+        upstream_model,
+        input_tokens: input_tokens as i32,
+        output_tokens: output_tokens as i32,
+        estimated,
+        cost_usd,
+        ttft_ms: stats.ttft_ms,
+        latency_ms: stats.latency_ms,
+        created_at: Utc::now(),
+    };
+
+    if let Err(err) = MessageUsage::record(&app_state.pool, &usage).await {
+        error!("Failed to record usage: {:?}", err);
+    }
+}
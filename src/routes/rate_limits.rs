@@ -0,0 +1,157 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use chrono::Utc;
+use std::sync::Arc;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::middleware::roles::{RateLimitsManage, RequirePermission};
+use crate::models::{RateLimit, RateLimitPlan, UserRateLimit};
+use crate::types::{SetUserRateLimitRequest, UpsertRateLimitPlanRequest};
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_plans,
+        upsert_plan,
+        get_user_rate_limit,
+        set_user_rate_limit,
+        delete_user_rate_limit
+    ),
+    components(schemas(
+        RateLimit,
+        RateLimitPlan,
+        UserRateLimit,
+        SetUserRateLimitRequest,
+        UpsertRateLimitPlanRequest
+    ))
+)]
+pub struct ApiDoc;
+
+/// List every rate limit plan, needs rate_limits:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "All rate limit plans", body = Vec<RateLimitPlan>, content_type = "application/json"))
+)]
+#[get("/plans")]
+async fn list_plans(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RateLimitsManage>,
+) -> Result<web::Json<Vec<RateLimitPlan>>, actix_web::Error> {
+    let plans = RateLimitPlan::get_all(&app_state.pool).await.map_err(|e| {
+        error!("Failed to get rate limit plans: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(plans))
+}
+
+/// Create or replace a rate limit plan, needs rate_limits:manage
+#[utoipa::path(
+    put,
+    request_body = UpsertRateLimitPlanRequest,
+    responses((status = 200, description = "Upserted rate limit plan", body = RateLimitPlan, content_type = "application/json"))
+)]
+#[put("/plans/{name}")]
+async fn upsert_plan(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RateLimitsManage>,
+    name: web::Path<String>,
+    web::Json(req_body): web::Json<UpsertRateLimitPlanRequest>,
+) -> Result<web::Json<RateLimitPlan>, actix_web::Error> {
+    let plan = RateLimitPlan {
+        name: name.into_inner(),
+        requests_per_minute: req_body.requests_per_minute,
+        burst: req_body.burst,
+        tokens_per_day: req_body.tokens_per_day,
+        downgrade_model: req_body.downgrade_model,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    let plan = RateLimitPlan::upsert(&app_state.pool, &plan, &app_state.rate_limit_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to upsert rate limit plan: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(plan))
+}
+
+/// Get the limits that currently apply to a user, needs rate_limits:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "Effective rate limit for the user", body = RateLimit, content_type = "application/json"))
+)]
+#[get("/users/{user_id}")]
+async fn get_user_rate_limit(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RateLimitsManage>,
+    user_id: web::Path<String>,
+) -> Result<web::Json<RateLimit>, actix_web::Error> {
+    let limit = RateLimit::for_user(&app_state.pool, &user_id, &app_state.rate_limit_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to get rate limit for user {}: {:?}", user_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(limit))
+}
+
+/// Put a user on a plan, optionally overriding its limits, needs rate_limits:manage
+#[utoipa::path(
+    put,
+    request_body = SetUserRateLimitRequest,
+    responses((status = 200, description = "The user's plan and overrides", body = UserRateLimit, content_type = "application/json"))
+)]
+#[put("/users/{user_id}")]
+async fn set_user_rate_limit(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RateLimitsManage>,
+    user_id: web::Path<String>,
+    web::Json(req_body): web::Json<SetUserRateLimitRequest>,
+) -> Result<web::Json<UserRateLimit>, actix_web::Error> {
+    let user_limit = UserRateLimit {
+        user_id: user_id.into_inner(),
+        plan: req_body.plan,
+        requests_per_minute: req_body.requests_per_minute,
+        burst: req_body.burst,
+        tokens_per_day: req_body.tokens_per_day,
+        downgrade_model: req_body.downgrade_model,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    };
+
+    let user_limit =
+        UserRateLimit::upsert(&app_state.pool, &user_limit, &app_state.rate_limit_cache)
+            .await
+            .map_err(|e| {
+                error!("Failed to set user rate limit: {:?}", e);
+                actix_web::error::ErrorInternalServerError(e)
+            })?;
+
+    Ok(web::Json(user_limit))
+}
+
+/// Move a user back to the default plan, needs rate_limits:manage
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "User moved back to the default plan"))
+)]
+#[delete("/users/{user_id}")]
+async fn delete_user_rate_limit(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RateLimitsManage>,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    UserRateLimit::delete(&app_state.pool, &user_id, &app_state.rate_limit_cache)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete user rate limit: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
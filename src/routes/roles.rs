@@ -0,0 +1,144 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use std::sync::Arc;
+use tracing::{error, info};
+use utoipa::OpenApi;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::roles::{RequirePermission, RolesManage};
+use crate::models::role::ADMIN_ROLE;
+use crate::models::{Role, UserRole};
+use crate::types::UserRolesResponse;
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_roles, get_my_roles, get_user_roles, grant_role, revoke_role),
+    components(schemas(Role, UserRole, UserRolesResponse))
+)]
+pub struct ApiDoc;
+
+async fn user_roles_response(
+    app_state: &AppState,
+    user_id: String,
+) -> Result<UserRolesResponse, actix_web::Error> {
+    let roles = Role::get_for_user(&app_state.pool, &user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get roles for user {}: {:?}", user_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(UserRolesResponse { user_id, roles })
+}
+
+/// List every role and its permissions, needs roles:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "All roles", body = Vec<Role>, content_type = "application/json"))
+)]
+#[get("")]
+async fn list_roles(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RolesManage>,
+) -> Result<web::Json<Vec<Role>>, actix_web::Error> {
+    let roles = Role::get_all(&app_state.pool).await.map_err(|e| {
+        error!("Failed to get roles: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(roles))
+}
+
+/// Get the authenticated user's roles and permissions
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "The user's roles", body = UserRolesResponse, content_type = "application/json"))
+)]
+#[get("/me")]
+async fn get_my_roles(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+) -> Result<web::Json<UserRolesResponse>, actix_web::Error> {
+    user_roles_response(&app_state, authenticated_user.user_id)
+        .await
+        .map(web::Json)
+}
+
+/// Get a user's roles and permissions, needs roles:manage
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "The user's roles", body = UserRolesResponse, content_type = "application/json"))
+)]
+#[get("/users/{user_id}")]
+async fn get_user_roles(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<RolesManage>,
+    user_id: web::Path<String>,
+) -> Result<web::Json<UserRolesResponse>, actix_web::Error> {
+    user_roles_response(&app_state, user_id.into_inner())
+        .await
+        .map(web::Json)
+}
+
+/// Grant a role to a user, needs roles:manage
+#[utoipa::path(
+    put,
+    responses((status = 200, description = "The granted role", body = UserRole, content_type = "application/json"))
+)]
+#[put("/users/{user_id}/{role}")]
+async fn grant_role(
+    app_state: web::Data<Arc<AppState>>,
+    admin: RequirePermission<RolesManage>,
+    path: web::Path<(String, String)>,
+) -> Result<web::Json<UserRole>, actix_web::Error> {
+    let (user_id, role) = path.into_inner();
+
+    let user_role = UserRole::grant(
+        &app_state.pool,
+        &user_id,
+        &role,
+        &admin.user_id,
+        &app_state.role_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to grant role {} to user {}: {:?}", role, user_id, e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    info!("User {} granted {} to {}", admin.user_id, role, user_id);
+    Ok(web::Json(user_role))
+}
+
+/// Revoke a role from a user, needs roles:manage. Admins can't revoke their own admin role
+#[utoipa::path(
+    delete,
+    responses((status = 204, description = "Role revoked"))
+)]
+#[delete("/users/{user_id}/{role}")]
+async fn revoke_role(
+    app_state: web::Data<Arc<AppState>>,
+    admin: RequirePermission<RolesManage>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (user_id, role) = path.into_inner();
+
+    if user_id == admin.user_id && role == ADMIN_ROLE {
+        return Err(actix_web::error::ErrorBadRequest(
+            "You can't revoke your own admin role",
+        ));
+    }
+
+    UserRole::revoke(&app_state.pool, &user_id, &role, &app_state.role_cache)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to revoke role {} from user {}: {:?}",
+                role, user_id, e
+            );
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    info!("User {} revoked {} from {}", admin.user_id, role, user_id);
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,66 @@
+use actix_web::{get, web};
+use std::sync::Arc;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::message::Role;
+use crate::models::SearchResult;
+use crate::types::SearchQuery;
+use crate::AppState;
+
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(OpenApi)]
+#[openapi(paths(search), components(schemas(Role, SearchQuery, SearchResult)))]
+pub struct ApiDoc;
+
+/// Full-text search over the user's messages and chat names, best match first.
+/// Deleted chats and regenerated messages are left out
+#[utoipa::path(
+    get,
+    params(
+        ("q" = String, Query, description = "Search terms, in web search syntax"),
+        ("role" = Option<Role>, Query, description = "Only messages with this role"),
+        ("model_id" = Option<String>, Query, description = "Only messages answered by this model"),
+        ("after" = Option<String>, Query, description = "Only results created at or after this time, RFC 3339"),
+        ("before" = Option<String>, Query, description = "Only results created before this time, RFC 3339"),
+        ("limit" = Option<i64>, Query, description = "Max number of results, defaults to 20, at most 100"),
+        ("offset" = Option<i64>, Query, description = "Number of results to skip"),
+    ),
+    responses(
+        (status = 200, description = "Matching messages and chats with highlighted snippets", body = Vec<SearchResult>, content_type = "application/json"),
+        (status = 400, description = "Empty search")
+    )
+)]
+#[get("")]
+async fn search(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    query: web::Query<SearchQuery>,
+) -> Result<web::Json<Vec<SearchResult>>, actix_web::Error> {
+    if query.q.trim().is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("Search can't be empty"));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let results = SearchResult::search(
+        &app_state.pool,
+        &authenticated_user.user_id,
+        &query,
+        limit,
+        offset,
+    )
+    .await
+    .map_err(|e| {
+        error!("Failed to search: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(results))
+}
@@ -0,0 +1,148 @@
+use actix_web::{get, web, HttpResponse};
+use std::sync::Arc;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::models::message::Role;
+use crate::models::{ChatShare, SharedChat, SharedMessage};
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_share, get_share_html),
+    components(schemas(Role, SharedChat, SharedMessage))
+)]
+pub struct ApiDoc;
+
+/// Shared pages only load images and inline styles, whatever ends up in the text
+const SHARE_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; img-src https: http:; style-src 'unsafe-inline'";
+
+/// Look up a live share and its snapshot or 404. Doesn't need authentication
+async fn find_share(app_state: &AppState, token: &str) -> Result<SharedChat, actix_web::Error> {
+    ChatShare::get_by_token(&app_state.pool, token)
+        .await
+        .map_err(|e| {
+            error!("Failed to get share: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Share not found"))?
+        .snapshot()
+        .map_err(|e| {
+            error!("Failed to read share snapshot: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })
+}
+
+/// A shared chat, public to anyone with the token
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The shared snapshot of the chat", body = SharedChat, content_type = "application/json"),
+        (status = 404, description = "Share not found or revoked")
+    )
+)]
+#[get("/{token}")]
+async fn get_share(
+    app_state: web::Data<Arc<AppState>>,
+    token: web::Path<String>,
+) -> Result<web::Json<SharedChat>, actix_web::Error> {
+    find_share(&app_state, &token).await.map(web::Json)
+}
+
+/// A shared chat as a standalone web page, public to anyone with the token
+#[utoipa::path(
+    get,
+    responses(
+        (status = 200, description = "The shared snapshot of the chat", body = String, content_type = "text/html"),
+        (status = 404, description = "Share not found or revoked")
+    )
+)]
+#[get("/{token}/html")]
+async fn get_share_html(
+    app_state: web::Data<Arc<AppState>>,
+    token: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let chat = find_share(&app_state, &token).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("Content-Security-Policy", SHARE_CONTENT_SECURITY_POLICY))
+        .insert_header(("X-Robots-Tag", "noindex"))
+        .body(render_html(&chat)))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render a shared chat as a page of plain text bubbles. Everything from the chat is escaped and
+/// only http(s) image URLs are kept
+fn render_html(chat: &SharedChat) -> String {
+    let name = escape_html(&chat.name);
+    let mut messages = String::new();
+    for message in &chat.messages {
+        let author = match (&message.role, &message.model_id) {
+            (Role::User, _) => "User".to_string(),
+            (Role::Assistant, Some(model_id)) => escape_html(model_id),
+            (role, _) => escape_html(&role.to_string()),
+        };
+        messages.push_str(&format!(
+            "<div class=\"message {}\">\n<div class=\"author\">{}</div>\n<div class=\"text\">{}</div>\n",
+            message.role,
+            author,
+            escape_html(&message.text)
+        ));
+        for url in message
+            .images
+            .iter()
+            .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+        {
+            messages.push_str(&format!(
+                "<img src=\"{}\" alt=\"\" loading=\"lazy\">\n",
+                escape_html(url)
+            ));
+        }
+        messages.push_str("</div>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{name}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #1a1a1a; }}
+.message {{ margin: 1rem 0; padding: 0.75rem 1rem; border-radius: 0.75rem; background: #f4f4f5; }}
+.message.user {{ background: #e0ecff; }}
+.author {{ font-size: 0.8rem; font-weight: 600; margin-bottom: 0.25rem; color: #555; }}
+.text {{ white-space: pre-wrap; overflow-wrap: anywhere; }}
+img {{ max-width: 100%; margin-top: 0.5rem; border-radius: 0.5rem; }}
+footer {{ font-size: 0.8rem; color: #777; }}
+</style>
+</head>
+<body>
+<h1>{name}</h1>
+{messages}<footer>Shared {shared_at}</footer>
+</body>
+</html>
+"#,
+        name = name,
+        messages = messages,
+        shared_at = chat.shared_at.format("%Y-%m-%d %H:%M UTC"),
+    )
+}
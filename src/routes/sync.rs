@@ -1,19 +1,35 @@
-use actix_web::{get, web};
+use actix_web::{get, web, HttpResponse};
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
 use sqlx::query_as;
+use std::collections::HashMap;
+use std::future::ready;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::join;
-use tracing::error;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
 use utoipa::OpenApi;
+use uuid::Uuid;
 
 use crate::middleware::auth::AuthenticatedUser;
 use crate::models::file::Filetype;
 use crate::models::message::Role;
-use crate::models::{Chat, File, Memory, Message};
-use crate::types::AllResponse;
+use crate::models::sync_change::{CHAT_ENTITY, FILE_ENTITY, MEMORY_ENTITY, MESSAGE_ENTITY};
+use crate::models::{Chat, File, Memory, Message, SyncChange, SyncCursor, SyncEvent};
+use crate::types::{AllResponse, ChangesQuery, ChangesResponse, Tombstone};
 use crate::AppState;
 
+const DEFAULT_CHANGES_LIMIT: i64 = 500;
+const MAX_CHANGES_LIMIT: i64 = 2000;
+/// Comfortably under the idle timeouts of the proxies between us and clients
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(15);
+
 #[derive(OpenApi)]
-#[openapi(paths(sync_all), components(schemas(AllResponse)))]
+#[openapi(
+    paths(sync_all, sync_changes, sync_stream),
+    components(schemas(AllResponse, ChangesQuery, ChangesResponse, SyncEvent, Tombstone))
+)]
 pub struct ApiDoc;
 
 /// Return all the chats and messages for the user
@@ -28,6 +44,14 @@ pub async fn sync_all(
 ) -> Result<web::Json<AllResponse>, actix_web::Error> {
     let user_id = user.user_id.clone();
 
+    // Taken before the reads, so anything written while they run is delivered again by /sync/changes
+    let cursor = SyncChange::current_cursor(&app_state.pool, &user_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get sync cursor: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
     let chats_future = query_as!(
         Chat,
         r#"
@@ -58,14 +82,13 @@ pub async fn sync_all(
     )
     .fetch_all(&app_state.pool);
 
-    let memory_future = query_as!(
-        Memory,
+    let memory_future = sqlx::query_as::<_, Memory>(
         r#"
         SELECT * FROM memories
-        WHERE user_id = $1 AND deleted_at IS NULL
+        WHERE user_id = $1 AND deleted_at IS NULL AND archived_at IS NULL
         "#,
-        user_id
     )
+    .bind(&user_id)
     .fetch_all(&app_state.pool);
 
     let (chats_result, messages_result, files_result, memories_result) =
@@ -96,5 +119,218 @@ pub async fn sync_all(
         messages,
         files,
         memories,
+        cursor: cursor.to_string(),
+    }))
+}
+
+/// Return the chats, messages, files and memories created, updated or deleted since a cursor, one page at a time
+#[utoipa::path(
+    get,
+    params(
+        ("since" = Option<String>, Query, description = "Cursor from /sync/all or the previous page"),
+        ("until" = Option<String>, Query, description = "Cursor of the latest sync event received, changes up to it are returned even before they settle"),
+        ("limit" = Option<i64>, Query, description = "Max number of changes in the page, defaults to 500"),
+    ),
+    responses(
+        (status = 200, description = "Changes since the cursor", body = ChangesResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid cursor"),
+        (status = 410, description = "The cursor is older than the retained change log, sync in full from /sync/all")
+    )
+)]
+#[get("/changes")]
+pub async fn sync_changes(
+    app_state: web::Data<Arc<AppState>>,
+    user: AuthenticatedUser,
+    query: web::Query<ChangesQuery>,
+) -> Result<web::Json<ChangesResponse>, actix_web::Error> {
+    let user_id = user.user_id.clone();
+
+    let since = match &query.since {
+        Some(since) => since
+            .parse::<SyncCursor>()
+            .map_err(actix_web::error::ErrorBadRequest)?,
+        None => SyncCursor::default(),
+    };
+    let until = query
+        .until
+        .as_deref()
+        .map(str::parse::<SyncCursor>)
+        .transpose()
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_CHANGES_LIMIT)
+        .clamp(1, MAX_CHANGES_LIMIT);
+
+    // Fetch one extra change to know whether there is another page
+    let mut changes = SyncChange::get_since(&app_state.pool, &user_id, since, until, limit + 1)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch sync changes: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    // Checked after the read, a prune that raced it has moved the horizon by now
+    let pruned_through = SyncChange::pruned_through(&app_state.pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch the pruned sync cursor: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+    if since < pruned_through {
+        return Err(actix_web::error::ErrorGone("Full resync required"));
+    }
+
+    let overflowed = changes.len() as i64 > limit;
+    changes.truncate(limit as usize);
+    // Only settled changes move the cursor, the early ones come again once they settle. Settled
+    // changes sort first, so a page without any has nothing more to page through yet
+    let settled = changes.iter().rev().find(|change| change.settled);
+    let has_more = overflowed && settled.is_some();
+    let cursor = settled.map(SyncChange::cursor).unwrap_or(since);
+
+    // Only the last change to each entity in the page matters
+    let mut latest: HashMap<(String, Uuid), bool> = HashMap::new();
+    for change in changes {
+        latest.insert((change.entity, change.entity_id), change.deleted);
+    }
+
+    let mut tombstones = Vec::new();
+    let mut changed: HashMap<String, Vec<Uuid>> = HashMap::new();
+    for ((entity, id), deleted) in latest {
+        if deleted {
+            tombstones.push(Tombstone { entity, id });
+        } else {
+            changed.entry(entity).or_default().push(id);
+        }
+    }
+
+    let ids = |entity: &str| changed.get(entity).cloned().unwrap_or_default();
+
+    let chats_future = sqlx::query_as::<_, Chat>(
+        r#"
+        SELECT * FROM chats
+        WHERE user_id = $1 AND id = ANY($2) AND deleted_at IS NULL
+        "#,
+    )
+    .bind(&user_id)
+    .bind(ids(CHAT_ENTITY))
+    .fetch_all(&app_state.pool);
+
+    let messages_future = sqlx::query_as::<_, Message>(
+        r#"
+        SELECT * FROM messages
+        WHERE user_id = $1 AND id = ANY($2)
+            AND chat_id IN (SELECT id FROM chats WHERE user_id = $1 AND deleted_at IS NULL)
+        "#,
+    )
+    .bind(&user_id)
+    .bind(ids(MESSAGE_ENTITY))
+    .fetch_all(&app_state.pool);
+
+    let files_future = sqlx::query_as::<_, File>(
+        r#"
+        SELECT * FROM files
+        WHERE user_id = $1 AND id = ANY($2)
+            AND chat_id IN (SELECT id FROM chats WHERE user_id = $1 AND deleted_at IS NULL)
+        "#,
+    )
+    .bind(&user_id)
+    .bind(ids(FILE_ENTITY))
+    .fetch_all(&app_state.pool);
+
+    let memories_future = sqlx::query_as::<_, Memory>(
+        r#"
+        SELECT * FROM memories
+        WHERE user_id = $1 AND id = ANY($2) AND deleted_at IS NULL AND archived_at IS NULL
+        "#,
+    )
+    .bind(&user_id)
+    .bind(ids(MEMORY_ENTITY))
+    .fetch_all(&app_state.pool);
+
+    let (chats_result, messages_result, files_result, memories_result) =
+        join!(chats_future, messages_future, files_future, memories_future);
+
+    let chats = chats_result.map_err(|e| {
+        error!("Failed to fetch changed chats: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let messages = messages_result.map_err(|e| {
+        error!("Failed to fetch changed messages: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let files = files_result.map_err(|e| {
+        error!("Failed to fetch changed files: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    let memories = memories_result.map_err(|e| {
+        error!("Failed to fetch changed memories: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    })?;
+
+    Ok(web::Json(ChangesResponse {
+        chats,
+        messages,
+        files,
+        memories,
+        tombstones,
+        cursor: cursor.to_string(),
+        has_more,
     }))
 }
+
+/// Push an event whenever one of the user's chats, messages, files or memories changes, on any instance.
+/// Events only say what changed, clients fetch it with /sync/changes passing the event's cursor as `until`.
+/// A `resync` event means events were dropped
+#[utoipa::path(
+    get,
+    responses((status = 200, description = "Server-sent `change` events carrying a SyncEvent", content_type = "text/event-stream"))
+)]
+#[get("/stream")]
+pub async fn sync_stream(
+    app_state: web::Data<Arc<AppState>>,
+    user: AuthenticatedUser,
+) -> HttpResponse {
+    let receiver = app_state.sync_events.subscribe(&user.user_id);
+    debug!("User {} subscribed to sync events", user.user_id);
+
+    let events = stream::unfold(
+        (receiver, user.user_id),
+        |(mut receiver, user_id)| async move {
+            let frame = match receiver.recv().await {
+                Ok(event) => {
+                    let json_string = serde_json::to_string(&event).unwrap_or_default();
+                    format!("event: change\ndata: {}\n\n", json_string)
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "Sync stream for user {} lagged by {} events",
+                        user_id, skipped
+                    );
+                    "event: resync\ndata: {}\n\n".to_string()
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            Some((Bytes::from(frame), (receiver, user_id)))
+        },
+    );
+
+    let keepalive = stream::unfold((), |_| async {
+        tokio::time::sleep(STREAM_KEEPALIVE).await;
+        Some((Bytes::from_static(b": keepalive\n\n"), ()))
+    });
+
+    let stream = stream::once(ready(Bytes::from_static(b"retry: 5000\n\n")))
+        .chain(stream::select(events, keepalive))
+        .map(Ok::<_, actix_web::Error>);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream)
+}
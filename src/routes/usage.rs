@@ -0,0 +1,144 @@
+use actix_web::{get, web};
+use chrono::{Duration, NaiveDate, Utc};
+use std::sync::Arc;
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::roles::{RequirePermission, UsageRead};
+use crate::models::{ParseStat, UsageDaily, UsageSummary};
+use crate::types::UsageQuery;
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_usage, get_usage_by_user, get_user_usage, get_parse_stats),
+    components(schemas(UsageDaily, UsageSummary, UsageQuery, ParseStat))
+)]
+pub struct ApiDoc;
+
+const DEFAULT_USERS_LIMIT: i64 = 100;
+const MAX_USERS_LIMIT: i64 = 1000;
+
+/// Resolve the requested day range, defaulting to the last 30 days
+fn day_range(query: &UsageQuery) -> (NaiveDate, NaiveDate) {
+    let end = query.end.unwrap_or_else(|| Utc::now().date_naive());
+    let start = query.start.unwrap_or(end - Duration::days(30));
+    (start, end)
+}
+
+/// Get the authenticated user's daily usage per model
+#[utoipa::path(
+    get,
+    params(
+        ("start" = Option<NaiveDate>, Query, description = "First day to include, defaults to 30 days ago"),
+        ("end" = Option<NaiveDate>, Query, description = "Last day to include, defaults to today"),
+    ),
+    responses((status = 200, description = "Daily usage per model", body = Vec<UsageDaily>, content_type = "application/json"))
+)]
+#[get("")]
+async fn get_usage(
+    app_state: web::Data<Arc<AppState>>,
+    authenticated_user: AuthenticatedUser,
+    query: web::Query<UsageQuery>,
+) -> Result<web::Json<Vec<UsageDaily>>, actix_web::Error> {
+    let (start, end) = day_range(&query);
+
+    let usage = UsageDaily::get_for_user(&app_state.pool, &authenticated_user.user_id, start, end)
+        .await
+        .map_err(|e| {
+            error!("Failed to get usage: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(usage))
+}
+
+/// Get usage totals per user, biggest spenders first, needs usage:read
+#[utoipa::path(
+    get,
+    params(
+        ("start" = Option<NaiveDate>, Query, description = "First day to include, defaults to 30 days ago"),
+        ("end" = Option<NaiveDate>, Query, description = "Last day to include, defaults to today"),
+        ("limit" = Option<i64>, Query, description = "Max number of users, defaults to 100, at most 1000"),
+    ),
+    responses((status = 200, description = "Usage totals per user", body = Vec<UsageSummary>, content_type = "application/json"))
+)]
+#[get("/users")]
+async fn get_usage_by_user(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<UsageRead>,
+    query: web::Query<UsageQuery>,
+) -> Result<web::Json<Vec<UsageSummary>>, actix_web::Error> {
+    let (start, end) = day_range(&query);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USERS_LIMIT)
+        .clamp(1, MAX_USERS_LIMIT);
+
+    let summaries = UsageDaily::summarize_by_user(&app_state.pool, start, end, limit)
+        .await
+        .map_err(|e| {
+            error!("Failed to summarize usage: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(summaries))
+}
+
+/// Get a user's daily usage per model, needs usage:read
+#[utoipa::path(
+    get,
+    params(
+        ("user_id" = String, Path, description = "User to get usage for"),
+        ("start" = Option<NaiveDate>, Query, description = "First day to include, defaults to 30 days ago"),
+        ("end" = Option<NaiveDate>, Query, description = "Last day to include, defaults to today"),
+    ),
+    responses((status = 200, description = "Daily usage per model", body = Vec<UsageDaily>, content_type = "application/json"))
+)]
+#[get("/users/{user_id}")]
+async fn get_user_usage(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<UsageRead>,
+    user_id: web::Path<String>,
+    query: web::Query<UsageQuery>,
+) -> Result<web::Json<Vec<UsageDaily>>, actix_web::Error> {
+    let (start, end) = day_range(&query);
+
+    let usage = UsageDaily::get_for_user(&app_state.pool, &user_id, start, end)
+        .await
+        .map_err(|e| {
+            error!("Failed to get usage for user {}: {:?}", user_id, e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(usage))
+}
+
+/// Get how the memory pipeline's LLM replies were parsed, per prompt and day, needs usage:read.
+/// A rising fallback or failed count means the model is drifting from the expected output
+#[utoipa::path(
+    get,
+    params(
+        ("start" = Option<NaiveDate>, Query, description = "First day to include, defaults to 30 days ago"),
+        ("end" = Option<NaiveDate>, Query, description = "Last day to include, defaults to today"),
+    ),
+    responses((status = 200, description = "Parse outcomes per prompt and day", body = Vec<ParseStat>, content_type = "application/json"))
+)]
+#[get("/parse_stats")]
+async fn get_parse_stats(
+    app_state: web::Data<Arc<AppState>>,
+    _permission: RequirePermission<UsageRead>,
+    query: web::Query<UsageQuery>,
+) -> Result<web::Json<Vec<ParseStat>>, actix_web::Error> {
+    let (start, end) = day_range(&query);
+
+    let stats = ParseStat::get_range(&app_state.pool, start, end)
+        .await
+        .map_err(|e| {
+            error!("Failed to get parse stats: {:?}", e);
+            actix_web::error::ErrorInternalServerError(e)
+        })?;
+
+    Ok(web::Json(stats))
+}
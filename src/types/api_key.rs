@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::api_key::ApiKeyScope;
+use crate::models::ApiKey;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    /// Restrict what the key can do, omit for full access
+    pub scopes: Option<Vec<ApiKeyScope>>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct CreateApiKeyResponse {
+    /// The plaintext key, only ever returned here
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateApiKeyRequest {
+    pub name: String,
+}
@@ -1,6 +1,9 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::Session;
 
 #[derive(Deserialize, ToSchema, Debug)]
 pub struct AuthCallbackQuery {
@@ -55,6 +58,9 @@ pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub iat: usize,
+    /// The session the token was issued for. Tokens issued before sessions existed have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -68,3 +74,10 @@ pub struct GetUserResponse {
     pub data: Vec<WorkOSUser>,
     pub list_metadata: ListMetadata,
 }
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ListSessionsResponse {
+    /// The session making the request, None when authenticated with an API key or a legacy token
+    pub current_session_id: Option<Uuid>,
+    pub sessions: Vec<Session>,
+}
@@ -1,5 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{Chat, File, Message};
 
 #[derive(Deserialize, ToSchema)]
 pub struct UpdateChatRequest {
@@ -10,3 +13,61 @@ pub struct UpdateChatRequest {
 pub struct AutorenameChatRequest {
     pub text: String,
 }
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ChatsQuery {
+    /// X-Next-Cursor from the previous page, omit for the most recently updated chats
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ChatMessagesQuery {
+    /// Id of the oldest message already loaded, omit for the latest messages
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ChatMessagesResponse {
+    /// Oldest first
+    pub messages: Vec<Message>,
+    /// Files attached to the messages in this page
+    pub files: Vec<File>,
+    /// Whether there are older messages, fetch them with the first message's id as `before`
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct MessageNode {
+    pub message: Message,
+    /// The message this one follows, None for the first message of a chat
+    pub parent_id: Option<Uuid>,
+    /// Ids of the messages following this one in the same chat, oldest first. More than one means
+    /// the reply was regenerated
+    pub children: Vec<Uuid>,
+    /// Whether the message is on its chat's active path
+    pub active: bool,
+    /// Position among the alternatives for the same spot, from 1
+    pub variant: usize,
+    /// Number of alternatives for the same spot, this one included
+    pub variants: usize,
+    /// Ids of the chats branched off this message
+    pub branches: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ChatTreeResponse {
+    /// The requested chat followed by the chats branched off it, oldest first
+    pub chats: Vec<Chat>,
+    /// Messages of all those chats, oldest first
+    pub messages: Vec<MessageNode>,
+    /// Files attached to the messages
+    pub files: Vec<File>,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ShareChatRequest {
+    /// Last message to share, omit for the latest message on the active path
+    pub up_to_message_id: Option<Uuid>,
+}
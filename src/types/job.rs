@@ -0,0 +1,21 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::models::{JobPayload, JobStatus};
+
+#[derive(Deserialize, ToSchema)]
+pub struct JobsQuery {
+    pub status: Option<JobStatus>,
+    pub kind: Option<String>,
+    pub user_id: Option<String>,
+    /// Max number of jobs, defaults to 100
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct EnqueueJobRequest {
+    pub payload: JobPayload,
+    /// When to run the job, defaults to now
+    pub run_at: Option<DateTime<Utc>>,
+}
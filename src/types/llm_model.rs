@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpsertModelRequest {
+    pub upstream_id: String,
+    pub context_window: Option<i32>,
+    pub max_output_tokens: Option<i32>,
+    pub summarize_truncated: Option<bool>,
+    pub input_cost_per_mtok: Option<f64>,
+    pub output_cost_per_mtok: Option<f64>,
+    pub supports_vision: Option<bool>,
+    pub fallbacks: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
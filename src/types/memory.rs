@@ -1,5 +1,9 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::{CustomMemoryGroup, Memory, MemoryRevision, MemorySource};
 
 #[derive(Deserialize)]
 pub struct CreateMemoryRequest {
@@ -20,3 +24,140 @@ pub struct GenerateMemoriesRequest {
     pub samples_per_query: Option<u32>,
     pub range: Option<(u32, u32)>,
 }
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct MemoryHistoryResponse {
+    pub memory_id: Uuid,
+    /// Oldest first
+    pub revisions: Vec<MemoryRevision>,
+    /// Messages the memory was generated from
+    pub sources: Vec<MemorySource>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemorySettingsRequest {
+    pub memory_enabled: Option<bool>,
+    pub realtime_enabled: Option<bool>,
+    /// Replaces the excluded groups, each must be a built in or custom group
+    pub excluded_groups: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct MemoryGroupRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct MemoryGroupsResponse {
+    pub built_in: Vec<String>,
+    pub custom: Vec<CustomMemoryGroup>,
+    /// Groups from either list the user excluded
+    pub excluded: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemorySort {
+    CreatedAt,
+    #[default]
+    UpdatedAt,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters for GET /memories, all optional. Without a limit every match is returned in one page
+#[derive(Deserialize, Debug)]
+pub struct MemoriesQuery {
+    /// Full-text search over the content
+    pub q: Option<String>,
+    pub grouping: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub sort: MemorySort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+    /// X-Next-Cursor from the previous page
+    pub cursor: Option<String>,
+}
+
+/// Portable memory export, grouped by grouping. POST /memories/import takes the same format
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct MemoryExport {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default = "Utc::now")]
+    pub exported_at: DateTime<Utc>,
+    pub groups: Vec<ExportedMemoryGroup>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ExportedMemoryGroup {
+    pub grouping: String,
+    pub memories: Vec<ExportedMemory>,
+}
+
+/// A memory with its timestamps and provenance. Only `content` is needed to import one
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ExportedMemory {
+    pub content: String,
+    #[serde(default)]
+    pub pinned: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub last_confirmed_at: Option<DateTime<Utc>>,
+    /// Who created the memory: user, nightly_job, realtime_job, admin_job or import
+    pub created_by: Option<String>,
+    /// Messages the memory was generated from
+    #[serde(default)]
+    pub source_message_ids: Vec<Uuid>,
+    /// Set for archived memories, which are imported archived
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Markdown,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportQuery {
+    /// Run the imported memories through the increment prompt so they merge into existing ones,
+    /// defaults to true. When false they're added as they are
+    pub merge: Option<bool>,
+}
+
+/// An import queued as a job, its memories show up through sync as it runs
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportMemoriesQueued {
+    pub job_id: Uuid,
+}
+
+/// What an import job created or skipped
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportedMemories {
+    /// Memories created or updated by the import
+    pub memories: Vec<Memory>,
+    /// Imported memories skipped because the user already had them word for word
+    pub duplicates: usize,
+}
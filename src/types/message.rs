@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct EditMessageRequest {
+    pub text: String,
+    /// Make the edited message the end of the active path and mark its replies regenerated, to re-run the chat from it
+    #[serde(default)]
+    pub regenerate_following: bool,
+}
@@ -1,3 +1,4 @@
+mod api_key;
 mod auth;
 mod chat;
 mod pay;
@@ -5,11 +6,26 @@ mod sync;
 mod memory;
 mod recordings;
 mod devents;
+mod job;
+mod llm_model;
+mod message;
+mod rate_limit;
+mod role;
+mod search;
+mod usage;
 
+pub use api_key::*;
 pub use auth::*;
 pub use chat::*;
 pub use pay::*;
 pub use sync::*;
 pub use memory::*;
 pub use recordings::*;
-pub use devents::*;
\ No newline at end of file
+pub use devents::*;
+pub use job::*;
+pub use llm_model::*;
+pub use message::*;
+pub use rate_limit::*;
+pub use role::*;
+pub use search::*;
+pub use usage::*;
\ No newline at end of file
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpsertRateLimitPlanRequest {
+    pub requests_per_minute: Option<i32>,
+    pub burst: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    pub downgrade_model: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetUserRateLimitRequest {
+    pub plan: String,
+    /// Overrides of the plan's limits, omit to inherit from the plan
+    pub requests_per_minute: Option<i32>,
+    /// Defaults to the plan's burst, or to requests_per_minute when that is overridden
+    pub burst: Option<i32>,
+    pub tokens_per_day: Option<i64>,
+    pub downgrade_model: Option<String>,
+}
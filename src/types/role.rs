@@ -0,0 +1,10 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::Role;
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct UserRolesResponse {
+    pub user_id: String,
+    pub roles: Vec<Role>,
+}
@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::models::message::Role;
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct SearchQuery {
+    /// Search terms, in web search syntax: quoted phrases, `or` and `-excluded` words
+    pub q: String,
+    /// Only messages with this role. Leaves out chat name matches
+    pub role: Option<Role>,
+    /// Only messages answered by this model. Leaves out chat name matches
+    pub model_id: Option<String>,
+    /// Only messages or chats created at or after this time
+    pub after: Option<DateTime<Utc>>,
+    /// Only messages or chats created before this time
+    pub before: Option<DateTime<Utc>>,
+    /// Max number of results, defaults to 20, at most 100
+    pub limit: Option<i64>,
+    /// Number of results to skip, for the next page
+    pub offset: Option<i64>,
+}
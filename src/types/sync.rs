@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 use crate::models::{Chat, File, Memory, Message};
 
@@ -9,4 +10,37 @@ pub struct AllResponse {
     pub messages: Vec<Message>,
     pub files: Vec<File>,
     pub memories: Vec<Memory>,
+    /// Pass to /sync/changes to get everything after this snapshot
+    pub cursor: String,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ChangesQuery {
+    /// Cursor from /sync/all or a previous page, omit to read the whole change log
+    pub since: Option<String>,
+    /// Cursor of the latest sync event, so its change comes back even while older transactions are open
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// An entity the client should delete locally
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Tombstone {
+    /// chat, message, file or memory
+    pub entity: String,
+    pub id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ChangesResponse {
+    /// Current state of every entity created or updated in this page
+    pub chats: Vec<Chat>,
+    pub messages: Vec<Message>,
+    pub files: Vec<File>,
+    pub memories: Vec<Memory>,
+    pub tombstones: Vec<Tombstone>,
+    /// Pass as `since` for the next page
+    pub cursor: String,
+    /// Whether there are more changes after this page
+    pub has_more: bool,
 }
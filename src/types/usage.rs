@@ -0,0 +1,13 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct UsageQuery {
+    /// First day to include, defaults to 30 days ago
+    pub start: Option<NaiveDate>,
+    /// Last day to include, defaults to today
+    pub end: Option<NaiveDate>,
+    /// Max number of users in the admin overview, defaults to 100
+    pub limit: Option<i64>,
+}